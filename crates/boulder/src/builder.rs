@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::{
-    io,
+    fs, io,
     os::unix::process::ExitStatusExt,
     path::{Path, PathBuf},
     process, thread,
@@ -25,7 +25,8 @@ use crate::{
     architecture::BuildTarget,
     container::{self, ExecError},
     job::{self, Step},
-    macros, pgo, profile, recipe, root, upstream, util, Env, Job, Macros, Paths, Recipe, Runtime,
+    macros, package, pgo, profile, recipe, root, upstream, util, Env, Job, Macros, Paths, Recipe,
+    Runtime,
 };
 
 pub struct Builder {
@@ -118,7 +119,7 @@ impl Builder {
         Ok(())
     }
 
-    pub fn build(self) -> Result<(), Error> {
+    pub fn build(&self) -> Result<(), Error> {
         container::exec(&self.paths, self.recipe.parsed.options.networking, || {
             // We're now in the container =)
 
@@ -266,6 +267,62 @@ impl Builder {
         })?;
         Ok(())
     }
+
+    /// Split the install root into packages, writing the resulting
+    /// stones into the `output` directory
+    pub fn package(&self, output: &Path) -> Result<(), Error> {
+        let arch_macros = Some("base".to_string())
+            .into_iter()
+            .chain(
+                self.targets
+                    .iter()
+                    .map(|target| target.build_target.to_string()),
+            )
+            .unique()
+            .collect::<Vec<_>>();
+        let packages = package::resolve(&self.recipe, &self.macros, &arch_macros)?;
+
+        let is_stone = |p: &Path| p.extension().and_then(|s| s.to_str()) == Some("stone");
+
+        // Package from within the container so we have access to
+        // the install root on rootless builds
+        container::exec(&self.paths, false, || {
+            let artefacts = self.paths.artefacts().guest;
+
+            // Remove stones from any previous build
+            for stone in util::sync::enumerate_files(&artefacts, is_stone)? {
+                fs::remove_file(stone)?;
+            }
+
+            let collected = package::collect(&self.paths.install().guest, &packages)?;
+
+            for (package, paths) in packages.iter().zip(&collected) {
+                // Skip packages with no content
+                if paths.iter().all(|info| info.metadata.is_dir()) {
+                    continue;
+                }
+
+                let analysis = package::analysis::analyse(paths);
+
+                package::emit(&self.recipe, package, paths, &analysis, &artefacts)?;
+            }
+
+            Ok(())
+        })?;
+
+        for stone in util::sync::enumerate_files(&self.paths.artefacts().host, is_stone)? {
+            let Some(file_name) = stone.file_name() else {
+                continue;
+            };
+            let destination = output.join(file_name);
+
+            fs::copy(&stone, &destination)?;
+
+            println!("{} {}", "Emitted".green(), destination.display());
+        }
+
+        Ok(())
+    }
 }
 
 fn logged(
@@ -426,6 +483,8 @@ pub enum Error {
     Root(#[from] root::Error),
     #[error("upstream")]
     Upstream(#[from] upstream::Error),
+    #[error("package")]
+    Package(#[from] package::Error),
     #[error("container")]
     Container(#[from] container::Error),
     #[error("recipe")]
@@ -52,6 +52,7 @@ pub fn handle(command: Command, env: Env) -> Result<(), Error> {
     let builder = Builder::new(&recipe, env, profile, ccache)?;
     builder.setup()?;
     builder.build()?;
+    builder.package(&output)?;
 
     Ok(())
 }
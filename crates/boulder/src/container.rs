@@ -8,7 +8,7 @@ use container::Container;
 use nix::sys::signal::Signal;
 use thiserror::Error;
 
-use crate::Paths;
+use crate::{package, Paths};
 
 pub fn exec(
     paths: &Paths,
@@ -57,6 +57,8 @@ pub enum ExecError {
     Signal(Signal),
     #[error("stopped by unknown signal")]
     UnknownSignal,
+    #[error("package")]
+    Package(#[from] package::Error),
     #[error(transparent)]
     Nix(#[from] nix::Error),
     #[error(transparent)]
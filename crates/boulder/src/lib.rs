@@ -18,6 +18,7 @@ mod dependency;
 pub mod env;
 pub mod job;
 pub mod macros;
+pub mod package;
 pub mod paths;
 pub mod pgo;
 pub mod profile;
@@ -0,0 +1,541 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Split the install root into packages and emit `.stone` files
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{self, Read},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use itertools::Itertools;
+use moss::{dependency, package::Meta, Dependency, Provider};
+use stone::{
+    header::v1::FileType,
+    payload::{layout, Layout},
+};
+use stone_recipe::{script, PathKind};
+use thiserror::Error;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{architecture, Macros, Recipe};
+
+pub use self::analysis::Analysis;
+
+pub mod analysis;
+
+/// A package produced from a recipe, along with the
+/// path rules used to select its contents
+#[derive(Debug)]
+pub struct Package {
+    pub name: String,
+    pub definition: stone_recipe::Package,
+    /// Number of leading `definition.paths` defined by the recipe
+    /// rather than a template
+    recipe_paths: usize,
+}
+
+/// Path rule, mapping a glob pattern to the package index
+/// it belongs to
+struct Rule {
+    pattern: fnmatch::Pattern,
+    kind: PathKind,
+    package: usize,
+}
+
+/// A single path collected from the install root
+#[derive(Debug)]
+pub struct PathInfo {
+    /// Absolute path on the filesystem
+    pub path: PathBuf,
+    /// Path relative to the install root, i.e. `/usr/bin/foo`
+    pub target: String,
+    pub metadata: fs::Metadata,
+    /// xxh3 128 digest for regular files
+    pub hash: Option<u128>,
+}
+
+impl PathInfo {
+    fn new(path: PathBuf, root: &Path) -> Result<Self, Error> {
+        let metadata = fs::symlink_metadata(&path)?;
+        let target = Path::new("/")
+            .join(path.strip_prefix(root).unwrap_or_else(|_| unreachable!()))
+            .display()
+            .to_string();
+
+        let hash = if metadata.is_file() {
+            let mut hasher = Xxh3::new();
+            let mut file = File::open(&path)?;
+            let mut buffer = [0; 8192];
+
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+            }
+
+            Some(hasher.digest128())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            path,
+            target,
+            metadata,
+            hash,
+        })
+    }
+
+    fn matches_kind(&self, kind: PathKind) -> bool {
+        let file_type = self.metadata.file_type();
+
+        match kind {
+            PathKind::Any => true,
+            PathKind::Exe => file_type.is_file() && self.metadata.mode() & 0o111 != 0,
+            PathKind::Symlink => file_type.is_symlink(),
+            PathKind::Special => {
+                file_type.is_char_device()
+                    || file_type.is_block_device()
+                    || file_type.is_fifo()
+                    || file_type.is_socket()
+            }
+        }
+    }
+
+    /// Layout entry for this path, relative to `/usr`
+    fn layout(&self) -> Result<Option<Layout>, Error> {
+        let Some(relative) = self.target.strip_prefix("/usr/") else {
+            return Ok(None);
+        };
+        let relative = relative.to_string();
+        let file_type = self.metadata.file_type();
+
+        let entry = if let Some(hash) = self.hash {
+            layout::Entry::Regular(hash, relative)
+        } else if file_type.is_symlink() {
+            let source = fs::read_link(&self.path)?.display().to_string();
+            layout::Entry::Symlink(source, relative)
+        } else if file_type.is_dir() {
+            layout::Entry::Directory(relative)
+        } else if file_type.is_char_device() {
+            layout::Entry::CharacterDevice(self.metadata.rdev(), relative)
+        } else if file_type.is_block_device() {
+            layout::Entry::BlockDevice(self.metadata.rdev(), relative)
+        } else if file_type.is_fifo() {
+            layout::Entry::Fifo(relative)
+        } else {
+            layout::Entry::Socket(relative)
+        };
+
+        Ok(Some(Layout {
+            // Packaged files are always owned by root
+            uid: 0,
+            gid: 0,
+            mode: self.metadata.mode(),
+            tag: 0,
+            entry,
+        }))
+    }
+}
+
+/// Resolve all packages defined by the recipe, merged with the
+/// package templates provided by the arch macros
+///
+/// The main package is always the first entry
+pub fn resolve(
+    recipe: &Recipe,
+    macros: &Macros,
+    arch_macros: &[String],
+) -> Result<Vec<Package>, Error> {
+    let mut parser = script::Parser::new();
+
+    for arch in arch_macros {
+        let macros = macros
+            .arch
+            .get(arch)
+            .cloned()
+            .ok_or_else(|| Error::MissingArchMacros(arch.clone()))?;
+
+        parser.add_macros(macros);
+    }
+
+    let source = &recipe.parsed.source;
+    parser.add_definition("name", &source.name);
+    parser.add_definition("version", &source.version);
+    parser.add_definition("release", source.release);
+
+    let expand = |input: &str| -> Result<String, Error> {
+        Ok(parser
+            .parse(input)?
+            .commands
+            .into_iter()
+            .find_map(|command| match command {
+                script::Command::Content(content) => Some(content),
+                script::Command::Break(_) => None,
+            })
+            .unwrap_or_default())
+    };
+
+    let mut packages = vec![Package {
+        name: source.name.clone(),
+        definition: stone_recipe::Package {
+            summary: None,
+            description: None,
+            run_deps: vec![],
+            conflicts: vec![],
+            paths: vec![],
+        },
+        recipe_paths: 0,
+    }];
+
+    // Recipe definitions take precedence over templates, so
+    // merge templates first and then recipe on top
+    let templates = arch_macros
+        .iter()
+        .filter_map(|arch| macros.arch.get(arch))
+        .flat_map(|macros| macros.packages.iter());
+    let definitions = Some(&recipe.parsed.package)
+        .into_iter()
+        .map(|package| (source.name.clone(), package));
+
+    for (name, definition, is_template) in templates
+        .map(|kv| Ok((expand(&kv.key)?, &kv.value, true)))
+        .chain(definitions.map(|(name, package)| Ok((name, package, false))))
+        .chain(
+            recipe
+                .parsed
+                .sub_packages
+                .iter()
+                .map(|kv| Ok((expand(&kv.key)?, &kv.value, false))),
+        )
+        .collect::<Result<Vec<_>, Error>>()?
+    {
+        let definition = stone_recipe::Package {
+            summary: definition.summary.as_deref().map(expand).transpose()?,
+            description: definition.description.as_deref().map(expand).transpose()?,
+            run_deps: definition
+                .run_deps
+                .iter()
+                .map(|dep| expand(dep))
+                .collect::<Result<_, _>>()?,
+            conflicts: definition
+                .conflicts
+                .iter()
+                .map(|conflict| expand(conflict))
+                .collect::<Result<_, _>>()?,
+            paths: definition
+                .paths
+                .iter()
+                .map(|path| {
+                    Ok(stone_recipe::Path {
+                        path: expand(&path.path.display().to_string())?.into(),
+                        kind: path.kind,
+                    })
+                })
+                .collect::<Result<_, Error>>()?,
+        };
+
+        if let Some(existing) = packages.iter_mut().find(|p| p.name == name) {
+            let merged = &mut existing.definition;
+
+            if definition.summary.is_some() {
+                merged.summary = definition.summary;
+            }
+            if definition.description.is_some() {
+                merged.description = definition.description;
+            }
+            merged.run_deps.extend(definition.run_deps);
+            merged.conflicts.extend(definition.conflicts);
+
+            // Recipe paths are matched before template paths
+            if is_template {
+                merged.paths.extend(definition.paths);
+            } else {
+                existing.recipe_paths += definition.paths.len();
+                merged.paths.splice(0..0, definition.paths);
+            }
+        } else {
+            let recipe_paths = if is_template {
+                0
+            } else {
+                definition.paths.len()
+            };
+
+            packages.push(Package {
+                name,
+                definition,
+                recipe_paths,
+            });
+        }
+    }
+
+    Ok(packages)
+}
+
+/// Collect all paths from the install root and split them
+/// into their owning [`Package`]
+///
+/// Returns the collected paths for each package, in the same
+/// order as `packages`
+pub fn collect(install_root: &Path, packages: &[Package]) -> Result<Vec<Vec<PathInfo>>, Error> {
+    // Main package is always the fallback, so sub package rules are
+    // matched first. Rules from the recipe take precedence over templates.
+    let ordered = || {
+        packages
+            .iter()
+            .enumerate()
+            .skip(1)
+            .chain(packages.iter().enumerate().take(1))
+    };
+    let rules = ordered()
+        .flat_map(|(package, p)| {
+            p.definition.paths[..p.recipe_paths]
+                .iter()
+                .map(move |path| (package, path))
+        })
+        .chain(ordered().flat_map(|(package, p)| {
+            p.definition.paths[p.recipe_paths..]
+                .iter()
+                .map(move |path| (package, path))
+        }))
+        .filter_map(|(package, path)| {
+            let pattern = rule_pattern(path.path.to_str()?);
+
+            Some(pattern.map(|pattern| Rule {
+                pattern,
+                kind: path.kind,
+                package,
+            }))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut collected = packages.iter().map(|_| vec![]).collect::<Vec<_>>();
+
+    for path in walk(install_root)? {
+        let info = PathInfo::new(path, install_root)?;
+
+        if info.target == "/usr" {
+            continue;
+        } else if !info.target.starts_with("/usr/") {
+            println!("Skipping path outside of /usr: {}", info.target);
+            continue;
+        }
+
+        let package = rules
+            .iter()
+            .find(|rule| info.matches_kind(rule.kind) && path_matches(&rule.pattern, &info.target))
+            .map(|rule| rule.package)
+            .unwrap_or(0);
+
+        collected[package].push(info);
+    }
+
+    Ok(collected)
+}
+
+/// Write a `.stone` binary package for `package` containing `paths`
+/// into `output_dir`, returning the path of the written stone
+pub fn emit(
+    recipe: &Recipe,
+    package: &Package,
+    paths: &[PathInfo],
+    analysis: &Analysis,
+    output_dir: &Path,
+) -> Result<PathBuf, Error> {
+    let source = &recipe.parsed.source;
+    let architecture = architecture::host().to_string();
+
+    let dependencies = package
+        .definition
+        .run_deps
+        .iter()
+        .map(|dep| parse_dependency(dep))
+        .collect::<Result<HashSet<_>, _>>()?
+        .into_iter()
+        .chain(analysis.dependencies.iter().cloned())
+        .collect();
+
+    let conflicts = package
+        .definition
+        .conflicts
+        .iter()
+        .map(|conflict| {
+            parse_dependency(conflict).map(|dep| Provider {
+                kind: dep.kind,
+                name: dep.name,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    let meta = Meta {
+        name: package.name.clone().into(),
+        version_identifier: source.version.clone(),
+        source_release: source.release,
+        build_release: 1,
+        architecture: architecture.clone(),
+        summary: package
+            .definition
+            .summary
+            .clone()
+            .or_else(|| recipe.parsed.package.summary.clone())
+            .unwrap_or_default(),
+        description: package
+            .definition
+            .description
+            .clone()
+            .or_else(|| recipe.parsed.package.description.clone())
+            .unwrap_or_default(),
+        source_id: source.name.clone(),
+        homepage: source.homepage.clone(),
+        licenses: source.license.clone(),
+        dependencies,
+        providers: analysis.providers.clone(),
+        conflicts,
+        uri: None,
+        hash: None,
+        download_size: None,
+        installed_size: None,
+    };
+
+    let layouts = paths
+        .iter()
+        .map(PathInfo::layout)
+        .flatten_ok()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Unique content, ordered by hash
+    let content = paths
+        .iter()
+        .filter_map(|info| Some((info.hash?, info)))
+        .collect::<BTreeMap<_, _>>();
+    let content_size = content
+        .values()
+        .map(|info| info.metadata.len())
+        .sum::<u64>();
+
+    let file_name = format!(
+        "{}-{}-{}-{}-{architecture}.stone",
+        package.name, source.version, source.release, meta.build_release,
+    );
+    let stone_path = output_dir.join(file_name);
+    let buffer_path = stone_path.with_extension("stone.content");
+
+    let mut out_file = File::create(&stone_path)?;
+    let buffer = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&buffer_path)?;
+
+    let mut writer = stone::Writer::new(&mut out_file, FileType::Binary)?
+        .with_content(buffer, Some(content_size))?;
+
+    writer.add_payload(meta.to_stone_payload().as_slice())?;
+    writer.add_payload(layouts.as_slice())?;
+
+    for info in content.values() {
+        writer.add_content(&mut File::open(&info.path)?)?;
+    }
+
+    writer.finalize()?;
+
+    fs::remove_file(&buffer_path)?;
+
+    Ok(stone_path)
+}
+
+/// Recursively list all entries beneath `dir`, sorted by path
+fn walk(dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    let mut paths = vec![];
+
+    for path in entries {
+        paths.push(path.clone());
+
+        if fs::symlink_metadata(&path)?.is_dir() {
+            paths.extend(walk(&path)?);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Parse a recipe rundep, where plain strings are package names
+fn parse_dependency(dep: &str) -> Result<Dependency, Error> {
+    if dep.contains('(') {
+        Ok(Dependency::from_str(dep)?)
+    } else {
+        Ok(Dependency {
+            kind: dependency::Kind::PackageName,
+            name: dep.to_string(),
+        })
+    }
+}
+
+/// Parse a recipe path as a glob. Recipe paths have no capture
+/// groups, so any parentheses are literal
+fn rule_pattern(path: &str) -> Result<fnmatch::Pattern, fnmatch::Error> {
+    path.replace('(', "\\(").replace(')', "\\)").parse()
+}
+
+/// Returns true if `path` or any of its ancestors match `pattern`
+fn path_matches(pattern: &fnmatch::Pattern, path: &str) -> bool {
+    Path::new(path)
+        .ancestors()
+        .filter_map(Path::to_str)
+        .any(|ancestor| pattern.is_match(ancestor))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("missing arch macros: {0}")]
+    MissingArchMacros(String),
+    #[error("script")]
+    Script(#[from] script::Error),
+    #[error("invalid dependency")]
+    Dependency(#[from] dependency::ParseError),
+    #[error("invalid path pattern")]
+    Pattern(#[from] fnmatch::Error),
+    #[error("stone write")]
+    Write(#[from] stone::write::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::{path_matches, rule_pattern};
+
+    #[test]
+    fn test_path_matches() {
+        let matches = |pattern: &str, path| path_matches(&rule_pattern(pattern).unwrap(), path);
+
+        assert!(matches("/usr/include", "/usr/include/foo/bar.h"));
+        assert!(matches("/usr/lib/lib*.so", "/usr/lib/libfoo.so"));
+        assert!(!matches("/usr/lib/lib*.so", "/usr/lib/libfoo.so.1"));
+        assert!(matches("/usr/lib/lib*.so.*", "/usr/lib/libfoo.so.1"));
+        assert!(!matches("/usr/lib/*.a", "/usr/lib/foo/bar.a"));
+        assert!(!matches("/usr/share/doc", "/usr/share/docs"));
+        assert!(matches(
+            "/usr/lib/libstdc++.so.6",
+            "/usr/lib/libstdc++.so.6"
+        ));
+        assert!(matches("/usr/include/c++/*", "/usr/include/c++/14/vector"));
+        assert!(matches("/usr/share/[x]", "/usr/share/[x]/y"));
+        assert!(matches("/usr/share/a (b)", "/usr/share/a (b)"));
+        assert!(!matches("/usr/lib/lib?.so", "/usr/lib/lib/.so"));
+    }
+}
@@ -0,0 +1,494 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Automatic dependency & provider analysis of collected paths
+
+use std::{
+    collections::HashSet,
+    error::Error as _,
+    fs,
+    io::{self, Read},
+    os::unix::fs::MetadataExt,
+    path::Path,
+};
+
+use elf::{abi, endian::AnyEndian, ElfBytes};
+use moss::{dependency::Kind, Dependency, Provider};
+use thiserror::Error;
+use tui::Stylize;
+
+use super::PathInfo;
+
+/// Dependencies and providers discovered for a set of paths
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub dependencies: HashSet<Dependency>,
+    pub providers: HashSet<Provider>,
+}
+
+impl Analysis {
+    fn depend(&mut self, kind: Kind, name: impl ToString) {
+        self.dependencies.insert(Dependency {
+            kind,
+            name: name.to_string(),
+        });
+    }
+
+    fn provide(&mut self, kind: Kind, name: impl ToString) {
+        self.providers.insert(Provider {
+            kind,
+            name: name.to_string(),
+        });
+    }
+}
+
+/// Analyse all `paths`, returning the discovered dependencies & providers
+///
+/// Dependencies satisfied by the paths themselves are dropped. Files
+/// which fail to parse are skipped with a warning
+pub fn analyse(paths: &[PathInfo]) -> Analysis {
+    let mut analysis = Analysis::default();
+
+    for info in paths.iter().filter(|info| info.metadata.is_file()) {
+        let mut file = Analysis::default();
+
+        match analyse_file(info, &mut file) {
+            Ok(()) => {
+                analysis.dependencies.extend(file.dependencies);
+                analysis.providers.extend(file.providers);
+            }
+            Err(error) => {
+                let reason = error.source().map(ToString::to_string).unwrap_or_default();
+                eprintln!(
+                    "{} skipping analysis of {}: {error}: {reason}",
+                    "Warning".yellow(),
+                    info.target
+                );
+            }
+        }
+    }
+
+    let Analysis {
+        dependencies,
+        providers,
+    } = &mut analysis;
+    dependencies.retain(|dep| {
+        !providers
+            .iter()
+            .any(|provider| provider.kind == dep.kind && provider.name == dep.name)
+    });
+
+    analysis
+}
+
+fn analyse_file(info: &PathInfo, analysis: &mut Analysis) -> Result<(), Error> {
+    binary(info, analysis);
+    pkgconfig(info, analysis)?;
+    cmake(info, analysis);
+
+    if info.metadata.mode() & 0o111 != 0 {
+        let mut magic = [0; 4];
+        let read = fs::File::open(&info.path)?.read(&mut magic)?;
+
+        if magic[..read] == *abi::ELFMAGIC.as_slice() {
+            elf(info, analysis)?;
+        } else if magic[..read].starts_with(b"#!") {
+            shebang(info, analysis)?;
+        }
+    } else if info.target.contains(".so") && is_library_dir(&info.target) {
+        // Shared libraries aren't always executable
+        elf(info, analysis)?;
+    }
+
+    Ok(())
+}
+
+/// Executables in `/usr/bin` & `/usr/sbin`
+fn binary(info: &PathInfo, analysis: &mut Analysis) {
+    if let Some(name) = info.target.strip_prefix("/usr/bin/") {
+        analysis.provide(Kind::Binary, name);
+    } else if let Some(name) = info.target.strip_prefix("/usr/sbin/") {
+        analysis.provide(Kind::SystemBinary, name);
+    }
+}
+
+/// `.pc` files provide themselves and depend on their `Requires`
+fn pkgconfig(info: &PathInfo, analysis: &mut Analysis) -> Result<(), Error> {
+    let path = Path::new(&info.target);
+
+    let (Some(name), Some(parent)) = (
+        path.file_name()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.strip_suffix(".pc")),
+        path.parent().and_then(|p| p.to_str()),
+    ) else {
+        return Ok(());
+    };
+
+    let kind = match parent {
+        "/usr/lib/pkgconfig" | "/usr/share/pkgconfig" => Kind::PkgConfig,
+        "/usr/lib32/pkgconfig" => Kind::PkgConfig32,
+        _ => return Ok(()),
+    };
+
+    analysis.provide(kind.clone(), name);
+
+    let content = fs::read_to_string(&info.path)?;
+
+    for requires in content.lines().filter_map(|line| {
+        line.strip_prefix("Requires:")
+            .or_else(|| line.strip_prefix("Requires.private:"))
+    }) {
+        let mut tokens = requires
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty());
+
+        while let Some(token) = tokens.next() {
+            // Skip version constraint & its version
+            if token.starts_with(['<', '>', '=', '!']) {
+                tokens.next();
+                continue;
+            }
+            // Can't resolve variables
+            if token.contains('$') {
+                continue;
+            }
+
+            analysis.depend(kind.clone(), token);
+        }
+    }
+
+    Ok(())
+}
+
+/// CMake config modules
+fn cmake(info: &PathInfo, analysis: &mut Analysis) {
+    let path = Path::new(&info.target);
+
+    let in_cmake_dir = ["/usr/lib/cmake/", "/usr/lib32/cmake/", "/usr/share/cmake/"]
+        .iter()
+        .any(|dir| info.target.starts_with(dir));
+
+    if !in_cmake_dir {
+        return;
+    }
+
+    if let Some(name) = path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .and_then(|s| {
+            s.strip_suffix("Config.cmake")
+                .or_else(|| s.strip_suffix("-config.cmake"))
+        })
+        .filter(|name| !name.is_empty())
+    {
+        analysis.provide(Kind::CMake, name);
+    }
+}
+
+/// Scripts depend on the binary of their interpreter
+fn shebang(info: &PathInfo, analysis: &mut Analysis) -> Result<(), Error> {
+    let mut buffer = [0; 256];
+    let read = fs::File::open(&info.path)?.read(&mut buffer)?;
+
+    let Some(line) = buffer[2..read]
+        .split(|b| *b == b'\n')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+    else {
+        return Ok(());
+    };
+
+    let mut parts = line.split_whitespace();
+
+    let interpreter = match parts.next() {
+        Some("/usr/bin/env" | "/bin/env") => parts.find(|arg| !arg.starts_with('-')),
+        other => other,
+    };
+
+    if let Some(name) = interpreter
+        .map(|s| s.rsplit('/').next().unwrap_or(s))
+        .filter(|name| !name.is_empty())
+    {
+        analysis.depend(Kind::Binary, name);
+    }
+
+    Ok(())
+}
+
+/// Shared library dependencies & providers of ELF files
+fn elf(info: &PathInfo, analysis: &mut Analysis) -> Result<(), Error> {
+    let data = fs::read(&info.path)?;
+
+    // Not everything named `.so` is an ELF (i.e. linker scripts)
+    let Ok(file) = ElfBytes::<AnyEndian>::minimal_parse(&data) else {
+        return Ok(());
+    };
+
+    let Some(machine) = machine(file.ehdr.e_machine) else {
+        return Ok(());
+    };
+
+    if let Some(segments) = file.segments() {
+        for header in segments.iter().filter(|h| h.p_type == abi::PT_INTERP) {
+            let interpreter = file.segment_data(&header)?;
+            let interpreter = String::from_utf8_lossy(interpreter);
+            let interpreter = interpreter.trim_end_matches('\0');
+
+            analysis.depend(Kind::Interpreter, format!("{interpreter}({machine})"));
+        }
+    }
+
+    let file_name = Path::new(&info.target)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+
+    // The dynamic loader provides itself via all its known locations
+    if file_name.starts_with("ld-linux") {
+        for dir in ["/usr/lib", "/usr/lib64", "/lib", "/lib64"] {
+            analysis.provide(Kind::Interpreter, format!("{dir}/{file_name}({machine})"));
+        }
+    }
+
+    let (Some(dynamic), Some(strtab_header)) =
+        (file.dynamic()?, file.section_header_by_name(".dynstr")?)
+    else {
+        return Ok(());
+    };
+    let strtab = file.section_data_as_strtab(&strtab_header)?;
+
+    let mut soname = None;
+
+    for entry in dynamic.iter() {
+        match entry.d_tag {
+            abi::DT_NEEDED => {
+                let needed = strtab.get(entry.d_val() as usize)?;
+                analysis.depend(Kind::SharedLibary, format!("{needed}({machine})"));
+            }
+            abi::DT_SONAME => {
+                soname = Some(strtab.get(entry.d_val() as usize)?);
+            }
+            _ => {}
+        }
+    }
+
+    // Only libraries in the linker path are provided, in
+    // absence of a soname we use the file name
+    if is_library_dir(&info.target) && file_name.contains(".so") {
+        let soname = soname.unwrap_or(file_name);
+        analysis.provide(Kind::SharedLibary, format!("{soname}({machine})"));
+    }
+
+    Ok(())
+}
+
+fn is_library_dir(target: &str) -> bool {
+    Path::new(target)
+        .parent()
+        .is_some_and(|parent| parent == Path::new("/usr/lib") || parent == Path::new("/usr/lib32"))
+}
+
+fn machine(e_machine: u16) -> Option<&'static str> {
+    match e_machine {
+        abi::EM_X86_64 => Some("x86_64"),
+        abi::EM_386 => Some("x86"),
+        abi::EM_AARCH64 => Some("aarch64"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("elf")]
+    Elf(#[from] elf::ParseError),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use std::{os::unix::fs::PermissionsExt, path::PathBuf};
+
+    use super::*;
+
+    /// Fixture root holding files at their install `target`
+    struct Root(PathBuf);
+
+    impl Root {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("boulder-analysis-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn add(&self, target: &str, content: &[u8], mode: u32) -> PathInfo {
+            let path = self.0.join(target.trim_start_matches('/'));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            PathInfo::new(path, &self.0).unwrap()
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn dependency(kind: Kind, name: &str) -> Dependency {
+        Dependency {
+            kind,
+            name: name.to_string(),
+        }
+    }
+
+    fn provider(kind: Kind, name: &str) -> Provider {
+        Provider {
+            kind,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_pkgconfig() {
+        let root = Root::new("pkgconfig");
+        let paths = [
+            root.add(
+                "/usr/lib/pkgconfig/foo.pc",
+                b"Name: foo\nRequires: bar >= 1.0, ${baz}\nRequires.private: qux\n",
+                0o644,
+            ),
+            root.add("/usr/lib32/pkgconfig/foo.pc", b"Requires: foo\n", 0o644),
+            root.add("/usr/lib/pkgconfig/qux.pc", b"Name: qux\n", 0o644),
+        ];
+
+        let analysis = analyse(&paths);
+
+        assert_eq!(
+            analysis.providers,
+            HashSet::from([
+                provider(Kind::PkgConfig, "foo"),
+                provider(Kind::PkgConfig, "qux"),
+                provider(Kind::PkgConfig32, "foo"),
+            ])
+        );
+        // `qux` is provided by the paths themselves
+        assert_eq!(
+            analysis.dependencies,
+            HashSet::from([dependency(Kind::PkgConfig, "bar")])
+        );
+    }
+
+    #[test]
+    fn test_cmake_and_binaries() {
+        let root = Root::new("cmake");
+        let paths = [
+            root.add("/usr/lib/cmake/Foo/FooConfig.cmake", b"", 0o644),
+            root.add("/usr/share/cmake/bar/bar-config.cmake", b"", 0o644),
+            root.add("/usr/lib/cmake/Foo/FooTargets.cmake", b"", 0o644),
+            root.add("/usr/bin/foo", b"", 0o755),
+            root.add("/usr/sbin/food", b"", 0o755),
+        ];
+
+        let analysis = analyse(&paths);
+
+        assert_eq!(
+            analysis.providers,
+            HashSet::from([
+                provider(Kind::CMake, "Foo"),
+                provider(Kind::CMake, "bar"),
+                provider(Kind::Binary, "foo"),
+                provider(Kind::SystemBinary, "food"),
+            ])
+        );
+        assert!(analysis.dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_shebang() {
+        let root = Root::new("shebang");
+        let paths = [
+            root.add("/usr/bin/a", b"#!/usr/bin/python3 -u\n", 0o755),
+            root.add("/usr/bin/b", b"#!/usr/bin/env -S perl -w\n", 0o755),
+            root.add("/usr/bin/c", b"#!/usr/bin/a\n", 0o755),
+            // Not executable, so not a script
+            root.add("/usr/share/d", b"#!/bin/zsh\n", 0o644),
+        ];
+
+        let analysis = analyse(&paths);
+
+        // `a` is provided by the paths themselves
+        assert_eq!(
+            analysis.dependencies,
+            HashSet::from([
+                dependency(Kind::Binary, "python3"),
+                dependency(Kind::Binary, "perl")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_elf() {
+        let root = Root::new("elf");
+        // The test binary itself is a dynamically linked ELF
+        let exe = fs::read(std::env::current_exe().unwrap()).unwrap();
+        let paths = [
+            root.add("/usr/lib/libfoo.so", &exe, 0o644),
+            // Linker scripts aren't ELF files
+            root.add("/usr/lib/libbar.so", b"INPUT(libbar.so.1)\n", 0o644),
+        ];
+
+        let analysis = analyse(&paths);
+
+        let machine = machine(
+            ElfBytes::<AnyEndian>::minimal_parse(&exe)
+                .unwrap()
+                .ehdr
+                .e_machine,
+        )
+        .unwrap();
+        assert_eq!(
+            analysis.providers,
+            HashSet::from([provider(
+                Kind::SharedLibary,
+                &format!("libfoo.so({machine})")
+            )])
+        );
+        assert!(analysis.dependencies.iter().any(
+            |dep| dep.kind == Kind::Interpreter && dep.name.ends_with(&format!("({machine})"))
+        ));
+        assert!(analysis.dependencies.contains(&dependency(
+            Kind::SharedLibary,
+            &format!("libc.so.6({machine})")
+        )));
+    }
+
+    #[test]
+    fn test_unparseable_skipped() {
+        let root = Root::new("unparseable");
+        let paths = [
+            root.add(
+                "/usr/lib/pkgconfig/broken.pc",
+                b"Requires: \xff\xfe\n",
+                0o644,
+            ),
+            root.add("/usr/lib/pkgconfig/foo.pc", b"Requires: bar\n", 0o644),
+        ];
+
+        let analysis = analyse(&paths);
+
+        assert_eq!(
+            analysis.providers,
+            HashSet::from([provider(Kind::PkgConfig, "foo")])
+        );
+        assert_eq!(
+            analysis.dependencies,
+            HashSet::from([dependency(Kind::PkgConfig, "bar")])
+        );
+    }
+}
@@ -77,6 +77,31 @@ impl Manager {
         configs
     }
 
+    /// Like [`Manager::load`], but fails if any config file
+    /// exists and can't be read or parsed, rather than skipping it
+    pub async fn try_load<T: Config>(&self) -> Result<Option<T>, LoadError> {
+        let domain = T::domain();
+
+        let mut merged = None::<T>;
+
+        for (entry, resolve) in self.scope.load_with() {
+            for path in enumerate_paths(entry, resolve, &domain).await {
+                let bytes = fs::read(&path)
+                    .await
+                    .map_err(|io| LoadError::Read(path.clone(), io))?;
+                let config =
+                    serde_yaml::from_slice(&bytes).map_err(|yaml| LoadError::Parse(path, yaml))?;
+
+                merged = Some(match merged {
+                    Some(merged) => merged.merge(config),
+                    None => config,
+                });
+            }
+        }
+
+        Ok(merged)
+    }
+
     pub async fn save<T: Config + Serialize>(
         &self,
         name: impl fmt::Display,
@@ -117,6 +142,14 @@ impl Manager {
 #[error("$HOME or $XDG_CONFIG_HOME env not set")]
 pub struct CreateUserError;
 
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("read config file {0:?}")]
+    Read(PathBuf, #[source] io::Error),
+    #[error("parse config file {0:?}")]
+    Parse(PathBuf, #[source] serde_yaml::Error),
+}
+
 #[derive(Debug, Error)]
 pub enum SaveError {
     #[error("create config dir {0:?}")]
@@ -331,3 +364,57 @@ impl<'a> Resolve<'a> {
         self.config_dir().join(format!("{domain}.d"))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Keys(BTreeMap<String, String>);
+
+    impl Config for Keys {
+        fn domain() -> String {
+            "keys".into()
+        }
+
+        fn merge(self, other: Self) -> Self {
+            Self(self.0.into_iter().chain(other.0).collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_try_load() {
+        let dir = std::env::temp_dir().join(format!("config-try-load-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir).await;
+        fs::create_dir_all(dir.join("keys.d")).await.unwrap();
+
+        let manager = Manager::custom(&dir);
+
+        assert!(manager.try_load::<Keys>().await.unwrap().is_none());
+
+        fs::write(dir.join("keys.yaml"), "a: one").await.unwrap();
+        fs::write(dir.join("keys.d/b.yaml"), "b: two")
+            .await
+            .unwrap();
+
+        let keys = manager.try_load::<Keys>().await.unwrap().unwrap();
+        assert_eq!(keys.0.len(), 2);
+
+        fs::write(dir.join("keys.d/c.yaml"), "- not a map")
+            .await
+            .unwrap();
+
+        // `load` skips the broken file while `try_load` refuses it
+        assert_eq!(manager.load::<Keys>().await.unwrap().0.len(), 2);
+        assert!(matches!(
+            manager.try_load::<Keys>().await,
+            Err(LoadError::Parse(path, _)) if path.ends_with("c.yaml")
+        ));
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
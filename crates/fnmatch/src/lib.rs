@@ -31,16 +31,13 @@ enum Fragment {
     /// `*`
     MatchAny,
 
-    /// `\`
-    BackSlash,
-
     /// `.`
     Dot,
 
     /// `/
     ForwardSlash,
 
-    /// Normal text.
+    /// Normal text, matched literally
     Text(String),
 
     /// Group: Name to fragment mapping
@@ -104,6 +101,7 @@ impl<'a> StringWalker<'a> {
 pub struct Pattern {
     pattern: String,
     regex: Regex,
+    anchored: Regex,
     groups: Vec<String>,
 }
 
@@ -141,6 +139,12 @@ impl Pattern {
         }
     }
 
+    /// Returns true if the whole of `path` matches our `pattern`,
+    /// rather than only some part of it
+    pub fn is_match(&self, path: &str) -> bool {
+        self.anchored.is_match(path)
+    }
+
     /// Return a copy of the internal capture groups
     pub fn groups(&self) -> Vec<String> {
         self.groups.clone()
@@ -171,7 +175,11 @@ fn fragments_from_string(s: &str) -> Result<Vec<Fragment>, Error> {
         let next_token = match ch {
             '?' => Some(Fragment::MatchOne),
             '*' => Some(Fragment::MatchAny),
-            '\\' => Some(Fragment::BackSlash),
+            // Escapes the next character
+            '\\' => {
+                text.extend(walker.next());
+                None
+            }
             '/' => Some(Fragment::ForwardSlash),
             '.' => Some(Fragment::Dot),
             '(' => {
@@ -218,12 +226,11 @@ fn fragments_from_string(s: &str) -> Result<Vec<Fragment>, Error> {
 fn fragment_to_regex_str(fragment: &Fragment) -> (String, Vec<String>) {
     let mut groups = vec![];
     let string = match fragment {
-        Fragment::MatchOne => ".".into(),
+        Fragment::MatchOne => "[^\\/]".into(),
         Fragment::MatchAny => "[^\\/]*".into(),
-        Fragment::BackSlash => "\\".into(),
         Fragment::ForwardSlash => "\\/".into(),
         Fragment::Dot => "\\.".into(),
-        Fragment::Text(t) => t.clone(),
+        Fragment::Text(t) => regex::escape(t),
         Fragment::Group(id, elements) => {
             let elements = elements
                 .iter()
@@ -259,6 +266,7 @@ impl FromStr for Pattern {
         Ok(Self {
             pattern: s.into(),
             regex: Regex::new(&compiled)?,
+            anchored: Regex::new(&format!("^(?:{compiled})$"))?,
             groups: groups.into_iter().collect(),
         })
     }
@@ -321,4 +329,36 @@ pub mod path_tests {
         let bad = k.match_path("/usr/lib/modules/6.2.6/l/modules.symbols");
         assert!(bad.is_none());
     }
+
+    #[test]
+    fn test_is_match() {
+        let k = "/usr/bin/*".parse::<Pattern>().unwrap();
+
+        assert!(k.is_match("/usr/bin/moss"));
+        assert!(!k.is_match("/usr/bin/moss/extra"));
+        assert!(!k.is_match("/root/usr/bin/moss"));
+        assert!(k.match_path("/root/usr/bin/moss").is_some());
+
+        let k = "/usr/lib/lib?.so".parse::<Pattern>().unwrap();
+        assert!(k.is_match("/usr/lib/liba.so"));
+        assert!(!k.is_match("/usr/lib/lib/.so"));
+    }
+
+    #[test]
+    fn test_literal_text() {
+        let k = "/usr/lib/libstdc++.so.6".parse::<Pattern>().unwrap();
+        assert!(k.is_match("/usr/lib/libstdc++.so.6"));
+        assert!(!k.is_match("/usr/lib/libstdcc.so.6"));
+
+        let k = "/usr/include/c++/*".parse::<Pattern>().unwrap();
+        assert!(k.is_match("/usr/include/c++/x"));
+
+        for path in ["/usr/share/[a]", "/usr/share/{a|b}^$"] {
+            assert!(path.parse::<Pattern>().unwrap().is_match(path));
+        }
+
+        let k = "/usr/share/\\(a\\)\\*".parse::<Pattern>().unwrap();
+        assert!(k.is_match("/usr/share/(a)*"));
+        assert!(!k.is_match("/usr/share/(a)b"));
+    }
 }
@@ -0,0 +1,336 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use clap::{arg, ArgMatches, Command};
+use futures::StreamExt;
+use moss::{
+    client::{self, plan::Plan},
+    dependency,
+    package::Flags,
+    registry::transaction,
+    repository,
+    state::Selection,
+    Provider,
+};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::fs;
+use tui::{
+    dialoguer::{theme::ColorfulTheme, Confirm},
+    Stylize,
+};
+
+use super::{lock_kind, open_client, print_plan, Format};
+
+pub fn command() -> Command {
+    Command::new("apply")
+        .about("Converge the system to a manifest")
+        .long_about(
+            "Install and remove packages so the explicitly selected packages are exactly \
+             those listed in the manifest, dropping any dependencies no longer needed. \
+             Repositories listed in the manifest are used to plan the changes and added \
+             to the configuration once they're confirmed",
+        )
+        .arg(
+            arg!(<FILE> "system manifest, i.e. system.yaml")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(arg!(--"dry-run" "Print the resulting changes without applying anything"))
+}
+
+/// Declarative set of explicit packages (and repositories) a system should have
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    /// Providers of the explicitly selected packages, i.e. `nano` or `binary(ls)`
+    packages: Vec<String>,
+    #[serde(default)]
+    repositories: repository::Map,
+}
+
+impl Manifest {
+    fn parse(text: &str) -> Result<Self, Error> {
+        let manifest = serde_yaml::from_str::<Self>(text)?;
+
+        // An empty selection would remove everything
+        if manifest.packages.is_empty() {
+            return Err(Error::NoPackages);
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Handle execution of `moss apply`
+pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let path = args.get_one::<PathBuf>("FILE").unwrap();
+    let yes_all = *args.get_one::<bool>("yes").unwrap();
+    let dry_run = args.get_flag("dry-run");
+    let text = Format::from_args(args) == Format::Text;
+
+    let manifest = Manifest::parse(
+        &fs::read_to_string(path)
+            .await
+            .map_err(Error::ReadManifest)?,
+    )?;
+
+    let mut client = open_client(args, root, lock_kind(dry_run)).await?;
+
+    // Repositories which are missing from the configuration or differ
+    let config = config::Manager::system(root, "moss");
+    let configured = config.load::<repository::Map>().await.unwrap_or_default();
+    let changed = repository::Map::with(
+        manifest
+            .repositories
+            .iter()
+            .filter(|(id, repository)| configured.get(id) != Some(*repository))
+            .map(|(id, repository)| (id.clone(), repository.clone())),
+    );
+
+    if !changed.is_empty() {
+        if dry_run {
+            if text {
+                for (id, _) in changed.iter() {
+                    println!(
+                        "{} repository {id} isn't configured yet and won't be used",
+                        "Warning".yellow()
+                    );
+                }
+                println!();
+            }
+        } else {
+            // Nothing is saved until the changes are confirmed
+            client.refresh_repositories_with(changed.clone()).await?;
+        }
+    }
+
+    let installed = client
+        .registry
+        .list_installed(Flags::NONE)
+        .collect::<Vec<_>>()
+        .await;
+    let installed_ids = installed
+        .iter()
+        .map(|p| p.id.clone())
+        .collect::<HashSet<_>>();
+
+    // Keep installed packages which provide an entry, otherwise pick
+    // the highest priority candidate
+    let mut explicit = vec![];
+    let mut missing = vec![];
+    let mut not_found = vec![];
+    for name in &manifest.packages {
+        let provider = Provider::from_name(name)?;
+
+        let id = if let Some(package) = installed
+            .iter()
+            .find(|p| p.meta.providers.contains(&provider))
+        {
+            package.id.clone()
+        } else if let Some(package) = client
+            .registry
+            .allowed_by_provider(&provider, Flags::AVAILABLE)
+            .boxed()
+            .next()
+            .await
+        {
+            missing.push(package.id.clone());
+            package.id
+        } else {
+            not_found.push(name.clone());
+            continue;
+        };
+
+        if !explicit.contains(&id) {
+            explicit.push(id);
+        }
+    }
+    if !not_found.is_empty() {
+        return Err(Error::NotFound(not_found.join(", ")));
+    }
+
+    // Start from what's installed, add what's missing, then drop
+    // everything no longer reachable from the explicit packages
+    let mut transaction = client
+        .registry
+        .transaction_with_installed(installed_ids.iter().cloned().collect())
+        .await?;
+    transaction.add(missing).await?;
+    transaction.remove_orphans(&explicit);
+
+    let finalized = client.resolve_packages(transaction.finalize()).await?;
+
+    let previous_selections = match client.installation.active_state {
+        Some(id) => client.state_db.get(&id).await?.selections,
+        None => vec![],
+    };
+    let selections = finalized
+        .iter()
+        .map(|package| Selection {
+            package: package.id.clone(),
+            explicit: explicit.contains(&package.id),
+            reason: previous_selections
+                .iter()
+                .find(|s| s.package == package.id)
+                .and_then(|s| s.reason.clone()),
+        })
+        .collect::<Vec<_>>();
+
+    let plan = Plan::new(&installed, &finalized);
+
+    if dry_run {
+        print_plan(args, &plan)?;
+        return Ok(());
+    }
+
+    // Only explicit markers may have changed
+    let marked = |selections: &[Selection]| {
+        selections
+            .iter()
+            .map(|s| (s.package.clone(), s.explicit))
+            .collect::<HashSet<_>>()
+    };
+    let reselected = marked(&previous_selections) != marked(&selections);
+    if plan.is_empty() && !reselected && changed.is_empty() {
+        print_plan(args, &plan)?;
+        return Ok(());
+    }
+
+    // Scripts always get the plan, even if only markers or repositories change
+    if !plan.is_empty() || !text {
+        print_plan(args, &plan)?;
+    }
+
+    if !plan.is_empty() {
+        if text {
+            println!();
+        }
+
+        let result = if yes_all {
+            true
+        } else {
+            Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(" Do you wish to continue? ")
+                .default(false)
+                .interact()?
+        };
+        if !result {
+            return Err(Error::Cancelled);
+        }
+    }
+
+    for (id, repository) in changed {
+        config
+            .save(&id, &repository::Map::with([(id.clone(), repository)]))
+            .await?;
+        if text {
+            println!("{} repository {id}", "Configured".green());
+        }
+    }
+
+    if plan.is_empty() && !reselected {
+        return Ok(());
+    }
+
+    let fetched = finalized
+        .iter()
+        .filter(|p| !installed_ids.contains(&p.id))
+        .collect::<Vec<_>>();
+    client.cache_packages(&fetched).await?;
+
+    client.apply_state(&selections, "Apply").await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("cancelled")]
+    Cancelled,
+
+    #[error("manifest lists no packages, refusing to remove everything")]
+    NoPackages,
+
+    #[error("no package found: {0}")]
+    NotFound(String),
+
+    #[error("read manifest")]
+    ReadManifest(#[source] io::Error),
+
+    #[error("parse manifest")]
+    ParseManifest(#[from] serde_yaml::Error),
+
+    #[error("invalid provider")]
+    Provider(#[from] dependency::ParseError),
+
+    #[error("save config")]
+    SaveConfig(#[from] config::SaveError),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("state db")]
+    StateDB(#[from] moss::db::state::Error),
+
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+
+    #[error("string processing")]
+    Dialog(#[from] tui::dialoguer::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let manifest = Manifest::parse(
+            "
+packages:
+  - nano
+  - binary(ls)
+repositories:
+  volatile:
+    description: Volatile
+    uri: https://example.com/volatile/x86_64/stone.index
+    priority: 0
+",
+        )
+        .unwrap();
+        assert_eq!(manifest.packages, vec!["nano", "binary(ls)"]);
+        assert!(manifest
+            .repositories
+            .get(&repository::Id::new("volatile".into()))
+            .is_some());
+
+        let manifest = Manifest::parse("packages: [nano]").unwrap();
+        assert!(manifest.repositories.is_empty());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(matches!(
+            Manifest::parse("packages: [nano]\nrepos: {}"),
+            Err(Error::ParseManifest(_))
+        ));
+        assert!(matches!(
+            Manifest::parse("packages: []"),
+            Err(Error::NoPackages)
+        ));
+        assert!(matches!(
+            Manifest::parse("repositories: {}"),
+            Err(Error::ParseManifest(_))
+        ));
+    }
+}
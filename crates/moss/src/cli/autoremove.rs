@@ -0,0 +1,79 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{collections::HashSet, path::Path};
+
+use clap::{ArgMatches, Command};
+use futures::StreamExt;
+use moss::{
+    client::{self, lock},
+    package::Flags,
+    registry::transaction,
+};
+use thiserror::Error;
+
+use super::{open_client, remove};
+
+pub fn command() -> Command {
+    Command::new("autoremove")
+        .about("Remove orphaned packages")
+        .long_about(
+            "Remove packages which were installed as a dependency and are no \
+             longer needed by any explicitly installed package",
+        )
+}
+
+/// Handle execution of `moss autoremove`
+pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let client = open_client(args, root, lock::Kind::Exclusive).await?;
+
+    let installed_ids = client
+        .registry
+        .list_installed(Flags::NONE)
+        .map(|p| p.id)
+        .collect::<HashSet<_>>()
+        .await;
+
+    let previous_selections = remove::previous_selections(&client).await?;
+
+    let explicit = remove::explicit(&previous_selections);
+    if explicit.is_empty() {
+        return Err(Error::NoExplicit);
+    }
+
+    let mut transaction = client
+        .registry
+        .transaction_with_installed(installed_ids.clone().into_iter().collect())
+        .await?;
+
+    transaction.remove_orphans(&explicit);
+
+    let finalized = transaction.finalize().cloned().collect::<HashSet<_>>();
+
+    remove::apply(
+        &client,
+        &installed_ids,
+        finalized,
+        previous_selections,
+        "Autoremove",
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("no explicitly installed packages, refusing to remove everything")]
+    NoExplicit,
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("transaction")]
+    Transaction(#[from] transaction::Error),
+
+    #[error("remove")]
+    Remove(#[from] remove::Error),
+}
@@ -0,0 +1,135 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::path::Path;
+
+use clap::{arg, ArgMatches, Command};
+use futures::StreamExt;
+use moss::{
+    client::{
+        self,
+        lock::{self, Lock},
+    },
+    package::{self, Flags},
+    registry::pin::Rules,
+    Installation,
+};
+use thiserror::Error;
+use tui::Stylize;
+
+use super::{open_client, pin, print_json, Format};
+
+pub fn command() -> Command {
+    Command::new("hold")
+        .about("Hold packages at their installed version")
+        .long_about(
+            "Held packages are never replaced by sync or install. \
+             Without any names, list all held packages",
+        )
+        .arg(arg!([NAME] ... "packages to hold").value_parser(clap::value_parser!(String)))
+        .arg(arg!(--remove "Release the hold on the packages").requires("NAME"))
+}
+
+/// Handle execution of `moss hold`
+pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let config = config::Manager::system(root, "moss");
+
+    let names = args
+        .get_many::<String>("NAME")
+        .into_iter()
+        .flatten()
+        .map(|name| package::Name::from(name.clone()))
+        .collect::<Vec<_>>();
+
+    if names.is_empty() {
+        let rules = config
+            .try_load::<Rules>()
+            .await
+            .map_err(pin::Error::from)?
+            .unwrap_or_default();
+        let held = rules
+            .iter()
+            .filter(|(_, rule)| rule.hold)
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+
+        if Format::from_args(args) == Format::Json {
+            print_json(&held)?;
+        } else {
+            for name in held {
+                println!("{}", name.to_string().bold());
+            }
+        }
+
+        return Ok(());
+    }
+
+    let hold = !args.get_flag("remove");
+
+    // Rules are read by every resolving moss process, so hold the
+    // installation exclusively until they're saved. Only installed
+    // packages can be held, but a hold can always be released
+    let (_client, _lock) = if hold {
+        let client = open_client(args, root, lock::Kind::Exclusive).await?;
+
+        let mut not_installed = vec![];
+        for name in &names {
+            if client
+                .registry
+                .by_name(name, Flags::INSTALLED)
+                .boxed()
+                .next()
+                .await
+                .is_none()
+            {
+                not_installed.push(name.to_string());
+            }
+        }
+
+        if !not_installed.is_empty() {
+            return Err(Error::NotInstalled(not_installed.join(", ")));
+        }
+
+        (Some(client), None)
+    } else {
+        let lock = Lock::acquire(
+            &Installation::open(root),
+            lock::Kind::Exclusive,
+            !args.get_flag("no-wait"),
+        )
+        .await
+        .map_err(pin::Error::from)?;
+
+        (None, Some(lock))
+    };
+
+    for name in names {
+        let mut rule = pin::load(&config, &name).await?;
+        rule.hold = hold;
+        pin::save(&config, &name, rule).await?;
+
+        if hold {
+            println!("{} {name}", "Held".green());
+        } else {
+            println!("{} {name}", "Released".green());
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("not installed: {0}")]
+    NotInstalled(String),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("pin")]
+    Pin(#[from] pin::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
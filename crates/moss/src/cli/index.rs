@@ -8,11 +8,12 @@ use std::{
     time::Duration,
 };
 
-use clap::{arg, value_parser, ArgMatches, Command};
+use clap::{arg, value_parser, ArgAction, ArgMatches, Command};
 use futures::{future::BoxFuture, stream, FutureExt, StreamExt, TryStreamExt};
 use moss::{
     client, environment,
     package::{self, Meta, MissingMetaFieldError},
+    repository::trust,
 };
 use sha2::{Digest, Sha256};
 use thiserror::Error;
@@ -23,6 +24,32 @@ pub fn command() -> Command {
     Command::new("index")
         .about("Index a collection of packages")
         .arg(arg!(<INDEX_DIR> "directory of index files").value_parser(value_parser!(PathBuf)))
+        .arg(
+            arg!(--sign <KEY_FILE> "sign the index with a secret key")
+                .long_help(
+                    "Sign the index with the hex encoded ed25519 secret key in KEY_FILE, \
+                     writing the detached signatures to stone.index.sig and stone.deltas.sig",
+                )
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--deltas "generate deltas from older releases to the latest")
+                .long_help(
+                    "Generate deltas from each older release of a package to its latest release, \
+                     containing only new or changed content. Deltas are written to the deltas \
+                     directory and listed in stone.deltas, which clients unaware of deltas ignore",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"installed-size" "record the installed size of each package in the index")
+                .long_help(
+                    "Record the installed size of each package in the index, so transaction \
+                     plans can report it. Clients which can't skip unknown metadata tags fail \
+                     to read such an index, so only enable this once they've been updated",
+                )
+                .action(ArgAction::SetTrue),
+        )
 }
 
 pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
@@ -31,6 +58,16 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
         .unwrap()
         .canonicalize()?;
 
+    // Load the key upfront so we fail before doing any work
+    let secret_key = match args.get_one::<PathBuf>("sign") {
+        Some(path) => Some(trust::SecretKey::from_hex(
+            &fs::read_to_string(path).await?,
+        )?),
+        None => None,
+    };
+
+    let installed_size = args.get_flag("installed-size");
+
     let stone_files = enumerate_stone_files(&dir).await?;
 
     println!("Indexing {} files\n", stone_files.len());
@@ -47,12 +84,13 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
     total_progress.tick();
 
     let list = stream::iter(&stone_files)
-        .map(|path| get_meta(path, &dir, &multi_progress, &total_progress))
+        .map(|path| get_meta(path, &dir, installed_size, &multi_progress, &total_progress))
         .buffer_unordered(environment::MAX_DISK_CONCURRENCY)
         .try_collect::<Vec<_>>()
         .await?;
 
     let mut map = BTreeMap::new();
+    let mut superseded = vec![];
 
     // Add each meta to the map, removing
     // dupes by keeping the latest release
@@ -72,32 +110,68 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
                     }
                     // Update if dupe is newer version
                     (prev, curr) if prev < curr => {
-                        entry.insert(meta);
+                        superseded.push(entry.insert(meta));
                     }
                     // Otherwise prev is more recent, don't replace
-                    _ => {}
+                    _ => superseded.push(meta),
                 }
             }
         }
     }
 
-    write_index(&dir, map, &total_progress).await?;
+    let deltas = if args.get_flag("deltas") {
+        stream::iter(&superseded)
+            .map(|from| {
+                get_delta(
+                    from,
+                    &map[&from.name],
+                    &dir,
+                    &multi_progress,
+                    &total_progress,
+                )
+            })
+            .buffer_unordered(environment::MAX_DISK_CONCURRENCY)
+            .try_filter_map(|delta| async { Ok(delta) })
+            .try_collect::<Vec<_>>()
+            .await?
+    } else {
+        vec![]
+    };
+
+    let written = write_index(&dir, map, deltas, &total_progress).await?;
 
     multi_progress.clear()?;
 
-    println!(
-        "\nIndex file written to {:?}",
-        dir.join("stone.index").display()
-    );
+    println!();
+    for name in &written {
+        println!("Index file written to {:?}", dir.join(name).display());
+    }
+
+    if let Some(secret_key) = secret_key {
+        for name in &written {
+            let index = fs::read(dir.join(name)).await?;
+            fs::write(dir.join(format!("{name}.sig")), secret_key.sign(&index)).await?;
+        }
+
+        println!(
+            "Index signed with public key {}",
+            secret_key.public().to_string().bold()
+        );
+    }
 
     Ok(())
 }
 
+/// Write the packages to `stone.index`, and `deltas` to `stone.deltas` so
+/// clients which reject the delta metadata can still read the index
+///
+/// Returns the names of the files written
 async fn write_index(
     dir: &Path,
     map: BTreeMap<package::Name, Meta>,
+    deltas: Vec<package::Delta>,
     total_progress: &ProgressBar,
-) -> Result<(), Error> {
+) -> Result<Vec<&'static str>, Error> {
     use std::fs::File;
 
     let dir = dir.to_path_buf();
@@ -122,7 +196,29 @@ async fn write_index(
 
         writer.finalize()?;
 
-        Ok(())
+        // Don't leave deltas of a previous run listed
+        if deltas.is_empty() {
+            for name in ["stone.deltas", "stone.deltas.sig"] {
+                match std::fs::remove_file(dir.join(name)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            return Ok(vec!["stone.index"]);
+        }
+
+        let mut file = File::create(dir.join("stone.deltas"))?;
+
+        let mut writer = stone::Writer::new(&mut file, stone::header::v1::FileType::Repository)?;
+
+        for delta in deltas {
+            let payload = delta.to_stone_payload();
+            writer.add_payload(payload.as_slice())?;
+        }
+
+        writer.finalize()?;
+
+        Ok(vec!["stone.index", "stone.deltas"])
     })
     .await
     .expect("join handle")
@@ -131,6 +227,7 @@ async fn write_index(
 async fn get_meta(
     path: &Path,
     dir: &Path,
+    installed_size: bool,
     multi_progress: &MultiProgress,
     total_progress: &ProgressBar,
 ) -> Result<Meta, Error> {
@@ -165,6 +262,14 @@ async fn get_meta(
     meta.hash = Some(hash);
     meta.download_size = Some(size);
     meta.uri = Some(relative_path.clone());
+    // Only the index records the installed size, and only when
+    // asked to since older decoders reject unknown tags
+    if installed_size {
+        meta.installed_size = payloads
+            .iter()
+            .find_map(|payload| payload.content())
+            .map(|content| content.header.plain_size);
+    }
 
     progress.finish();
     multi_progress.remove(&progress);
@@ -174,6 +279,157 @@ async fn get_meta(
     Ok(meta)
 }
 
+/// Generate a delta from the package `from` to the package `to`,
+/// returning `None` if it isn't any smaller than `to`
+async fn get_delta(
+    from: &Meta,
+    to: &Meta,
+    dir: &Path,
+    multi_progress: &MultiProgress,
+    total_progress: &ProgressBar,
+) -> Result<Option<package::Delta>, Error> {
+    let (Some(from_uri), Some(from_hash), Some(to_uri), Some(to_hash)) =
+        (&from.uri, &from.hash, &to.uri, &to.hash)
+    else {
+        return Ok(None);
+    };
+
+    // Named by both hashes so existing deltas can be reused
+    let relative_path = format!(
+        "deltas/{}-{}-{}.delta.stone",
+        to.name,
+        &from_hash[..from_hash.len().min(16)],
+        &to_hash[..to_hash.len().min(16)],
+    );
+    let path = dir.join(&relative_path);
+
+    if !fs::try_exists(&path).await? {
+        fs::create_dir_all(dir.join("deltas")).await?;
+
+        task::spawn_blocking({
+            let from = dir.join(from_uri);
+            let to = dir.join(to_uri);
+            let path = path.clone();
+
+            move || write_delta(&from, &to, &path)
+        })
+        .await
+        .expect("join handle")?;
+    }
+
+    let progress = multi_progress.insert_before(total_progress, ProgressBar::new_spinner());
+    progress.enable_steady_tick(Duration::from_millis(150));
+
+    let (size, hash) = stat_file(&path, &relative_path, &progress).await?;
+
+    progress.finish();
+    multi_progress.remove(&progress);
+
+    // No bandwidth to be saved
+    if size >= to.download_size.unwrap_or(u64::MAX) {
+        fs::remove_file(&path).await?;
+        return Ok(None);
+    }
+
+    multi_progress.println(format!(
+        "{} {} {} -> {}",
+        "Delta".green(),
+        to.name.to_string().bold(),
+        from.source_release,
+        to.source_release,
+    ))?;
+
+    Ok(Some(package::Delta {
+        from: from_hash.clone().into(),
+        to: to_hash.clone().into(),
+        uri: relative_path,
+        hash,
+        download_size: size,
+    }))
+}
+
+/// Write a delta stone to `out_path` containing the metadata & layout of the
+/// package at `to`, with only the content not already present in `from`
+fn write_delta(from: &Path, to: &Path, out_path: &Path) -> Result<(), Error> {
+    use std::{
+        collections::HashSet,
+        fs::{self, File},
+        io::{Read, Seek, SeekFrom},
+    };
+
+    use moss::stone::{header::v1::FileType, read::PayloadKind};
+
+    let existing = stone::read(File::open(from)?)?
+        .payloads()?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .filter_map(PayloadKind::index)
+        .flat_map(|payload| &payload.body)
+        .map(|index| index.digest)
+        .collect::<HashSet<_>>();
+
+    let mut reader = stone::read(File::open(to)?)?;
+    let payloads = reader.payloads()?.collect::<Result<Vec<_>, _>>()?;
+
+    let content_path = out_path.with_extension("content");
+    let buffer_path = out_path.with_extension("buffer");
+
+    // Unpack the full content so changed blobs can be split out
+    let content_file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&content_path)?;
+    if let Some(content) = payloads.iter().find_map(PayloadKind::content) {
+        reader.unpack_content(content, &mut &content_file)?;
+    }
+
+    let changed = payloads
+        .iter()
+        .filter_map(PayloadKind::index)
+        .flat_map(|payload| &payload.body)
+        .filter(|index| !existing.contains(&index.digest))
+        .collect::<Vec<_>>();
+
+    let buffer = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&buffer_path)?;
+
+    let mut out_file = File::create(out_path)?;
+    let mut writer = stone::Writer::new(&mut out_file, FileType::Delta)?.with_content(
+        buffer,
+        Some(changed.iter().map(|index| index.end - index.start).sum()),
+    )?;
+
+    for payload in &payloads {
+        match payload {
+            PayloadKind::Meta(meta) => writer.add_payload(meta.body.as_slice())?,
+            PayloadKind::Attributes(attributes) => {
+                writer.add_payload(attributes.body.as_slice())?
+            }
+            PayloadKind::Layout(layout) => writer.add_payload(layout.body.as_slice())?,
+            PayloadKind::Index(_) | PayloadKind::Content(_) => {}
+        }
+    }
+
+    for index in changed {
+        let mut file = &content_file;
+        file.seek(SeekFrom::Start(index.start))?;
+        writer.add_content(&mut file.take(index.end - index.start))?;
+    }
+
+    writer.finalize()?;
+
+    fs::remove_file(&content_path)?;
+    fs::remove_file(&buffer_path)?;
+
+    Ok(())
+}
+
 async fn stat_file(
     path: &Path,
     relative_path: &str,
@@ -222,7 +478,11 @@ fn enumerate_stone_files(dir: &Path) -> BoxFuture<Result<Vec<PathBuf>, Error>> {
 
             if meta.is_dir() {
                 paths.extend(enumerate_stone_files(&path).await?);
-            } else if meta.is_file() && path.extension().and_then(|s| s.to_str()) == Some("stone") {
+            } else if meta.is_file()
+                && path.extension().and_then(|s| s.to_str()) == Some("stone")
+                // Deltas aren't packages
+                && !path.to_string_lossy().ends_with(".delta.stone")
+            {
                 paths.push(path);
             }
         }
@@ -257,4 +517,153 @@ pub enum Error {
 
     #[error("client")]
     Client(#[from] client::Error),
+
+    #[error("signing key")]
+    SigningKey(#[from] trust::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, fs::File};
+
+    use moss::stone::{header::v1::FileType, read::PayloadKind};
+
+    use super::*;
+
+    const PACKAGE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../test/bash-completion-2.11-1-1-x86_64.stone"
+    );
+
+    fn read(path: &Path) -> (stone::Header, Vec<PayloadKind>) {
+        let mut reader = stone::read(File::open(path).unwrap()).unwrap();
+        let payloads = reader
+            .payloads()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        (reader.header, payloads)
+    }
+
+    fn digests(payloads: &[PayloadKind]) -> HashSet<u128> {
+        payloads
+            .iter()
+            .filter_map(PayloadKind::index)
+            .flat_map(|payload| &payload.body)
+            .map(|index| index.digest)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_write_index() {
+        let dir = std::env::temp_dir().join(format!("moss-write-index-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (_, package) = read(Path::new(PACKAGE));
+        let mut meta =
+            Meta::from_stone_payload(&package.iter().find_map(PayloadKind::meta).unwrap().body)
+                .unwrap();
+        meta.hash = Some("bbbb".into());
+        let map = BTreeMap::from([(meta.name.clone(), meta)]);
+        let delta = package::Delta {
+            from: "aaaa".to_string().into(),
+            to: "bbbb".to_string().into(),
+            uri: "deltas/bash-completion-aaaa-bbbb.delta.stone".into(),
+            hash: "cccc".into(),
+            download_size: 1,
+        };
+
+        let written = write_index(
+            &dir,
+            map.clone(),
+            vec![delta.clone()],
+            &ProgressBar::hidden(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, ["stone.index", "stone.deltas"]);
+
+        // Clients unaware of deltas must still be able to read the index
+        let (_, index) = read(&dir.join("stone.index"));
+        assert_eq!(index.len(), 1);
+        for payload in index.iter().filter_map(PayloadKind::meta) {
+            assert_eq!(
+                package::Delta::from_stone_payload(&payload.body).unwrap(),
+                None
+            );
+        }
+
+        let (_, deltas) = read(&dir.join("stone.deltas"));
+        assert_eq!(
+            deltas
+                .iter()
+                .filter_map(PayloadKind::meta)
+                .map(|payload| package::Delta::from_stone_payload(&payload.body).unwrap())
+                .collect::<Vec<_>>(),
+            [Some(delta)]
+        );
+
+        // Deltas of a previous run aren't left behind
+        let written = write_index(&dir, map, vec![], &ProgressBar::hidden())
+            .await
+            .unwrap();
+        assert_eq!(written, ["stone.index"]);
+        assert!(!dir.join("stone.deltas").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_delta() {
+        let dir = std::env::temp_dir().join(format!("moss-write-delta-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (_, package) = read(Path::new(PACKAGE));
+
+        // A package with the same metadata but none of the content
+        let empty = dir.join("empty.stone");
+        let mut writer =
+            stone::Writer::new(File::create(&empty).unwrap(), FileType::Binary).unwrap();
+        for payload in &package {
+            if let PayloadKind::Meta(meta) = payload {
+                writer.add_payload(meta.body.as_slice()).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+
+        for (from, expected) in [
+            (Path::new(PACKAGE), HashSet::new()),
+            (&empty, digests(&package)),
+        ] {
+            let delta = dir.join("out.delta.stone");
+            write_delta(from, Path::new(PACKAGE), &delta).unwrap();
+
+            let (header, payloads) = read(&delta);
+
+            assert!(
+                matches!(header, stone::Header::V1(header) if header.file_type == FileType::Delta)
+            );
+            assert_eq!(
+                payloads.iter().find_map(PayloadKind::meta).map(|p| &p.body),
+                package.iter().find_map(PayloadKind::meta).map(|p| &p.body)
+            );
+            assert_eq!(
+                payloads
+                    .iter()
+                    .find_map(PayloadKind::layout)
+                    .map(|p| &p.body),
+                package
+                    .iter()
+                    .find_map(PayloadKind::layout)
+                    .map(|p| &p.body)
+            );
+            // Only content missing from `from` is carried
+            assert_eq!(digests(&payloads), expected);
+            assert!(!dir.join("out.delta.content").exists());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
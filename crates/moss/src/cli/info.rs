@@ -8,14 +8,18 @@ use clap::{arg, ArgMatches, Command};
 use futures::StreamExt;
 use itertools::Itertools;
 use moss::{
-    client::{self, Client},
-    environment,
-    package::Flags,
+    client::{self, lock, Client},
+    db,
+    package::{self, Flags, Meta},
+    stone::payload::layout,
     Package, Provider,
 };
+use serde::Serialize;
 use thiserror::Error;
 use tui::Stylize;
 
+use super::{open_client, print_json, Format};
+
 const COLUMN_WIDTH: usize = 20;
 
 pub fn command() -> Command {
@@ -23,6 +27,7 @@ pub fn command() -> Command {
         .about("Query packages")
         .long_about("List detailed package information from all available sources")
         .arg(arg!(<NAME> ... "packages to query").value_parser(clap::value_parser!(String)))
+        .arg(arg!(-f --files "List files shipped by the packages"))
 }
 
 /// For all arguments, try to match a package
@@ -35,7 +40,11 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
         .collect::<Vec<_>>();
 
     let root = args.get_one::<PathBuf>("root").unwrap().clone();
-    let client = Client::new(environment::NAME, root).await?;
+    let client = open_client(args, root, lock::Kind::Shared).await?;
+
+    let json = Format::from_args(args) == Format::Json;
+    let show_files = args.get_flag("files");
+    let mut found = vec![];
 
     for pkg in pkgs {
         let lookup = Provider::from_name(&pkg).unwrap();
@@ -48,10 +57,31 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
             return Err(Error::NotFound(pkg));
         }
         for candidate in resolved {
-            print_package(&candidate);
+            // Layouts are only known once a package has been fetched
+            let files = if show_files {
+                Some(files(&client, &candidate.id).await?)
+            } else {
+                None
+            };
+
+            if json {
+                found.push(Info {
+                    files,
+                    ..Info::from(candidate)
+                });
+            } else {
+                print_package(&candidate);
+                if let Some(files) = files {
+                    print_files(&files);
+                }
+            }
         }
     }
 
+    if json {
+        print_json(&found)?;
+    }
+
     Ok(())
 }
 
@@ -108,6 +138,52 @@ fn print_package(pkg: &Package) {
     }
 }
 
+/// Sorted installed paths of the package, excluding directories
+async fn files(client: &Client, id: &package::Id) -> Result<Vec<String>, Error> {
+    Ok(client
+        .layout_db
+        .query(id)
+        .await?
+        .into_iter()
+        .filter(|layout| !matches!(layout.entry, layout::Entry::Directory(_)))
+        .map(|layout| db::layout::path(&layout.entry))
+        .sorted()
+        .collect())
+}
+
+fn print_files(files: &[String]) {
+    print_titled("Files");
+    if files.is_empty() {
+        println!("{}", "unavailable until fetched".dim());
+    } else {
+        print_paragraph(&files.join("\n"));
+    }
+}
+
+/// Serializable package information
+#[derive(Debug, Serialize)]
+struct Info {
+    id: package::Id,
+    #[serde(flatten)]
+    meta: Meta,
+    installed: bool,
+    explicit: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<Vec<String>>,
+}
+
+impl From<Package> for Info {
+    fn from(package: Package) -> Self {
+        Self {
+            id: package.id,
+            meta: package.meta,
+            installed: package.flags.contains(Flags::INSTALLED),
+            explicit: package.flags.contains(Flags::EXPLICIT),
+            files: None,
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("No such package {0}")]
@@ -115,4 +191,10 @@ pub enum Error {
 
     #[error("client")]
     Client(#[from] client::Error),
+
+    #[error("layout db")]
+    LayoutDB(#[from] db::layout::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
@@ -3,14 +3,18 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use clap::{arg, ArgMatches, Command};
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
+use moss::package::{self, MissingMetaFieldError};
 use moss::stone;
 use moss::stone::payload::layout;
 use moss::stone::payload::meta;
 use moss::stone::read::PayloadKind;
+use serde::Serialize;
 use std::path::PathBuf;
 use thiserror::Error;
 
+use super::{print_json, Format};
+
 const COLUMN_WIDTH: usize = 20;
 
 pub fn command() -> Command {
@@ -31,7 +35,114 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
         .cloned()
         .collect::<Vec<_>>();
 
-    inspect(paths).await
+    match Format::from_args(args) {
+        Format::Text => inspect(paths).await,
+        Format::Json => inspect_json(paths).await,
+    }
+}
+
+/// Serializable contents of a stone file
+#[derive(Debug, Serialize)]
+struct Inspection {
+    path: PathBuf,
+    version: String,
+    packages: Vec<package::Meta>,
+    deltas: Vec<package::Delta>,
+    layout: Vec<Layout>,
+}
+
+#[derive(Debug, Serialize)]
+struct Layout {
+    path: String,
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+}
+
+impl From<layout::Layout> for Layout {
+    fn from(layout: layout::Layout) -> Self {
+        let kind = kind(&layout.entry);
+        let (target, digest, source) = match layout.entry {
+            layout::Entry::Regular(hash, target) => (target, Some(format!("{hash:032x}")), None),
+            layout::Entry::Symlink(source, target) => (target, None, Some(source)),
+            layout::Entry::Directory(target)
+            | layout::Entry::CharacterDevice(_, target)
+            | layout::Entry::BlockDevice(_, target)
+            | layout::Entry::Fifo(target)
+            | layout::Entry::Socket(target) => (target, None, None),
+        };
+
+        Self {
+            path: format!("/usr/{target}"),
+            kind,
+            digest,
+            source,
+            mode: layout.mode,
+            uid: layout.uid,
+            gid: layout.gid,
+        }
+    }
+}
+
+/// Inspect the given .stone files and print them as JSON
+async fn inspect_json(paths: Vec<PathBuf>) -> Result<(), Error> {
+    let mut inspections = vec![];
+
+    for path in paths {
+        let (header, payloads) = stone::stream_payloads(&path).await?;
+        let payloads = payloads.try_collect::<Vec<_>>().await?;
+
+        let mut inspection = Inspection {
+            version: format!("{:?}", header.version()),
+            path,
+            packages: vec![],
+            deltas: vec![],
+            layout: vec![],
+        };
+
+        for payload in payloads {
+            match payload {
+                PayloadKind::Meta(meta) => {
+                    if let Some(delta) = package::Delta::from_stone_payload(&meta.body)? {
+                        inspection.deltas.push(delta);
+                    } else {
+                        inspection
+                            .packages
+                            .push(package::Meta::from_stone_payload(&meta.body)?);
+                    }
+                }
+                PayloadKind::Layout(layouts) => {
+                    inspection
+                        .layout
+                        .extend(layouts.body.into_iter().map(Layout::from));
+                }
+                _ => {}
+            }
+        }
+
+        inspections.push(inspection);
+    }
+
+    print_json(&inspections)?;
+
+    Ok(())
+}
+
+fn kind(entry: &layout::Entry) -> &'static str {
+    match entry {
+        layout::Entry::Regular(..) => "regular",
+        layout::Entry::Symlink(..) => "symlink",
+        layout::Entry::Directory(_) => "directory",
+        layout::Entry::CharacterDevice(..) => "character-device",
+        layout::Entry::BlockDevice(..) => "block-device",
+        layout::Entry::Fifo(_) => "fifo",
+        layout::Entry::Socket(_) => "socket",
+    }
 }
 
 async fn inspect(paths: Vec<PathBuf>) -> Result<(), Error> {
@@ -118,7 +229,14 @@ async fn inspect(paths: Vec<PathBuf>) -> Result<(), Error> {
                         layout::Entry::Symlink(source, target) => {
                             println!("    - /usr/{} -> {} [Symlink]", target, source)
                         }
-                        _ => unreachable!(),
+                        layout::Entry::CharacterDevice(device, target) => {
+                            println!("    - /usr/{} [CharacterDevice] {}", target, device)
+                        }
+                        layout::Entry::BlockDevice(device, target) => {
+                            println!("    - /usr/{} [BlockDevice] {}", target, device)
+                        }
+                        layout::Entry::Fifo(target) => println!("    - /usr/{} [Fifo]", target),
+                        layout::Entry::Socket(target) => println!("    - /usr/{} [Socket]", target),
                     };
                 }
             }
@@ -135,4 +253,10 @@ pub enum Error {
 
     #[error("stone format")]
     Format(#[from] stone::read::Error),
+
+    #[error(transparent)]
+    MissingMetaField(#[from] MissingMetaFieldError),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
@@ -5,15 +5,20 @@
 use std::path::{Path, PathBuf};
 
 use clap::{arg, value_parser, ArgMatches, Command};
-use moss::{client::Client, environment};
+use moss::client::{self, install};
+use thiserror::Error;
 
-pub use moss::client::install::Error;
+use super::{lock_kind, open_client, print_plan};
 
 pub fn command() -> Command {
     Command::new("install")
         .about("Install packages")
         .long_about("Install the requested software to the local system")
-        .arg(arg!(<NAME> ... "packages to install").value_parser(value_parser!(String)))
+        .arg(
+            arg!(<NAME> ... "packages to install")
+                .long_help("Names of packages to install, or paths to local .stone files")
+                .value_parser(value_parser!(String)),
+        )
         .arg(
             arg!(--to <blit_target> "Blit this install to the provided directory instead of the root")
                 .long_help(
@@ -23,6 +28,7 @@ pub fn command() -> Command {
                 )
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(arg!(--"dry-run" "Print the resulting changes without installing anything"))
 }
 
 /// Handle execution of `moss install`
@@ -34,14 +40,33 @@ pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
         .map(String::as_str)
         .collect::<Vec<_>>();
     let yes = *args.get_one::<bool>("yes").unwrap();
+    let dry_run = args.get_flag("dry-run");
 
     // Grab a client for the root
-    let mut client = Client::new(environment::NAME, root).await?;
+    let mut client = open_client(args, root, lock_kind(dry_run)).await?;
 
     // Make ephemeral if a blit target was provided
     if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
         client = client.ephemeral(blit_target)?;
     }
 
-    client.install(&pkgs, yes).await
+    if dry_run {
+        let plan = client.plan_install(&pkgs).await?;
+        print_plan(args, &plan)?;
+        return Ok(());
+    }
+
+    Ok(client.install(&pkgs, yes).await?)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error(transparent)]
+    Install(#[from] install::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
@@ -7,15 +7,17 @@ use std::path::PathBuf;
 use clap::{arg, ArgMatches, Command};
 use futures::StreamExt;
 use itertools::Itertools;
+use serde::Serialize;
 use thiserror::Error;
 
 use moss::{
-    client::{self, Client},
-    environment,
-    package::Flags,
+    client::{self, lock},
+    package::{self, Flags, Meta},
 };
 use tui::Stylize;
 
+use super::{open_client, print_json, Format as OutputFormat};
+
 pub fn command() -> Command {
     Command::new("list")
         .about("List packages")
@@ -64,7 +66,7 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
     };
 
     // Grab a client for the target, enumerate packages
-    let client = Client::new(environment::NAME, root).await?;
+    let client = open_client(args, root, lock::Kind::Shared).await?;
     let pkgs = client.registry.list(filter_flags).collect::<Vec<_>>().await;
 
     let sync_available = if sync.is_some() {
@@ -106,10 +108,12 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
             Format {
                 name: p.meta.name.to_string(),
                 revision: Revision {
-                    version: p.meta.version_identifier,
+                    version: p.meta.version_identifier.clone(),
                     release: p.meta.source_release.to_string(),
                 },
-                summary: p.meta.summary,
+                summary: p.meta.summary.clone(),
+                id: p.id,
+                meta: p.meta,
                 explicit: if filter_flags == Flags::INSTALLED {
                     p.flags.contains(Flags::EXPLICIT)
                 } else {
@@ -132,6 +136,11 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
     set.sort_by_key(|s| s.name.clone());
     set.dedup_by_key(|s| s.name.clone());
 
+    if OutputFormat::from_args(args) == OutputFormat::Json {
+        print_json(&set)?;
+        return Ok(());
+    }
+
     // Grab maximum length
     let max_length = set.iter().map(Format::size).max().unwrap_or_default();
 
@@ -169,11 +178,17 @@ pub async fn handle(args: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct Format {
+    #[serde(skip)]
     name: String,
+    #[serde(skip)]
     summary: String,
+    #[serde(skip)]
     revision: Revision,
+    id: package::Id,
+    #[serde(flatten)]
+    meta: Meta,
     explicit: bool,
     sync: Option<Revision>,
 }
@@ -186,7 +201,7 @@ impl Format {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct Revision {
     version: String,
     release: String,
@@ -204,4 +219,6 @@ pub enum Error {
     NoneFound,
     #[error("client")]
     Client(#[from] client::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
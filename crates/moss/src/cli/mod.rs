@@ -4,19 +4,31 @@
 
 use std::path::PathBuf;
 
-use clap::{Arg, ArgAction, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueEnum};
+use moss::{
+    client::{self, lock, plan::Plan},
+    environment, Client,
+};
+use serde::Serialize;
 use thiserror::Error;
 
+mod apply;
+mod autoremove;
 mod extract;
+mod hold;
 mod index;
 mod info;
 mod inspect;
 mod install;
 mod list;
+mod pin;
 mod remove;
 mod repo;
+mod search;
+mod search_file;
 mod state;
 mod sync;
+mod verify;
 mod version;
 
 /// Generate the CLI command structure
@@ -39,6 +51,29 @@ fn command() -> Command {
                 .default_value("/")
                 .value_parser(clap::value_parser!(PathBuf)),
         )
+        .arg(
+            Arg::new("offline")
+                .long("offline")
+                .global(true)
+                .help("Never access the network, only use cached indexes and packages")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-wait")
+                .long("no-wait")
+                .global(true)
+                .help("Fail instead of waiting if another moss process is using the root")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .global(true)
+                .help("Output format")
+                .action(ArgAction::Set)
+                .default_value("text")
+                .value_parser(clap::value_parser!(Format)),
+        )
         .arg(
             Arg::new("yes")
                 .short('y')
@@ -48,19 +83,88 @@ fn command() -> Command {
                 .action(ArgAction::SetTrue),
         )
         .arg_required_else_help(true)
+        .subcommand(apply::command())
+        .subcommand(autoremove::command())
         .subcommand(extract::command())
+        .subcommand(hold::command())
         .subcommand(index::command())
         .subcommand(info::command())
         .subcommand(inspect::command())
         .subcommand(install::command())
         .subcommand(list::command())
+        .subcommand(pin::command())
         .subcommand(remove::command())
         .subcommand(repo::command())
+        .subcommand(search::command())
+        .subcommand(search_file::command())
         .subcommand(state::command())
         .subcommand(sync::command())
+        .subcommand(verify::command())
         .subcommand(version::command())
 }
 
+/// Output format of commands which print data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Human readable output
+    Text,
+    /// Machine readable JSON
+    Json,
+}
+
+impl Format {
+    /// Returns the format requested by the global `--format` argument
+    fn from_args(args: &ArgMatches) -> Self {
+        args.get_one::<Format>("format")
+            .copied()
+            .unwrap_or(Format::Text)
+    }
+}
+
+/// Print `value` to stdout as JSON
+fn print_json(value: &impl Serialize) -> Result<(), serde_json::Error> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Open a [`Client`] for `root` with a `lock` of the installation,
+/// honouring the global `--offline` and `--no-wait` flags
+async fn open_client(
+    args: &ArgMatches,
+    root: impl Into<PathBuf>,
+    lock: lock::Kind,
+) -> Result<Client, client::Error> {
+    Client::with_options(environment::NAME, root, client_options(args, lock)).await
+}
+
+/// [`client::Options`] for a `lock` of the installation, honouring the global flags
+fn client_options(args: &ArgMatches, lock: lock::Kind) -> client::Options {
+    client::Options {
+        offline: args.get_flag("offline"),
+        lock,
+        no_wait: args.get_flag("no-wait"),
+        ..Default::default()
+    }
+}
+
+/// Dry runs only need to read the installation
+fn lock_kind(dry_run: bool) -> lock::Kind {
+    if dry_run {
+        lock::Kind::Shared
+    } else {
+        lock::Kind::Exclusive
+    }
+}
+
+/// Print the `plan` of a dry run in the requested format
+fn print_plan(args: &ArgMatches, plan: &Plan) -> Result<(), serde_json::Error> {
+    match Format::from_args(args) {
+        Format::Text => plan.print(),
+        Format::Json => print_json(plan)?,
+    }
+    Ok(())
+}
+
 /// Process all CLI arguments
 pub async fn process() -> Result<(), Error> {
     let matches = command().get_matches();
@@ -72,16 +176,27 @@ pub async fn process() -> Result<(), Error> {
     let root = matches.get_one::<PathBuf>("root").unwrap();
 
     match command().get_matches().subcommand() {
+        Some(("apply", args)) => apply::handle(args, root).await.map_err(Error::Apply),
+        Some(("autoremove", args)) => autoremove::handle(args, root)
+            .await
+            .map_err(Error::Autoremove),
         Some(("extract", args)) => extract::handle(args).await.map_err(Error::Extract),
+        Some(("hold", args)) => hold::handle(args, root).await.map_err(Error::Hold),
         Some(("index", args)) => index::handle(args).await.map_err(Error::Index),
         Some(("info", args)) => info::handle(args).await.map_err(Error::Info),
         Some(("inspect", args)) => inspect::handle(args).await.map_err(Error::Inspect),
         Some(("install", args)) => install::handle(args, root).await.map_err(Error::Install),
         Some(("list", args)) => list::handle(args).await.map_err(Error::List),
+        Some(("pin", args)) => pin::handle(args, root).await.map_err(Error::Pin),
         Some(("remove", args)) => remove::handle(args, root).await.map_err(Error::Remove),
         Some(("repo", args)) => repo::handle(args, root).await.map_err(Error::Repo),
+        Some(("search", args)) => search::handle(args, root).await.map_err(Error::Search),
+        Some(("search-file", args)) => search_file::handle(args, root)
+            .await
+            .map_err(Error::SearchFile),
         Some(("state", args)) => state::handle(args, root).await.map_err(Error::State),
         Some(("sync", args)) => sync::handle(args, root).await.map_err(Error::Sync),
+        Some(("verify", args)) => verify::handle(args, root).await.map_err(Error::Verify),
         Some(("version", _)) => {
             version::print();
             Ok(())
@@ -96,6 +211,15 @@ pub async fn process() -> Result<(), Error> {
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("apply")]
+    Apply(#[from] apply::Error),
+
+    #[error("autoremove")]
+    Autoremove(#[from] autoremove::Error),
+
+    #[error("hold")]
+    Hold(#[from] hold::Error),
+
     #[error("index")]
     Index(#[from] index::Error),
 
@@ -114,15 +238,27 @@ pub enum Error {
     #[error("extract")]
     Extract(#[from] extract::Error),
 
+    #[error("pin")]
+    Pin(#[from] pin::Error),
+
     #[error("remove")]
     Remove(#[from] remove::Error),
 
     #[error("repo")]
     Repo(#[from] repo::Error),
 
+    #[error("search")]
+    Search(#[from] search::Error),
+
+    #[error("search-file")]
+    SearchFile(#[from] search_file::Error),
+
     #[error("state")]
     State(#[from] state::Error),
 
     #[error("sync")]
     Sync(#[from] sync::Error),
+
+    #[error("verify")]
+    Verify(#[from] verify::Error),
 }
@@ -0,0 +1,159 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{io, path::Path};
+
+use clap::{arg, ArgMatches, Command};
+use moss::{
+    client::lock::{self, Lock},
+    package,
+    registry::pin::{Rule, Rules},
+    repository, Installation,
+};
+use thiserror::Error;
+use tui::Stylize;
+
+use super::{print_json, Format};
+
+pub fn command() -> Command {
+    Command::new("pin")
+        .about("Pin packages to a repository or version")
+        .long_about(
+            "Only select candidates of the package from the given repository and/or version. \
+             Without a name, list all pinned packages",
+        )
+        .arg(arg!([NAME] "package to pin").value_parser(clap::value_parser!(String)))
+        .arg(
+            arg!(-r --repository <ID> "Only select candidates from this repository")
+                .value_parser(clap::value_parser!(String)),
+        )
+        .arg(
+            arg!(-V --"pin-version" <VERSION> "Only select this version, i.e. 1.2.3, 1.2.3-4 or 1.2.3-4-1")
+                .value_parser(clap::value_parser!(String)),
+        )
+        .arg(arg!(--remove "Remove the pin from the package").requires("NAME"))
+}
+
+/// Handle execution of `moss pin`
+pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let config = config::Manager::system(root, "moss");
+
+    let Some(name) = args.get_one::<String>("NAME") else {
+        let rules = config.try_load::<Rules>().await?.unwrap_or_default();
+        let pinned = rules
+            .iter()
+            .filter(|(_, rule)| rule.repository.is_some() || rule.version.is_some());
+
+        if Format::from_args(args) == Format::Json {
+            print_json(&Rules::with(pinned.map(|(n, r)| (n.clone(), r.clone()))))?;
+        } else {
+            for (name, rule) in pinned {
+                println!("{} {}", name.to_string().bold(), describe(rule));
+            }
+        }
+
+        return Ok(());
+    };
+    let name = package::Name::from(name.clone());
+
+    // Rules are read by every resolving moss process
+    let _lock = Lock::acquire(
+        &Installation::open(root),
+        lock::Kind::Exclusive,
+        !args.get_flag("no-wait"),
+    )
+    .await?;
+
+    let mut rule = load(&config, &name).await?;
+
+    if args.get_flag("remove") {
+        rule.repository = None;
+        rule.version = None;
+        save(&config, &name, rule).await?;
+
+        println!("{} {name}", "Unpinned".green());
+        return Ok(());
+    }
+
+    let repository = args
+        .get_one::<String>("repository")
+        .map(|id| repository::Id::new(id.clone()));
+    let version = args.get_one::<String>("pin-version").cloned();
+
+    if repository.is_none() && version.is_none() {
+        return Err(Error::MissingConstraint);
+    }
+
+    rule.repository = repository.or(rule.repository);
+    rule.version = version.or(rule.version);
+
+    let description = describe(&rule);
+    save(&config, &name, rule).await?;
+
+    println!("{} {name} {description}", "Pinned".green());
+
+    Ok(())
+}
+
+/// Human readable description of the pinned constraints
+fn describe(rule: &Rule) -> String {
+    match (&rule.repository, &rule.version) {
+        (Some(repository), Some(version)) => format!("to {version} from {repository}"),
+        (Some(repository), None) => format!("to {repository}"),
+        (None, Some(version)) => format!("to {version}"),
+        (None, None) => String::new(),
+    }
+}
+
+/// Load the rule of the package `name`
+pub(super) async fn load(config: &config::Manager, name: &package::Name) -> Result<Rule, Error> {
+    Ok(config
+        .try_load::<Rules>()
+        .await?
+        .and_then(|rules| rules.get(name).cloned())
+        .unwrap_or_default())
+}
+
+/// Save the `rule` of package `name` to its own config file, deleting
+/// the file once the rule no longer constrains anything
+pub(super) async fn save(
+    config: &config::Manager,
+    name: &package::Name,
+    rule: Rule,
+) -> Result<(), Error> {
+    if rule.is_empty() {
+        return match config.delete::<Rules>(name).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::DeleteConfig(e)),
+        };
+    }
+
+    config
+        .save(name, &Rules::with([(name.clone(), rule)]))
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("A repository or version to pin to is required")]
+    MissingConstraint,
+
+    #[error("load config")]
+    LoadConfig(#[from] config::LoadError),
+
+    #[error("save config")]
+    SaveConfig(#[from] config::SaveError),
+
+    #[error("delete config")]
+    DeleteConfig(#[source] io::Error),
+
+    #[error("lock")]
+    Lock(#[from] lock::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
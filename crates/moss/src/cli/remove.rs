@@ -8,9 +8,8 @@ use clap::{arg, ArgMatches, Command};
 use futures::StreamExt;
 use itertools::{Either, Itertools};
 use moss::{
-    client::{self, Client},
-    environment,
-    package::Flags,
+    client::{self, plan::Plan, Client},
+    package::{self, Flags},
     registry::transaction,
     state::Selection,
     Provider,
@@ -18,11 +17,17 @@ use moss::{
 use thiserror::Error;
 use tui::{pretty::print_to_columns, Stylize};
 
+use super::{lock_kind, open_client, print_plan};
+
 pub fn command() -> Command {
     Command::new("remove")
         .about("Remove packages")
-        .long_about("Remove packages by name")
+        .long_about(
+            "Remove packages by name, along with any dependencies no longer \
+             needed by an explicitly installed package",
+        )
         .arg(arg!(<NAME> ... "packages to install").value_parser(clap::value_parser!(String)))
+        .arg(arg!(--"dry-run" "Print the resulting changes without removing anything"))
 }
 
 /// Handle execution of `moss remove`
@@ -33,9 +38,10 @@ pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
         .flatten()
         .map(|name| Provider::from_name(name).unwrap())
         .collect::<Vec<_>>();
+    let dry_run = args.get_flag("dry-run");
 
     // Grab a client for the target, enumerate packages
-    let client = Client::new(environment::NAME, root).await?;
+    let client = open_client(args, root, lock_kind(dry_run)).await?;
 
     let installed = client
         .registry
@@ -72,14 +78,74 @@ pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
     // Remove all pkgs for removal
     transaction.remove(for_removal).await?;
 
-    // Finalized tx has all reverse deps removed
+    let previous_selections = previous_selections(&client).await?;
+
+    // Drop dependencies no longer needed by any explicit package. Without
+    // any explicit selections we can't tell what's still needed
+    let explicit = explicit(&previous_selections);
+    if !explicit.is_empty() {
+        transaction.remove_orphans(&explicit);
+    }
+
+    // Finalized tx has all reverse deps & orphans removed
     let finalized = transaction.finalize().cloned().collect::<HashSet<_>>();
 
+    if dry_run {
+        let next = installed
+            .iter()
+            .filter(|p| finalized.contains(&p.id))
+            .cloned()
+            .collect::<Vec<_>>();
+        print_plan(args, &Plan::new(&installed, &next))?;
+        return Ok(());
+    }
+
+    apply(
+        &client,
+        &installed_ids,
+        finalized,
+        previous_selections,
+        "Remove",
+    )
+    .await
+}
+
+/// Selections of the active state
+pub(super) async fn previous_selections(client: &Client) -> Result<Vec<Selection>, Error> {
+    Ok(match client.installation.active_state {
+        Some(id) => client.state_db.get(&id).await?.selections,
+        None => vec![],
+    })
+}
+
+/// Ids of all explicitly selected packages
+pub(super) fn explicit(selections: &[Selection]) -> Vec<package::Id> {
+    selections
+        .iter()
+        .filter(|s| s.explicit)
+        .map(|s| s.package.clone())
+        .collect()
+}
+
+/// Apply a new state containing only the `finalized` packages, removing
+/// the remaining `installed` packages
+pub(super) async fn apply(
+    client: &Client,
+    installed_ids: &HashSet<package::Id>,
+    finalized: HashSet<package::Id>,
+    previous_selections: Vec<Selection>,
+    summary: &str,
+) -> Result<(), Error> {
     // Resolve all removed packages, where removed is (installed - finalized)
     let removed = client
         .resolve_packages(installed_ids.difference(&finalized))
         .await?;
 
+    if removed.is_empty() {
+        println!("No packages to remove");
+        return Ok(());
+    }
+
     println!("The following package(s) will be removed:");
     println!();
     print_to_columns(&removed);
@@ -96,36 +162,29 @@ pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
 
     // Map finalized state to a [`Selection`] by referencing
     // it's value from the previous state
-    let new_state_pkgs = {
-        let previous_selections = match client.installation.active_state {
-            Some(id) => client.state_db.get(&id).await?.selections,
-            None => vec![],
-        };
-
-        finalized
-            .into_iter()
-            .map(|id| {
-                previous_selections
-                    .iter()
-                    .find(|s| s.package == id)
-                    .cloned()
-                    // Should be unreachable since new state from removal
-                    // is always a subset of the previous state
-                    .unwrap_or_else(|| {
-                        eprintln!("Unreachable: previous selection not found during removal for package {id:?}, marking as not explicit");
-
-                        Selection {
-                            package: id,
-                            explicit: false,
-                            reason: None,
-                        }
-                    })
-            })
-            .collect::<Vec<_>>()
-    };
+    let new_state_pkgs = finalized
+        .into_iter()
+        .map(|id| {
+            previous_selections
+                .iter()
+                .find(|s| s.package == id)
+                .cloned()
+                // Should be unreachable since new state from removal
+                // is always a subset of the previous state
+                .unwrap_or_else(|| {
+                    eprintln!("Unreachable: previous selection not found during removal for package {id:?}, marking as not explicit");
+
+                    Selection {
+                        package: id,
+                        explicit: false,
+                        reason: None,
+                    }
+                })
+        })
+        .collect::<Vec<_>>();
 
     // Apply state
-    client.apply_state(&new_state_pkgs, "Remove").await?;
+    client.apply_state(&new_state_pkgs, summary).await?;
 
     Ok(())
 }
@@ -144,6 +203,9 @@ pub enum Error {
     #[error("state db")]
     StateDB(#[from] moss::db::state::Error),
 
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+
     #[error("io")]
     Io(#[from] std::io::Error),
 }
@@ -7,18 +7,22 @@ use std::{path::Path, process};
 use clap::{arg, Arg, ArgAction, ArgMatches, Command};
 use itertools::Itertools;
 use moss::{
+    client::lock::{self, Lock},
     repository::{self, Priority},
     Installation, Repository,
 };
+use serde::Serialize;
 use thiserror::Error;
 use url::Url;
 
+use super::{print_json, Format};
+
 /// Control flow for the subcommands
 enum Action<'a> {
-    // Root
-    List(&'a Path),
-    // Root, Id, Url, Comment
-    Add(&'a Path, String, Url, String, Priority),
+    // Root, Format
+    List(&'a Path, Format),
+    // Root, Id, Url, Comment, Priority, Mirrors
+    Add(&'a Path, String, Url, String, Priority, Vec<Url>),
     // Root, Id
     Remove(&'a Path, String),
     // Root, Id
@@ -50,6 +54,14 @@ pub fn command() -> Command {
                         .action(ArgAction::Set)
                         .default_value("0")
                         .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("mirror")
+                        .short('m')
+                        .long("mirror")
+                        .help("Base URI of a mirror, may be repeated")
+                        .action(ArgAction::Append)
+                        .value_parser(clap::value_parser!(Url)),
                 ),
         )
         .subcommand(
@@ -73,6 +85,7 @@ pub fn command() -> Command {
 /// Handle subcommands to `repo`
 pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
     let config = config::Manager::system(root, "moss");
+    let offline = args.get_flag("offline");
 
     let handler = match args.subcommand() {
         Some(("add", cmd_args)) => Action::Add(
@@ -81,8 +94,14 @@ pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
             cmd_args.get_one::<Url>("URI").cloned().unwrap(),
             cmd_args.get_one::<String>("comment").cloned().unwrap(),
             Priority::new(*cmd_args.get_one::<u64>("priority").unwrap()),
+            cmd_args
+                .get_many::<Url>("mirror")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect(),
         ),
-        Some(("list", _)) => Action::List(root),
+        Some(("list", cmd_args)) => Action::List(root, Format::from_args(cmd_args)),
         Some(("remove", cmd_args)) => {
             Action::Remove(root, cmd_args.get_one::<String>("NAME").cloned().unwrap())
         }
@@ -92,13 +111,28 @@ pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
         _ => unreachable!(),
     };
 
+    // Only listing is safe alongside other moss processes
+    let kind = if matches!(handler, Action::List(..)) {
+        lock::Kind::Shared
+    } else {
+        lock::Kind::Exclusive
+    };
+    let _lock = Lock::acquire(&Installation::open(root), kind, !args.get_flag("no-wait")).await?;
+
     // dispatch to runtime handler function
     match handler {
-        Action::List(root) => list(root, config).await,
-        Action::Add(root, name, uri, comment, priority) => {
-            add(root, config, name, uri, comment, priority).await
+        Action::List(root, format) => list(root, config, format).await,
+        Action::Add(root, name, uri, comment, priority, mirrors) => {
+            let repository = Repository {
+                description: comment,
+                uri,
+                priority,
+                mirrors,
+            };
+            add(root, config, name, repository, offline).await
         }
         Action::Remove(root, name) => remove(root, config, name).await,
+        Action::Update(_, _) if offline => Err(Error::Offline),
         Action::Update(root, name) => update(root, config, name).await,
     }
 }
@@ -108,9 +142,8 @@ async fn add(
     root: &Path,
     config: config::Manager,
     name: String,
-    uri: Url,
-    comment: String,
-    priority: Priority,
+    repository: Repository,
+    offline: bool,
 ) -> Result<(), Error> {
     let installation = Installation::open(root);
 
@@ -118,16 +151,13 @@ async fn add(
 
     let id = repository::Id::new(name);
 
-    manager
-        .add_repository(
-            id.clone(),
-            Repository {
-                description: comment,
-                uri,
-                priority,
-            },
-        )
-        .await?;
+    manager.add_repository(id.clone(), repository).await?;
+
+    // The index will be fetched the next time we're online
+    if offline {
+        println!("{id} added, run `moss repo update` once online to fetch its index");
+        return Ok(());
+    }
 
     manager.refresh_all().await?;
 
@@ -137,11 +167,29 @@ async fn add(
 }
 
 /// List the repositories and pretty print them
-async fn list(root: &Path, config: config::Manager) -> Result<(), Error> {
+async fn list(root: &Path, config: config::Manager, format: Format) -> Result<(), Error> {
     let installation = Installation::open(root);
     let manager = repository::Manager::system(config, installation).await?;
 
     let configured_repos = manager.list();
+
+    if format == Format::Json {
+        #[derive(Serialize)]
+        struct Entry<'a> {
+            id: &'a repository::Id,
+            #[serde(flatten)]
+            repository: &'a Repository,
+        }
+
+        let entries = configured_repos
+            .sorted_by(|(_, a), (_, b)| a.priority.cmp(&b.priority).reverse())
+            .map(|(id, repository)| Entry { id, repository })
+            .collect::<Vec<_>>();
+
+        print_json(&entries)?;
+        return Ok(());
+    }
+
     if configured_repos.len() == 0 {
         println!("No repositories have been configured yet");
         return Ok(());
@@ -195,6 +243,15 @@ async fn remove(root: &Path, config: config::Manager, repo: String) -> Result<()
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("Can't update repositories when offline")]
+    Offline,
+
     #[error("repo manager")]
     RepositoryManager(#[from] repository::manager::Error),
+
+    #[error("lock")]
+    Lock(#[from] lock::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
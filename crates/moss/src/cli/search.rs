@@ -0,0 +1,157 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{collections::BTreeSet, path::Path};
+
+use clap::{arg, ArgMatches, Command};
+use futures::StreamExt;
+use itertools::Itertools;
+use moss::{
+    client::{self, lock},
+    package::{self, Flags, Meta},
+};
+use serde::Serialize;
+use thiserror::Error;
+use tui::Stylize;
+
+use super::{open_client, print_json, Format};
+
+pub fn command() -> Command {
+    Command::new("search")
+        .visible_alias("sr")
+        .about("Search packages")
+        .long_about("Search packages by name, summary, description or provider")
+        .arg(arg!(<TERM> "text to search for").value_parser(clap::value_parser!(String)))
+        .arg(arg!(-i --installed "Only search installed packages"))
+}
+
+/// Search all packages, best matches first
+pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let term = args.get_one::<String>("TERM").unwrap();
+    let flags = if args.get_flag("installed") {
+        Flags::INSTALLED
+    } else {
+        Flags::NONE
+    };
+
+    let client = open_client(args, root, lock::Kind::Shared).await?;
+
+    let installed = client
+        .registry
+        .list_installed(Flags::NONE)
+        .map(|package| package.meta.name)
+        .collect::<BTreeSet<_>>()
+        .await;
+
+    // Thanks to priorities, the first of each name is the winning candidate
+    let results = client
+        .registry
+        .search(term, flags)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .unique_by(|package| package.meta.name.to_string())
+        .sorted_by(|a, b| {
+            rank(&a.meta, term)
+                .cmp(&rank(&b.meta, term))
+                .then_with(|| a.meta.name.cmp(&b.meta.name))
+        })
+        .collect_vec();
+
+    if results.is_empty() {
+        return Err(Error::NoneFound(term.clone()));
+    }
+
+    if Format::from_args(args) == Format::Json {
+        #[derive(Serialize)]
+        struct Entry {
+            id: package::Id,
+            #[serde(flatten)]
+            meta: Meta,
+            installed: bool,
+        }
+
+        let entries = results
+            .into_iter()
+            .map(|package| Entry {
+                installed: installed.contains(&package.meta.name),
+                id: package.id,
+                meta: package.meta,
+            })
+            .collect::<Vec<_>>();
+
+        print_json(&entries)?;
+        return Ok(());
+    }
+
+    let width = |meta: &Meta| {
+        meta.name.as_ref().len()
+            + meta.version_identifier.len()
+            + meta.source_release.to_string().len()
+    };
+    let max_width = results
+        .iter()
+        .map(|package| width(&package.meta))
+        .max()
+        .unwrap_or_default();
+
+    for package in results {
+        let meta = &package.meta;
+        let is_installed = installed.contains(&meta.name);
+
+        let name = if is_installed {
+            meta.name.to_string().bold()
+        } else {
+            meta.name.to_string().reset()
+        };
+        let padding = max_width - width(meta) + 2;
+
+        print!(
+            "{name} {:padding$} {}-{}",
+            " ",
+            meta.version_identifier.clone().magenta(),
+            meta.source_release.to_string().dim(),
+        );
+        if is_installed {
+            print!(" {}", "[installed]".green());
+        }
+        println!(" - {}", meta.summary);
+    }
+
+    Ok(())
+}
+
+/// How well `meta` matches `term`, lower is better
+fn rank(meta: &Meta, term: &str) -> u8 {
+    let term = term.to_lowercase();
+    let name = meta.name.as_ref().to_lowercase();
+
+    if name == term {
+        0
+    } else if name.starts_with(&term) {
+        1
+    } else if name.contains(&term) {
+        2
+    } else if meta
+        .providers
+        .iter()
+        .any(|provider| provider.name.to_lowercase().contains(&term))
+    {
+        3
+    } else if meta.summary.to_lowercase().contains(&term) {
+        4
+    } else {
+        5
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No packages found matching {0:?}")]
+    NoneFound(String),
+    #[error("client")]
+    Client(#[from] client::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{collections::HashMap, path::Path};
+
+use clap::{arg, ArgMatches, Command};
+use futures::StreamExt;
+use itertools::Itertools;
+use moss::{
+    client::{self, lock},
+    db,
+    package::{self, Flags},
+    stone::payload::layout,
+};
+use serde::Serialize;
+use thiserror::Error;
+use tui::Stylize;
+
+use super::{open_client, print_json, Format};
+
+pub fn command() -> Command {
+    Command::new("search-file")
+        .visible_alias("sf")
+        .about("Search files of installed packages")
+        .long_about(
+            "Find the installed packages owning paths which match a path or glob. \
+             Patterns without a leading / are matched against file names only",
+        )
+        .arg(arg!(<PATTERN> "path or glob to search for").value_parser(clap::value_parser!(String)))
+}
+
+/// Handle execution of `moss search-file`
+pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let input = args.get_one::<String>("PATTERN").unwrap();
+    let is_absolute = input.starts_with('/');
+    let normalized = normalize(input);
+    let pattern = normalized.parse::<fnmatch::Pattern>()?;
+
+    let client = open_client(args, root, lock::Kind::Shared).await?;
+
+    let installed = client
+        .registry
+        .list_installed(Flags::NONE)
+        .map(|package| (package.id, package.meta.name))
+        .collect::<HashMap<_, _>>()
+        .await;
+
+    // Absolute patterns can narrow the search down to their literal prefix
+    let layouts = if is_absolute {
+        client
+            .layout_db
+            .query_path_prefix(literal_prefix(&normalized))
+            .await?
+    } else {
+        client.layout_db.all().await?
+    };
+
+    let found = layouts
+        .into_iter()
+        .filter_map(|(id, layout)| {
+            let name = installed.get(&id)?;
+            let path = db::layout::path(&layout.entry);
+
+            matches(&pattern, is_absolute, &path).then(|| Owner {
+                path,
+                name: name.to_string(),
+                package: id,
+                symlink: match layout.entry {
+                    layout::Entry::Symlink(source, _) => Some(source),
+                    _ => None,
+                },
+            })
+        })
+        .sorted_by(|a, b| a.path.cmp(&b.path).then_with(|| a.name.cmp(&b.name)))
+        .collect::<Vec<_>>();
+
+    if found.is_empty() {
+        return Err(Error::NoneFound(input.clone()));
+    }
+
+    if Format::from_args(args) == Format::Json {
+        print_json(&found)?;
+        return Ok(());
+    }
+
+    for owner in found {
+        print!("{} {}", owner.name.bold(), owner.path);
+        if let Some(source) = owner.symlink {
+            print!(" -> {}", source.dim());
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/// An installed package owning a matching path
+#[derive(Debug, Serialize)]
+struct Owner {
+    path: String,
+    name: String,
+    package: package::Id,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    symlink: Option<String>,
+}
+
+/// Top level directories are symlinks into `/usr`, so
+/// resolve them to where their contents are recorded
+fn normalize(pattern: &str) -> String {
+    for link in ["/bin/", "/sbin/", "/lib/", "/lib32/"] {
+        if pattern.starts_with(link) {
+            return format!("/usr{pattern}");
+        }
+    }
+    if let Some(rest) = pattern.strip_prefix("/lib64/") {
+        return format!("/usr/lib/{rest}");
+    }
+
+    pattern.to_string()
+}
+
+/// Absolute patterns match the whole `path`, others only its file name
+fn matches(pattern: &fnmatch::Pattern, is_absolute: bool, path: &str) -> bool {
+    if is_absolute {
+        pattern.is_match(path)
+    } else {
+        path.rsplit('/')
+            .next()
+            .is_some_and(|file_name| pattern.is_match(file_name))
+    }
+}
+
+/// The leading part of `pattern` without any special characters
+fn literal_prefix(pattern: &str) -> &str {
+    let end = pattern.find(['*', '?', '(', '\\']).unwrap_or(pattern.len());
+
+    &pattern[..end]
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No installed files found matching {0:?}")]
+    NoneFound(String),
+
+    #[error("invalid pattern")]
+    Pattern(#[from] fnmatch::Error),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("layout db")]
+    LayoutDB(#[from] db::layout::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let pattern = normalize("/lib/libstdc++.so.6");
+        assert_eq!(literal_prefix(&pattern), "/usr/lib/libstdc++.so.6");
+        assert!(matches(
+            &pattern.parse().unwrap(),
+            true,
+            "/usr/lib/libstdc++.so.6"
+        ));
+
+        let pattern = "/usr/include/c++/*/vector";
+        assert_eq!(literal_prefix(pattern), "/usr/include/c++/");
+        assert!(matches(
+            &pattern.parse().unwrap(),
+            true,
+            "/usr/include/c++/14/vector"
+        ));
+
+        let pattern = "libstdc++.so.*".parse().unwrap();
+        assert!(matches(&pattern, false, "/usr/lib/libstdc++.so.6"));
+        assert!(!matches(&pattern, true, "/usr/lib/libstdc++.so.6"));
+    }
+}
@@ -7,11 +7,16 @@ use std::path::Path;
 use clap::{arg, ArgAction, ArgMatches, Command};
 use futures::{stream, StreamExt, TryFutureExt, TryStreamExt};
 use moss::{
-    client::{self, prune, Client},
-    environment, state,
+    client::{self, lock, plan::Plan, prune},
+    db, environment,
+    package::{self, Flags},
+    state, Client, Package, State,
 };
+use serde::Serialize;
 use thiserror::Error;
-use tui::Stylize;
+use tui::{BinaryBytes, Stylize};
+
+use super::{client_options, open_client, print_json, Format};
 
 pub fn command() -> Command {
     Command::new("state")
@@ -19,6 +24,31 @@ pub fn command() -> Command {
         .long_about("Manage state ...")
         .subcommand_required(true)
         .subcommand(Command::new("list").about("List all states"))
+        .subcommand(
+            Command::new("show")
+                .about("Show the packages of a state")
+                .arg(arg!(<ID> "State id to show").value_parser(clap::value_parser!(i64))),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show the package changes between two states")
+                .arg(arg!(<FROM> "State id to compare from").value_parser(clap::value_parser!(i64)))
+                .arg(arg!(<TO> "State id to compare to").value_parser(clap::value_parser!(i64))),
+        )
+        .subcommand(
+            Command::new("activate")
+                .about("Activate a specific state")
+                .arg(arg!(<ID> "State id to be activated").value_parser(clap::value_parser!(i64))),
+        )
+        .subcommand(Command::new("rollback").about("Activate the previous state"))
+        .subcommand(
+            Command::new("recover")
+                .about("Recover from an interrupted transaction")
+                .long_about(
+                    "Finish an interrupted transaction if the new state was already swapped \
+                     into place, otherwise roll it back",
+                ),
+        )
         .subcommand(
             Command::new("prune").about("Prune old states").arg(
                 arg!(-k --keep "Keep this many states")
@@ -31,15 +61,20 @@ pub fn command() -> Command {
 
 pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
     match args.subcommand() {
-        Some(("list", _)) => list(root).await,
+        Some(("list", args)) => list(args, root).await,
+        Some(("show", args)) => show(args, root).await,
+        Some(("diff", args)) => diff(args, root).await,
+        Some(("activate", args)) => activate(args, root).await,
+        Some(("rollback", args)) => rollback(args, root).await,
+        Some(("recover", args)) => recover(args, root).await,
         Some(("prune", args)) => prune(args, root).await,
         _ => unreachable!(),
     }
 }
 
 /// List all known states, newest first
-pub async fn list(root: &Path) -> Result<(), Error> {
-    let client = Client::new(environment::NAME, root).await?;
+pub async fn list(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let client = open_client(args, root, lock::Kind::Shared).await?;
 
     let state_ids = client.state_db.list_ids().await?;
 
@@ -49,15 +84,169 @@ pub async fn list(root: &Path) -> Result<(), Error> {
         .await?;
 
     states.reverse();
-    states.into_iter().for_each(print_state);
+
+    if Format::from_args(args) == Format::Json {
+        print_json(&states)?;
+    } else {
+        states.into_iter().for_each(print_state);
+    }
+
+    Ok(())
+}
+
+/// Show a state and the packages selected in it
+pub async fn show(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let id = *args.get_one::<i64>("ID").unwrap();
+
+    let client = open_client(args, root, lock::Kind::Shared).await?;
+
+    let state = client.state_db.get(&id.into()).await?;
+    let packages = resolve_selections(&client, &state).await?;
+
+    let mut selected = state
+        .selections
+        .iter()
+        .zip(packages)
+        .map(|(selection, package)| Selected {
+            name: package.meta.name,
+            version: package.meta.version_identifier,
+            release: package.meta.source_release,
+            explicit: selection.explicit,
+            reason: selection.reason.clone(),
+        })
+        .collect::<Vec<_>>();
+    selected.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if Format::from_args(args) == Format::Json {
+        print_json(&Shown {
+            state: &state,
+            packages: selected,
+        })?;
+        return Ok(());
+    }
+
+    print_state(state.clone());
+
+    let max_length = selected
+        .iter()
+        .map(|s| s.name.to_string().len())
+        .max()
+        .unwrap_or_default();
+
+    for item in selected {
+        let name = item.name.to_string();
+        let width = max_length - name.len() + 2;
+        let (name, marker) = if item.explicit {
+            (name.bold(), "explicit".cyan())
+        } else {
+            (name.dim(), "transitive".dim())
+        };
+
+        print!("{name} {:width$} ", " ");
+        print!(
+            "{}-{}",
+            item.version.magenta(),
+            item.release.to_string().dim()
+        );
+        println!(" ({marker})");
+    }
+
+    Ok(())
+}
+
+/// Show the packages added, removed and changed going from one state to another
+pub async fn diff(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let from = *args.get_one::<i64>("FROM").unwrap();
+    let to = *args.get_one::<i64>("TO").unwrap();
+
+    let client = open_client(args, root, lock::Kind::Shared).await?;
+
+    let from = client.state_db.get(&from.into()).await?;
+    let to = client.state_db.get(&to.into()).await?;
+
+    let plan = Plan::new(
+        &resolve_selections(&client, &from).await?,
+        &resolve_selections(&client, &to).await?,
+    );
+
+    if Format::from_args(args) == Format::Json {
+        print_json(&plan.changes)?;
+        return Ok(());
+    }
+
+    println!(
+        "State #{} -> State #{}",
+        from.id.to_string().bold(),
+        to.id.to_string().bold()
+    );
+    println!();
+
+    if plan.is_empty() {
+        println!("No changes");
+    } else {
+        plan.print_changes();
+    }
+
+    Ok(())
+}
+
+/// Activate the given state
+pub async fn activate(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let id = *args.get_one::<i64>("ID").unwrap();
+
+    let client = open_client(args, root, lock::Kind::Exclusive).await?;
+    let state = client.activate_state(id.into()).await?;
+
+    println!(
+        "{} state #{}",
+        "Activated".green(),
+        state.id.to_string().bold()
+    );
+
+    Ok(())
+}
+
+/// Activate the state prior to the active state
+pub async fn rollback(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let client = open_client(args, root, lock::Kind::Exclusive).await?;
+    let state = client.rollback_state().await?;
+
+    println!(
+        "{} to state #{}",
+        "Rolled back".green(),
+        state.id.to_string().bold()
+    );
+
+    Ok(())
+}
+
+/// Finish or roll back an interrupted transaction
+pub async fn recover(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    // Recover explicitly, rather than as a side effect of opening the client
+    let options = client::Options {
+        no_recover: true,
+        ..client_options(args, lock::Kind::Exclusive)
+    };
+    let client = Client::with_options(environment::NAME, root, options).await?;
+
+    match client.recover().await? {
+        Some(recovery) => println!("{}", recovery.to_string().green()),
+        None => println!("No interrupted transaction to recover"),
+    }
+
     Ok(())
 }
 
 pub async fn prune(args: &ArgMatches, root: &Path) -> Result<(), Error> {
     let keep = *args.get_one::<u64>("keep").unwrap();
 
-    let client = Client::new(environment::NAME, root).await?;
-    client.prune(prune::Strategy::KeepRecent(keep)).await?;
+    let client = open_client(args, root, lock::Kind::Exclusive).await?;
+    let pruned = client.prune(prune::Strategy::KeepRecent(keep)).await?;
+
+    for (id, error) in &pruned.kept {
+        println!("{} keeping state {id}: {error}", "Warning".yellow());
+    }
+    println!("{} {}", "Reclaimed".green(), BinaryBytes(pruned.reclaimed));
 
     Ok(())
 }
@@ -75,17 +264,57 @@ fn print_state(state: state::State) {
         "Description:".bold(),
         state.description.unwrap_or(String::from("no description"))
     );
-    // TODO: List packages?
-    // TODO: Start with normal list, compute diff, reverse to print ?
     println!("{} {}", "Packages:".bold(), state.selections.len());
     println!();
 }
 
+/// Resolve the packages selected in `state` from the install db,
+/// in order of the selections
+async fn resolve_selections(client: &Client, state: &State) -> Result<Vec<Package>, Error> {
+    stream::iter(&state.selections)
+        .then(|selection| async {
+            let meta = client.install_db.get(&selection.package).await?;
+
+            Ok(Package {
+                id: selection.package.clone(),
+                meta,
+                flags: Flags::NONE,
+            })
+        })
+        .try_collect()
+        .await
+}
+
+/// A state along with its resolved selections
+#[derive(Serialize)]
+struct Shown<'a> {
+    #[serde(flatten)]
+    state: &'a State,
+    packages: Vec<Selected>,
+}
+
+/// A selection of a state, resolved from the install db
+#[derive(Serialize)]
+struct Selected {
+    name: package::Name,
+    version: String,
+    release: u64,
+    explicit: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("client")]
     Client(#[from] client::Error),
 
     #[error("state db")]
-    StateDB(#[from] moss::db::state::Error),
+    StateDB(#[from] db::state::Error),
+
+    #[error("meta db")]
+    MetaDB(#[from] db::meta::Error),
+
+    #[error("json")]
+    Json(#[from] serde_json::Error),
 }
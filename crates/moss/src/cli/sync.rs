@@ -12,7 +12,7 @@ use moss::environment;
 use moss::registry::transaction;
 use moss::state::Selection;
 use moss::{
-    client::{self, Client},
+    client::{self, plan::Plan, Client},
     package::{self, Flags},
     Package,
 };
@@ -22,6 +22,8 @@ use tui::dialoguer::theme::ColorfulTheme;
 use tui::dialoguer::Confirm;
 use tui::pretty::print_to_columns;
 
+use super::{lock_kind, open_client, print_plan};
+
 pub fn command() -> Command {
     Command::new("sync")
         .about("Sync packages")
@@ -36,13 +38,15 @@ pub fn command() -> Command {
                 )
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(arg!(--"dry-run" "Print the resulting changes without syncing anything"))
 }
 
 pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
     let yes_all = *args.get_one::<bool>("yes").unwrap();
     let upgrade_only = *args.get_one::<bool>("upgrade-only").unwrap();
+    let dry_run = args.get_flag("dry-run");
 
-    let mut client = Client::new(environment::NAME, root).await?;
+    let mut client = open_client(args, root, lock_kind(dry_run)).await?;
 
     // Make ephemeral if a blit target was provided
     if let Some(blit_target) = args.get_one::<PathBuf>("to").cloned() {
@@ -80,6 +84,17 @@ pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
         .filter(|p| client.is_ephemeral() || !installed.iter().any(|i| i.id == p.id))
         .collect::<Vec<_>>();
 
+    if dry_run {
+        // Ephemeral blits start from an empty root
+        let previous = if client.is_ephemeral() {
+            &[][..]
+        } else {
+            &installed[..]
+        };
+        print_plan(args, &Plan::new(previous, &finalized))?;
+        return Ok(());
+    }
+
     if synced.is_empty() {
         println!("No packages to sync");
         return Ok(());
@@ -172,10 +187,17 @@ async fn resolve_with_sync(
             }
         })
         .map(|p| async {
-            // Get first available = use highest priority
+            let rule = client.registry.pins().get(&p.meta.name);
+
+            // Held packages stay as they are
+            if rule.is_some_and(|rule| rule.hold) {
+                return Ok(Cow::Borrowed(p));
+            }
+
+            // Get first available = use highest priority, within any pins
             if let Some(lookup) = client
                 .registry
-                .by_name(&p.meta.name, package::Flags::AVAILABLE)
+                .allowed_by_name(&p.meta.name, package::Flags::AVAILABLE)
                 .boxed()
                 .next()
                 .await
@@ -191,6 +213,9 @@ async fn resolve_with_sync(
                 } else {
                     Ok(Cow::Borrowed(p))
                 }
+            } else if rule.is_some() {
+                // Nothing matches the pin, keep what we have
+                Ok(Cow::Borrowed(p))
             } else {
                 Err(Error::NameNotFound(p.meta.name.clone()))
             }
@@ -231,6 +256,9 @@ pub enum Error {
     #[error("transaction")]
     Transaction(#[from] transaction::Error),
 
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+
     #[error("io")]
     Io(#[from] std::io::Error),
 }
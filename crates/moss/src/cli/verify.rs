@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{path::Path, time::Duration};
+
+use clap::{arg, ArgMatches, Command};
+use moss::client::{self, lock, verify};
+use thiserror::Error;
+use tui::{ProgressBar, ProgressStyle, Stylize};
+
+use super::open_client;
+
+pub fn command() -> Command {
+    Command::new("verify")
+        .about("Verify the installation")
+        .long_about(
+            "Verify files of the active state match their packages, \
+             checking contents, permissions and symlink targets",
+        )
+        .arg(arg!(--repair "Restore all files which failed verification"))
+}
+
+/// Handle execution of `moss verify`
+pub async fn handle(args: &ArgMatches, root: &Path) -> Result<(), Error> {
+    let repair = args.get_flag("repair");
+
+    let client = open_client(
+        args,
+        root,
+        if repair {
+            lock::Kind::Exclusive
+        } else {
+            lock::Kind::Shared
+        },
+    )
+    .await?;
+
+    let progress = ProgressBar::new(1).with_style(
+        ProgressStyle::with_template("\n|{bar:20.red/blue}| {pos}/{len} {msg}")
+            .unwrap()
+            .progress_chars("■≡=- "),
+    );
+    progress.set_message("Verifying files");
+    progress.enable_steady_tick(Duration::from_millis(150));
+
+    let issues = client.verify(&progress).await?;
+
+    progress.finish_and_clear();
+
+    if issues.is_empty() {
+        println!("{} all files verified", "Success".green());
+        return Ok(());
+    }
+
+    for issue in &issues {
+        println!(
+            "{} {} - {}",
+            "Failed".red(),
+            issue.path.display().to_string().bold(),
+            issue.kind
+        );
+    }
+    println!();
+
+    if !repair {
+        return Err(Error::Failed(issues.len()));
+    }
+
+    client.repair(&issues).await?;
+
+    println!("{} repaired {} path(s)", "Success".green(), issues.len());
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("{0} path(s) failed verification, use --repair to restore them")]
+    Failed(usize),
+
+    #[error("client")]
+    Client(#[from] client::Error),
+
+    #[error("verify")]
+    Verify(#[from] verify::Error),
+}
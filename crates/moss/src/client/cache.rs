@@ -2,20 +2,25 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{io, path::PathBuf};
+use std::{
+    cell::Cell,
+    io, iter,
+    path::{Path, PathBuf},
+};
 
 use futures::{stream, StreamExt};
-use stone::{payload, read::PayloadKind};
+use sha2::{Digest, Sha256};
+use stone::{header::v1::FileType, payload, read::PayloadKind};
 use thiserror::Error;
-use tokio::{
-    fs::{self, File},
-    io::AsyncWriteExt,
-    runtime::Handle,
-    task,
-};
+use tokio::{fs, runtime::Handle, task};
 use url::Url;
+use xxhash_rust::xxh3::Xxh3;
 
-use crate::{environment, package, request, Installation};
+use crate::{
+    environment, package,
+    registry::job::{self, Job},
+    request, Installation,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Progress {
@@ -30,45 +35,71 @@ impl Progress {
     }
 }
 
-/// Fetch a package with the provided [`package::Meta`] and [`Installation`] and return a [`Download`] on success.
+/// Fetch a package with the provided [`package::Meta`] from the location described by
+/// `job` into the [`Installation`] and return a [`Download`] on success.
 pub async fn fetch(
     meta: &package::Meta,
+    job: &Job,
     installation: &Installation,
     on_progress: impl Fn(Progress),
 ) -> Result<Download, Error> {
-    let url = meta.uri.as_ref().ok_or(Error::MissingUri)?.parse::<Url>()?;
-    let hash = meta.hash.as_ref().ok_or(Error::MissingHash)?;
+    let url = match &job.origin {
+        job::Origin::RemoteFile(url) => url.clone(),
+        job::Origin::LocalFile(path) => Url::from_file_path(path).map_err(|_| Error::MissingUri)?,
+    };
+    let Some(job::CheckType::Sha256(hash)) = &job.check else {
+        return Err(Error::MissingHash);
+    };
 
     let download_path = download_path(installation, hash).await?;
 
     if fs::try_exists(&download_path).await? {
-        return Ok(Download {
-            id: meta.id().into(),
-            path: download_path,
-            installation: installation.clone(),
-            was_cached: true,
-        });
+        // Only reuse cached downloads which are still intact
+        if sha256(&download_path).await? == *hash {
+            return Ok(Download {
+                id: meta.id().into(),
+                path: download_path,
+                installation: installation.clone(),
+                was_cached: true,
+            });
+        }
+
+        fs::remove_file(&download_path).await?;
     }
 
-    let mut bytes = request::get(url).await?;
-    let mut out = File::create(&download_path).await?;
+    // Download to a partial file so an interrupted download
+    // is never mistaken for a valid cache entry, and can be
+    // resumed by the next attempt
+    let partial_path = download_path.with_extension("part");
 
-    let mut total = 0;
+    let urls = iter::once(url).chain(job.mirrors.iter().cloned());
+    let previous = Cell::new(0);
 
-    while let Some(chunk) = bytes.next().await {
-        let bytes = chunk?;
-        let delta = bytes.len() as u64;
-        total += delta;
-        out.write_all(&bytes).await?;
+    request::download(urls, &partial_path, |completed| {
+        // Restarted downloads report less than before
+        let delta = completed.saturating_sub(previous.replace(completed));
 
         (on_progress)(Progress {
             delta,
-            completed: total,
-            total: meta.download_size.unwrap_or(total),
+            completed,
+            total: if job.size > 0 { job.size } else { completed },
+        });
+    })
+    .await?;
+
+    let computed = sha256(&partial_path).await?;
+
+    if computed != *hash {
+        fs::remove_file(&partial_path).await?;
+
+        return Err(Error::HashMismatch {
+            name: meta.name.to_string(),
+            expected: hash.clone(),
+            computed,
         });
     }
 
-    out.flush().await?;
+    fs::rename(&partial_path, &download_path).await?;
 
     Ok(Download {
         id: meta.id().into(),
@@ -78,6 +109,22 @@ pub async fn fetch(
     })
 }
 
+/// Returns the hex encoded sha256 of the file at `path`
+async fn sha256(path: &Path) -> Result<String, Error> {
+    use std::fs::File;
+
+    let path = path.to_owned();
+
+    task::spawn_blocking(move || {
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+
+        Ok(hex::encode(hasher.finalize()))
+    })
+    .await
+    .expect("join handle")
+}
+
 /// A package that has been downloaded to the installation
 pub struct Download {
     id: package::Id,
@@ -140,6 +187,32 @@ impl Download {
             }
         }
 
+        /// Hashes all bytes written through it with xxh3
+        struct DigestWriter<'a, W> {
+            writer: W,
+            hasher: &'a mut Xxh3,
+        }
+
+        impl<'a, W> DigestWriter<'a, W> {
+            pub fn new(writer: W, hasher: &'a mut Xxh3) -> Self {
+                Self { writer, hasher }
+            }
+        }
+
+        impl<'a, W: Write> Write for DigestWriter<'a, W> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                let bytes = self.writer.write(buf)?;
+
+                self.hasher.update(&buf[..bytes]);
+
+                Ok(bytes)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.writer.flush()
+            }
+        }
+
         let rt = Handle::current();
 
         task::spawn_blocking(move || {
@@ -150,6 +223,8 @@ impl Download {
 
             let mut reader = stone::read(File::open(&self.path)?)?;
 
+            let is_delta = matches!(reader.header, stone::Header::V1(header) if header.file_type == FileType::Delta);
+
             let payloads = reader.payloads()?.collect::<Result<Vec<_>, _>>()?;
             let indicies = payloads
                 .iter()
@@ -157,8 +232,24 @@ impl Download {
                 .flat_map(|p| &p.body)
                 .collect::<Vec<_>>();
 
+            // Deltas only carry new content, the remaining assets
+            // must already be cached from the package they apply to
+            let required = if is_delta {
+                payloads
+                    .iter()
+                    .filter_map(PayloadKind::layout)
+                    .flat_map(|p| &p.body)
+                    .filter_map(|layout| match &layout.entry {
+                        payload::layout::Entry::Regular(digest, _) => Some(*digest),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            } else {
+                indicies.iter().map(|index| index.digest).collect()
+            };
+
             // If download was cached & all assets exist, we can skip unpacking
-            if self.was_cached && rt.block_on(check_assets_exist(&indicies, &self.installation)) {
+            if self.was_cached && rt.block_on(assets_exist(required.clone(), &self.installation)) {
                 return Ok(UnpackedAsset { payloads });
             }
 
@@ -191,9 +282,19 @@ impl Download {
                         &format!("{:02x}", idx.digest),
                     ))?;
 
-                    let mut output = File::create(path)?;
+                    let mut output = File::create(&path)?;
+                    let mut hasher = Xxh3::new();
 
-                    copy(&mut split_file, &mut output)?;
+                    copy(
+                        &mut split_file,
+                        &mut DigestWriter::new(&mut output, &mut hasher),
+                    )?;
+
+                    // Don't leave a corrupt asset around to be linked
+                    if hasher.digest128() != idx.digest {
+                        remove_file(&path)?;
+                        return Err(Error::CorruptAsset(format!("{:02x}", idx.digest)));
+                    }
 
                     Ok(())
                 })
@@ -201,6 +302,10 @@ impl Download {
 
             remove_file(&content_path)?;
 
+            if is_delta && !rt.block_on(assets_exist(required, &self.installation)) {
+                return Err(Error::MissingDeltaAssets(self.id));
+            }
+
             Ok(UnpackedAsset { payloads })
         })
         .await
@@ -208,11 +313,14 @@ impl Download {
     }
 }
 
-/// Returns true if all assets already exist in the installation
-async fn check_assets_exist(indicies: &[&payload::Index], installation: &Installation) -> bool {
-    stream::iter(indicies)
-        .map(|index| async move {
-            if let Ok(path) = asset_path(installation, &format!("{:02x}", index.digest)).await {
+/// Returns true if all assets with the given `digests` already exist in the installation
+pub async fn assets_exist(
+    digests: impl IntoIterator<Item = u128>,
+    installation: &Installation,
+) -> bool {
+    stream::iter(digests)
+        .map(|digest| async move {
+            if let Ok(path) = asset_path(installation, &format!("{digest:02x}")).await {
                 return fs::try_exists(path).await.unwrap_or_default();
             }
 
@@ -224,24 +332,60 @@ async fn check_assets_exist(indicies: &[&payload::Index], installation: &Install
 }
 
 pub async fn download_path(installation: &Installation, hash: &str) -> Result<PathBuf, Error> {
+    let path = download_location(installation, hash)?;
+
+    if let Some(directory) = path.parent() {
+        if !directory.exists() {
+            fs::create_dir_all(directory).await?;
+        }
+    }
+
+    Ok(path)
+}
+
+/// Returns the path of the intact download with the given `hash` if it's
+/// already cached, without creating any directories
+pub async fn cached_download(
+    installation: &Installation,
+    hash: &str,
+) -> Result<Option<PathBuf>, Error> {
+    let path = download_location(installation, hash)?;
+
+    if fs::try_exists(&path).await? && sha256(&path).await? == hash {
+        Ok(Some(path))
+    } else {
+        Ok(None)
+    }
+}
+
+fn download_location(installation: &Installation, hash: &str) -> Result<PathBuf, Error> {
     if hash.len() < 5 {
         return Err(Error::MalformedHash(hash.to_string()));
     }
 
-    let directory = installation
+    Ok(installation
         .cache_path("downloads")
         .join("v1")
         .join(&hash[..5])
-        .join(&hash[hash.len() - 5..]);
+        .join(&hash[hash.len() - 5..])
+        .join(hash))
+}
+
+pub async fn asset_path(installation: &Installation, hash: &str) -> Result<PathBuf, Error> {
+    let path = asset_location(installation, hash);
 
-    if !directory.exists() {
-        fs::create_dir_all(&directory).await?;
+    if let Some(directory) = path.parent() {
+        if !directory.exists() {
+            fs::create_dir_all(directory).await?;
+        }
     }
 
-    Ok(directory.join(hash))
+    Ok(path)
 }
 
-pub async fn asset_path(installation: &Installation, hash: &str) -> Result<PathBuf, Error> {
+/// Returns the path of the asset with the given `hash` in the
+/// store, without creating any directories
+pub(super) fn asset_location(installation: &Installation, hash: &str) -> PathBuf {
     let directory = if hash.len() >= 10 {
         installation
             .assets_path("v2")
@@ -252,11 +396,7 @@ pub async fn asset_path(installation: &Installation, hash: &str) -> Result<PathB
         installation.assets_path("v2")
     };
 
-    if !directory.exists() {
-        fs::create_dir_all(&directory).await?;
-    }
-
-    Ok(directory.join(hash))
+    directory.join(hash)
 }
 
 #[derive(Debug, Error)]
@@ -267,6 +407,16 @@ pub enum Error {
     MissingUri,
     #[error("Missing content payload")]
     MissingContent,
+    #[error("Hash mismatch for {name}, expected {expected} but got {computed}")]
+    HashMismatch {
+        name: String,
+        expected: String,
+        computed: String,
+    },
+    #[error("Missing assets to apply delta for {0:?}")]
+    MissingDeltaAssets(package::Id),
+    #[error("Corrupt asset {0}")]
+    CorruptAsset(String),
     #[error("Malformed download hash: {0}")]
     MalformedHash(String),
     #[error("stone format")]
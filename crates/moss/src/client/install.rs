@@ -2,7 +2,10 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::path::Path;
+
 use futures::{future::join_all, StreamExt};
+use itertools::Itertools;
 use thiserror::Error;
 use tui::{
     dialoguer::{theme::ColorfulTheme, Confirm},
@@ -10,7 +13,7 @@ use tui::{
 };
 
 use crate::{
-    client::{self, Client},
+    client::{self, plan::Plan, Client},
     package::{self, Flags},
     registry::transaction,
     state::Selection,
@@ -18,46 +21,15 @@ use crate::{
 };
 
 pub async fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<(), Error> {
-    // Resolve input packages
-    let input = resolve_input(pkgs, client).await?;
-
-    // Add all inputs
-    let mut tx = client.registry.transaction()?;
-
-    tx.add(input.clone()).await?;
-
-    // Resolve transaction to metadata
-    let resolved = client.resolve_packages(tx.finalize()).await?;
-
-    // Get installed packages to check against
-    let installed = client
-        .registry
-        .list_installed(Flags::NONE)
-        .collect::<Vec<_>>()
-        .await;
-    let is_installed = |p: &Package| installed.iter().any(|i| i.meta.name == p.meta.name);
-
-    // Get missing packages that are:
-    //
-    // Stateful: Not installed
-    // Ephemeral: all
-    let missing = resolved
-        .iter()
-        .filter(|p| client.is_ephemeral() || !is_installed(p))
-        .collect::<Vec<_>>();
+    let resolved = resolve(client, pkgs).await?;
 
     // If no new packages exist, exit and print
     // packages already installed
-    if missing.is_empty() {
-        let installed = resolved
-            .iter()
-            .filter(|p| is_installed(p) && input.contains(&p.id))
-            .collect::<Vec<_>>();
-
-        if !installed.is_empty() {
+    if resolved.missing.is_empty() {
+        if !resolved.already_installed.is_empty() {
             println!("The following package(s) are already installed:");
             println!();
-            print_to_columns(&installed);
+            print_to_columns(&resolved.already_installed);
         }
 
         return Ok(());
@@ -65,9 +37,16 @@ pub async fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<()
 
     println!("The following package(s) will be installed:");
     println!();
-    print_to_columns(&missing);
+    print_to_columns(&resolved.missing);
     println!();
 
+    if !resolved.replaced.is_empty() {
+        println!("The following package(s) will be replaced:");
+        println!();
+        print_to_columns(&resolved.replaced);
+        println!();
+    }
+
     // Must we prompt?
     let result = if yes {
         true
@@ -82,32 +61,208 @@ pub async fn install(client: &mut Client, pkgs: &[&str], yes: bool) -> Result<()
     }
 
     // Cache packages
-    client.cache_packages(&missing).await?;
-
-    // Calculate the new state of packages (old_state + missing)
-    let new_state_pkgs = {
-        // Only use previous state in stateful mode
-        let previous_selections = match client.installation.active_state {
-            Some(id) if !client.is_ephemeral() => client.state_db.get(&id).await?.selections,
-            _ => vec![],
-        };
-        let missing_selections = missing.iter().map(|p| Selection {
+    client
+        .cache_packages(&resolved.missing.iter().collect::<Vec<_>>())
+        .await?;
+
+    // Perfect, apply state.
+    client
+        .apply_state(&resolved.selections(), "Install")
+        .await?;
+
+    Ok(())
+}
+
+/// Resolve the [`Plan`] of installing `pkgs` without fetching
+/// any packages or recording a new state
+pub async fn plan(client: &mut Client, pkgs: &[&str]) -> Result<Plan, Error> {
+    let resolved = resolve(client, pkgs).await?;
+
+    let previous = client
+        .resolve_packages(resolved.previous_selections.iter().map(|s| &s.package))
+        .await?;
+    let next = previous
+        .iter()
+        .filter(|p| !resolved.replaced.iter().any(|r| r.id == p.id))
+        .chain(&resolved.missing)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    Ok(Plan::new(&previous, &next))
+}
+
+/// The outcome of resolving an install
+struct Resolved {
+    /// Packages requested by the user
+    input: Vec<package::Id>,
+    /// Packages which need to be installed
+    missing: Vec<Package>,
+    /// Previously selected packages which are replaced
+    replaced: Vec<Package>,
+    /// Requested packages which are already installed
+    already_installed: Vec<Package>,
+    previous_selections: Vec<Selection>,
+}
+
+impl Resolved {
+    /// Calculate the new state of packages (old_state - replaced + missing)
+    fn selections(&self) -> Vec<Selection> {
+        let previous_selections = self
+            .previous_selections
+            .iter()
+            .filter(|s| !self.replaced.iter().any(|p| p.id == s.package))
+            .cloned();
+        let missing_selections = self.missing.iter().map(|p| Selection {
             package: p.id.clone(),
             // Package is explicit if it was one of the input
             // packages provided by the user
-            explicit: input.iter().any(|id| *id == p.id),
+            explicit: self.input.contains(&p.id),
             reason: None,
         });
 
         missing_selections
             .chain(previous_selections)
             .collect::<Vec<_>>()
+    }
+}
+
+async fn resolve(client: &mut Client, pkgs: &[&str]) -> Result<Resolved, Error> {
+    // Local stone files are loaded into the registry
+    // and take part in resolution like any other package
+    let (local_paths, names): (Vec<_>, Vec<_>) = pkgs.iter().partition(|p| is_local_stone(p));
+    let local = client.load_local_packages(local_paths).await?;
+
+    // Resolve input packages
+    let input = local
+        .iter()
+        .cloned()
+        .chain(resolve_input(&names, client).await?)
+        .collect::<Vec<_>>();
+
+    // Add all inputs
+    let mut tx = client.registry.transaction()?;
+
+    tx.add(input.clone()).await?;
+
+    // Resolve transaction to metadata
+    let resolved = client.resolve_packages(tx.finalize()).await?;
+
+    // Only use previous state in stateful mode
+    let previous_selections = match client.installation.active_state {
+        Some(id) if !client.is_ephemeral() => client.state_db.get(&id).await?.selections,
+        _ => vec![],
     };
 
-    // Perfect, apply state.
-    client.apply_state(&new_state_pkgs, "Install").await?;
+    // Previously selected packages conflicting with the
+    // transaction are replaced by it
+    let previous_ids = previous_selections
+        .iter()
+        .map(|s| s.package.clone())
+        .collect::<Vec<_>>();
+    let conflicting = tx
+        .conflicts_with(&previous_ids)
+        .await?
+        .into_iter()
+        .flat_map(|conflict| [conflict.package, conflict.conflicting])
+        .filter(|p| previous_ids.contains(&p.id));
 
-    Ok(())
+    // Get installed packages to check against
+    let installed = client
+        .registry
+        .list_installed(Flags::NONE)
+        .collect::<Vec<_>>()
+        .await;
+    // Local packages are reinstalled unless that exact build is installed
+    let is_installed = |p: &Package| {
+        installed.iter().any(|i| {
+            if local.contains(&p.id) {
+                i.id == p.id
+            } else {
+                i.meta.name == p.meta.name
+            }
+        })
+    };
+    let is_local_rebuild = |i: &Package| {
+        resolved
+            .iter()
+            .any(|p| local.contains(&p.id) && p.id != i.id && p.meta.name == i.meta.name)
+    };
+
+    let replaced = conflicting
+        .chain(installed.iter().filter(|i| is_local_rebuild(i)).cloned())
+        .unique_by(|p| p.id.clone())
+        .collect::<Vec<_>>();
+
+    // Whatever relied on a replaced package must still have its
+    // dependencies met once it's gone
+    if !replaced.is_empty() {
+        let kept = client
+            .resolve_packages(
+                previous_ids
+                    .iter()
+                    .filter(|id| !replaced.iter().any(|p| p.id == **id)),
+            )
+            .await?;
+
+        let unmet = unmet_dependencies(&kept, &resolved, &replaced);
+        if !unmet.is_empty() {
+            return Err(Error::Required(unmet));
+        }
+    }
+
+    // Get missing packages that are:
+    //
+    // Stateful: Not installed
+    // Ephemeral: all
+    let missing = resolved
+        .iter()
+        .filter(|p| client.is_ephemeral() || !is_installed(p))
+        .cloned()
+        .collect::<Vec<_>>();
+    let already_installed = resolved
+        .iter()
+        .filter(|p| is_installed(p) && input.contains(&p.id))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    Ok(Resolved {
+        input,
+        missing,
+        replaced,
+        already_installed,
+        previous_selections,
+    })
+}
+
+/// Dependencies of the `kept` packages which were provided by a `replaced`
+/// package and aren't provided by either the `kept` or `added` packages
+fn unmet_dependencies(kept: &[Package], added: &[Package], replaced: &[Package]) -> Vec<String> {
+    let provides = |packages: &[Package], provider: &Provider| {
+        packages.iter().any(|p| p.meta.providers.contains(provider))
+    };
+
+    kept.iter()
+        .flat_map(|package| {
+            package.meta.dependencies.iter().map(move |dependency| {
+                let provider = Provider {
+                    kind: dependency.kind.clone(),
+                    name: dependency.name.clone(),
+                };
+                (package, provider)
+            })
+        })
+        .filter(|(_, provider)| {
+            provides(replaced, provider) && !provides(kept, provider) && !provides(added, provider)
+        })
+        .map(|(package, provider)| format!("{} requires {provider}", package.meta.name))
+        .collect()
+}
+
+/// Returns true if the argument refers to a local `.stone` file
+fn is_local_stone(arg: &str) -> bool {
+    let path = Path::new(arg);
+
+    path.extension().is_some_and(|ext| ext == "stone") && path.is_file()
 }
 
 /// Resolves the package arguments as valid input packages. Returns an error
@@ -134,7 +289,7 @@ async fn find_packages<'a>(id: &'a str, client: &Client) -> (String, Option<Pack
     let provider = Provider::from_name(id).unwrap();
     let result = client
         .registry
-        .by_provider(&provider, Flags::AVAILABLE)
+        .allowed_by_provider(&provider, Flags::AVAILABLE)
         .collect::<Vec<_>>()
         .await;
 
@@ -153,6 +308,9 @@ pub enum Error {
     #[error("no package found: {0}")]
     NoPackage(String),
 
+    #[error("replaced packages are still needed: {}", .0.join(", "))]
+    Required(Vec<String>),
+
     #[error("transaction")]
     Transaction(#[from] transaction::Error),
 
@@ -171,3 +329,61 @@ pub enum Error {
     #[error("io")]
     Io(#[from] std::io::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn package(name: &str, providers: &[&str], dependencies: &[&str]) -> Package {
+        Package {
+            id: package::Id::from(name.to_string()),
+            meta: package::Meta {
+                name: package::Name::from(name.to_string()),
+                version_identifier: Default::default(),
+                source_release: Default::default(),
+                build_release: Default::default(),
+                architecture: Default::default(),
+                summary: Default::default(),
+                description: Default::default(),
+                source_id: Default::default(),
+                homepage: Default::default(),
+                licenses: Default::default(),
+                dependencies: dependencies.iter().map(|d| d.parse().unwrap()).collect(),
+                providers: providers
+                    .iter()
+                    .chain(&[name])
+                    .map(|p| Provider::from_name(p).unwrap())
+                    .collect(),
+                conflicts: Default::default(),
+                uri: Default::default(),
+                hash: Default::default(),
+                download_size: Default::default(),
+                installed_size: Default::default(),
+            },
+            flags: Flags::NONE,
+        }
+    }
+
+    #[test]
+    fn test_unmet_dependencies() {
+        let old = package("openssl", &["soname(libssl.so.3)"], &[]);
+        let curl = package("curl", &[], &["soname(libssl.so.3)", "name(zlib)"]);
+        let zlib = package("zlib", &[], &[]);
+
+        // The replacement provides the same library
+        let libressl = package("libressl", &["soname(libssl.so.3)"], &[]);
+        assert!(unmet_dependencies(
+            &[curl.clone(), zlib.clone()],
+            &[libressl],
+            std::slice::from_ref(&old)
+        )
+        .is_empty());
+
+        // It doesn't, so curl would break
+        let other = package("other", &[], &[]);
+        assert_eq!(
+            unmet_dependencies(&[curl, zlib], &[other], &[old]),
+            ["curl requires soname(libssl.so.3)"]
+        );
+    }
+}
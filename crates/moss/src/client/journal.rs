@@ -0,0 +1,428 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Journal of an in-progress transaction, recorded to `.moss/journal`
+//! so an interrupted transaction can be finished or rolled back
+
+use std::{fmt, io};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::fs::{self, remove_dir_all, rename, File};
+use tokio::io::AsyncWriteExt;
+
+use super::{create_root_links, postblit, Client};
+use crate::{db, state, Installation};
+
+/// The kind of transaction being journaled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Applying a new state
+    Apply,
+    /// Activating a previously recorded state
+    Activate,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Apply => "apply".fmt(f),
+            Kind::Activate => "activate".fmt(f),
+        }
+    }
+}
+
+/// How far the transaction got
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    /// The new `/usr` is being prepared in staging
+    Staging,
+    /// The new state is recorded in the database
+    Recorded,
+    /// The new `/usr` has been swapped into place
+    Swapped,
+}
+
+/// A transaction in progress
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Journal {
+    pub kind: Kind,
+    pub step: Step,
+    /// State active when the transaction began
+    pub from: Option<state::Id>,
+    /// State being applied or activated, once known
+    pub to: Option<state::Id>,
+    /// Latest recorded state when the transaction began. Any newer
+    /// state was recorded by this transaction
+    pub latest: Option<state::Id>,
+    /// The tree of `to` was moved out of its archive into staging
+    pub archived: bool,
+}
+
+impl Journal {
+    /// Begin journaling a transaction of `kind`, away from the active state
+    pub async fn begin(
+        installation: &Installation,
+        state_db: &db::state::Database,
+        kind: Kind,
+        to: Option<state::Id>,
+    ) -> Result<Self, Error> {
+        let latest = state_db
+            .list_ids()
+            .await?
+            .into_iter()
+            .map(|(id, _)| id)
+            .max_by_key(|id| i64::from(*id));
+
+        let journal = Self {
+            kind,
+            step: Step::Staging,
+            from: installation.active_state,
+            to,
+            latest,
+            archived: false,
+        };
+        journal.write(installation).await?;
+
+        Ok(journal)
+    }
+
+    /// Load the journal of an interrupted transaction, if any
+    pub async fn load(installation: &Installation) -> Result<Option<Self>, Error> {
+        match fs::read(installation.journal_path()).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record the transaction reached `step`
+    pub async fn advance(&mut self, installation: &Installation, step: Step) -> Result<(), Error> {
+        self.step = step;
+        self.write(installation).await
+    }
+
+    /// The transaction is complete, remove the journal
+    pub async fn finish(installation: &Installation) -> Result<(), Error> {
+        match fs::remove_file(installation.journal_path()).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Atomically replace the journal on disk
+    async fn write(&self, installation: &Installation) -> Result<(), Error> {
+        let path = installation.journal_path();
+        let partial = path.with_extension("part");
+
+        let mut file = File::create(&partial).await?;
+        file.write_all(&serde_json::to_vec(self)?).await?;
+        file.sync_all().await?;
+
+        rename(&partial, &path).await?;
+
+        Ok(())
+    }
+}
+
+/// Outcome of recovering an interrupted transaction
+#[derive(Debug, Clone)]
+pub enum Recovery {
+    /// `/usr` was never swapped, so the transaction was undone
+    RolledBack(Journal),
+    /// `/usr` was swapped, so the remaining steps were completed
+    Completed(Journal),
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recovery::RolledBack(journal) => write!(
+                f,
+                "Rolled back the interrupted {} transaction",
+                journal.kind
+            ),
+            Recovery::Completed(journal) => match journal.to.or(journal.from) {
+                Some(id) => write!(
+                    f,
+                    "Completed the interrupted {} transaction of state {id}",
+                    journal.kind
+                ),
+                None => write!(f, "Completed the interrupted {} transaction", journal.kind),
+            },
+        }
+    }
+}
+
+/// Finish or roll back the transaction recorded in the journal, if any
+pub async fn recover(client: &Client) -> Result<Option<Recovery>, super::Error> {
+    let installation = &client.installation;
+
+    let Some(journal) = Journal::load(installation).await? else {
+        return Ok(None);
+    };
+
+    // `/usr/.stateID` is swapped atomically alongside `/usr` itself,
+    // so it's the truth even if the journal didn't get to record it
+    let swapped = journal.step == Step::Swapped || installation.active_state != journal.from;
+
+    let recovery = if swapped {
+        complete(client, &journal).await?;
+        Recovery::Completed(journal)
+    } else {
+        roll_back(client, &journal).await?;
+        Recovery::RolledBack(journal)
+    };
+
+    Journal::finish(installation).await?;
+
+    Ok(Some(recovery))
+}
+
+/// Run the steps following the swap of `/usr`
+async fn complete(client: &Client, journal: &Journal) -> Result<(), super::Error> {
+    let installation = &client.installation;
+
+    create_root_links(&installation.root).await?;
+
+    // After the swap, the previous `/usr` is in staging until archived
+    if let Some(from) = journal.from {
+        let archived = installation.root_path(from.to_string()).join("usr");
+
+        if installation.staging_path("usr").exists() && !archived.exists() {
+            client.archive_state(from).await?;
+        }
+    }
+
+    // Triggers may or may not have run, but they're safe to repeat
+    if let Some(active) = installation.active_state {
+        let state = client.state_db.get(&active).await?;
+        let handlers = postblit::handlers(
+            &client.layout_db,
+            state.selections.iter().map(|s| &s.package),
+            &installation.root,
+            &installation.root,
+        )
+        .await?;
+        postblit::run(&installation.root, handlers).await?;
+    }
+
+    Ok(())
+}
+
+/// Undo everything preceding the swap of `/usr`
+async fn roll_back(client: &Client, journal: &Journal) -> Result<(), super::Error> {
+    let installation = &client.installation;
+    let staging = installation.staging_path("usr");
+
+    match (journal.kind, journal.to) {
+        // Return the tree of the state being activated to its archive
+        (Kind::Activate, Some(to)) if journal.archived => {
+            let archived = installation.root_path(to.to_string()).join("usr");
+
+            if staging.exists() && !archived.exists() {
+                rename(&staging, &archived).await?;
+            }
+        }
+        // Forget states recorded by this transaction
+        (Kind::Apply, _) => {
+            let recorded = client
+                .state_db
+                .list_ids()
+                .await?
+                .into_iter()
+                .map(|(id, _)| id)
+                .filter(|id| {
+                    journal
+                        .latest
+                        .is_none_or(|latest| i64::from(*id) > i64::from(latest))
+                })
+                .collect::<Vec<_>>();
+
+            if !recorded.is_empty() {
+                client.state_db.batch_remove(&recorded).await?;
+            }
+        }
+        _ => {}
+    }
+
+    // Anything left in staging is a partial tree
+    if staging.exists() {
+        remove_dir_all(&staging).await?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("state db")]
+    StateDB(#[from] db::state::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+    use crate::client::{self, test::Root};
+
+    /// Reopen a client without recovering, as after a crash
+    async fn reopen(root: &Root) -> Client {
+        let options = client::Options {
+            offline: true,
+            no_recover: true,
+            ..Default::default()
+        };
+        Client::with_options("test", &root.0, options)
+            .await
+            .unwrap()
+    }
+
+    async fn state_ids(client: &Client) -> Vec<i64> {
+        let mut ids = client
+            .state_db
+            .list_ids()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| i64::from(id))
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    fn state_id(usr: &Path) -> String {
+        std::fs::read_to_string(usr.join(".stateID")).unwrap()
+    }
+
+    /// Interrupt an apply of state 2 at `step`, with a partial tree in staging
+    async fn interrupt_apply(root: &Root, step: Step) -> Client {
+        let client = root.installed().await;
+        let installation = &client.installation;
+
+        let mut journal = Journal::begin(installation, &client.state_db, Kind::Apply, None)
+            .await
+            .unwrap();
+
+        let staging = installation.staging_path("usr");
+        fs::create_dir_all(&staging).await.unwrap();
+        fs::write(staging.join(".stateID"), "2").await.unwrap();
+
+        let selections = client.state_db.get(&1.into()).await.unwrap().selections;
+        let state = client.state_db.add(&selections, None, None).await.unwrap();
+        assert_eq!(i64::from(state.id), 2);
+
+        if step >= Step::Recorded {
+            journal.to = Some(state.id);
+            journal.advance(installation, Step::Recorded).await.unwrap();
+        }
+        if step == Step::Swapped {
+            client.promote_staging().await.unwrap();
+            journal.advance(installation, Step::Swapped).await.unwrap();
+        }
+
+        drop(client);
+        reopen(root).await
+    }
+
+    #[tokio::test]
+    async fn test_roll_back_staging() {
+        let root = Root::new("journal-staging");
+        let client = interrupt_apply(&root, Step::Staging).await;
+
+        let recovery = recover(&client).await.unwrap();
+        assert!(matches!(recovery, Some(Recovery::RolledBack(_))));
+
+        // The state recorded before the journal advanced is still forgotten
+        assert_eq!(state_ids(&client).await, vec![1]);
+        assert_eq!(state_id(&root.0.join("usr")), "1");
+        assert!(!client.installation.staging_path("usr").exists());
+        assert!(Journal::load(&client.installation).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_roll_back_recorded() {
+        let root = Root::new("journal-recorded");
+        let client = interrupt_apply(&root, Step::Recorded).await;
+
+        let recovery = recover(&client).await.unwrap();
+        assert!(matches!(recovery, Some(Recovery::RolledBack(_))));
+
+        assert_eq!(state_ids(&client).await, vec![1]);
+        assert_eq!(state_id(&root.0.join("usr")), "1");
+        assert!(!client.installation.staging_path("usr").exists());
+        assert!(Journal::load(&client.installation).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_complete_swapped() {
+        let root = Root::new("journal-swapped");
+        let client = interrupt_apply(&root, Step::Swapped).await;
+        assert_eq!(client.installation.active_state, Some(2.into()));
+
+        let recovery = recover(&client).await.unwrap();
+        assert!(matches!(recovery, Some(Recovery::Completed(_))));
+
+        // The previous tree is archived and the new state kept
+        assert_eq!(state_ids(&client).await, vec![1, 2]);
+        assert_eq!(state_id(&root.0.join("usr")), "2");
+        assert_eq!(
+            state_id(&client.installation.root_path("1").join("usr")),
+            "1"
+        );
+        assert!(!client.installation.staging_path("usr").exists());
+        assert!(root.0.join("bin").is_symlink());
+        assert!(Journal::load(&client.installation).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_roll_back_activate_archived() {
+        let root = Root::new("journal-activate");
+
+        // Apply a second state so the first is archived
+        let client = root.installed().await;
+        let selections = client.state_db.get(&1.into()).await.unwrap().selections;
+        client.apply_state(&selections, "Again").await.unwrap();
+        drop(client);
+
+        let client = reopen(&root).await;
+        let installation = &client.installation;
+        let archived = installation.root_path("1").join("usr");
+        let staging = installation.staging_path("usr");
+
+        // Interrupt activating state 1 after its tree left the archive
+        let mut journal = Journal::begin(
+            installation,
+            &client.state_db,
+            Kind::Activate,
+            Some(1.into()),
+        )
+        .await
+        .unwrap();
+        journal.archived = true;
+        journal.advance(installation, Step::Staging).await.unwrap();
+        fs::create_dir_all(installation.staging_dir())
+            .await
+            .unwrap();
+        rename(&archived, &staging).await.unwrap();
+
+        let recovery = recover(&client).await.unwrap();
+        assert!(matches!(recovery, Some(Recovery::RolledBack(_))));
+
+        // The tree is back in its archive and no state was forgotten
+        assert_eq!(state_ids(&client).await, vec![1, 2]);
+        assert_eq!(state_id(&root.0.join("usr")), "2");
+        assert_eq!(state_id(&archived), "1");
+        assert!(!staging.exists());
+        assert!(Journal::load(installation).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,211 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Advisory locking of an [`Installation`] so concurrent moss
+//! processes never write to the same databases & trees
+
+use std::{
+    fs::{File, OpenOptions},
+    os::fd::AsRawFd,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::warn;
+use nix::{
+    errno::Errno,
+    fcntl::{flock, FlockArg},
+};
+use thiserror::Error;
+use tokio::task;
+
+use crate::Installation;
+
+/// How the installation is locked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Kind {
+    /// Any number of processes may read the installation
+    Shared,
+    /// A single process may modify the installation
+    #[default]
+    Exclusive,
+}
+
+/// An advisory lock on `.moss/lock`, released once dropped
+#[derive(Debug)]
+pub struct Lock {
+    /// `None` if the lock file can't be opened, i.e. a read-only root
+    file: Option<File>,
+    exclusive: AtomicBool,
+    wait: bool,
+}
+
+impl Lock {
+    /// Acquire a lock of `kind` on the installation. If another process
+    /// holds a conflicting lock, either wait for it to be released or fail
+    /// with [`Error::Locked`] if `wait` is false
+    pub async fn acquire(
+        installation: &Installation,
+        kind: Kind,
+        wait: bool,
+    ) -> Result<Self, Error> {
+        let path = installation.lock_path();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .or_else(|_| File::open(&path));
+
+        let lock = Self {
+            file: match file {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    warn!("Unable to open lock file {path:?}: {e}");
+                    None
+                }
+            },
+            exclusive: AtomicBool::new(false),
+            wait,
+        };
+
+        match kind {
+            Kind::Shared => lock.lock(Kind::Shared).await?,
+            Kind::Exclusive => {
+                lock.exclusive().await?;
+            }
+        }
+
+        Ok(lock)
+    }
+
+    /// Upgrade to an exclusive lock, returning true if it wasn't already held
+    ///
+    /// The upgrade isn't atomic, another process may take the
+    /// lock in between, so anything read under the shared lock
+    /// must be checked again
+    pub async fn exclusive(&self) -> Result<bool, Error> {
+        if self.exclusive.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+
+        self.lock(Kind::Exclusive).await?;
+        self.exclusive.store(true, Ordering::Release);
+
+        Ok(true)
+    }
+
+    /// Downgrade to a shared lock, if exclusive
+    pub async fn shared(&self) -> Result<(), Error> {
+        if !self.exclusive.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        self.lock(Kind::Shared).await?;
+        self.exclusive.store(false, Ordering::Release);
+
+        Ok(())
+    }
+
+    async fn lock(&self, kind: Kind) -> Result<(), Error> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let fd = file.as_raw_fd();
+
+        let (nonblocking, blocking) = match kind {
+            Kind::Shared => (FlockArg::LockSharedNonblock, FlockArg::LockShared),
+            Kind::Exclusive => (FlockArg::LockExclusiveNonblock, FlockArg::LockExclusive),
+        };
+
+        match flock(fd, nonblocking) {
+            Ok(()) => return Ok(()),
+            Err(Errno::EWOULDBLOCK) => {}
+            Err(e) => return Err(Error::Flock(e)),
+        }
+
+        if !self.wait {
+            return Err(Error::Locked);
+        }
+
+        eprintln!("Waiting for another moss process to release the installation lock...");
+
+        // `file` outlives the blocking call since we await it
+        task::spawn_blocking(move || flock(fd, blocking))
+            .await
+            .expect("join handle")
+            .map_err(Error::Flock)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Installation is locked by another moss process")]
+    Locked,
+    #[error("flock")]
+    Flock(#[source] Errno),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::{self, test::Root, Client};
+
+    #[tokio::test]
+    async fn test_conflicts() {
+        let root = Root::new("lock");
+        let installation = Installation::open(&root.0);
+
+        let exclusive = Lock::acquire(&installation, Kind::Exclusive, false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            Lock::acquire(&installation, Kind::Exclusive, false).await,
+            Err(Error::Locked)
+        ));
+        assert!(matches!(
+            Lock::acquire(&installation, Kind::Shared, false).await,
+            Err(Error::Locked)
+        ));
+
+        // Readers don't conflict with each other once downgraded
+        exclusive.shared().await.unwrap();
+        let shared = Lock::acquire(&installation, Kind::Shared, false)
+            .await
+            .unwrap();
+        assert!(matches!(exclusive.exclusive().await, Err(Error::Locked)));
+
+        drop(shared);
+        assert!(exclusive.exclusive().await.unwrap());
+        drop(exclusive);
+
+        Lock::acquire(&installation, Kind::Exclusive, false)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_after_state_changed() {
+        let root = Root::new("lock-upgrade");
+        drop(root.installed().await);
+
+        let options = client::Options {
+            offline: true,
+            lock: Kind::Shared,
+            ..Default::default()
+        };
+        let client = Client::with_options("test", &root.0, options)
+            .await
+            .unwrap();
+
+        // Another process applied a new state while we only read
+        std::fs::write(root.0.join("usr/.stateID"), "2").unwrap();
+
+        assert!(matches!(
+            client.lock_exclusive().await,
+            Err(client::Error::StateChanged)
+        ));
+    }
+}
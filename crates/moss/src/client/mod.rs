@@ -3,20 +3,23 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use std::{
+    collections::HashMap,
+    fs::File,
     io,
-    os::fd::RawFd,
+    os::fd::{FromRawFd, RawFd},
     path::{Path, PathBuf},
     time::Duration,
 };
 
 use futures::{future::try_join_all, stream, StreamExt, TryStreamExt};
 use itertools::Itertools;
+use log::warn;
 use nix::{
     errno::Errno,
-    fcntl::{self, OFlag},
+    fcntl::{self, AtFlags, OFlag},
     libc::{syscall, SYS_renameat2, AT_FDCWD, RENAME_EXCHANGE},
-    sys::stat::{fchmodat, mkdirat, Mode},
-    unistd::{close, linkat, mkdir, symlinkat},
+    sys::stat::{fchmodat, fstatat, mkdirat, mknodat, Mode, SFlag},
+    unistd::{close, fchownat, linkat, mkdir, mkfifoat, symlinkat, FchownatFlags, Gid, Uid},
 };
 use stone::{payload::layout, read::PayloadKind};
 use thiserror::Error;
@@ -25,10 +28,15 @@ use tui::{MultiProgress, ProgressBar, ProgressStyle, Stylize};
 use vfs::tree::{builder::TreeBuilder, BlitFile, Element};
 
 use self::install::install;
+use self::journal::Journal;
 use self::prune::prune;
 use crate::{
     db, environment, package,
-    registry::plugin::{self, Plugin},
+    registry::{
+        job::{self, Job},
+        pin,
+        plugin::{self, Plugin},
+    },
     repository,
     state::{self, Selection},
     Installation, Package, Registry, State,
@@ -36,7 +44,12 @@ use crate::{
 
 pub mod cache;
 pub mod install;
+pub mod journal;
+pub mod lock;
+pub mod plan;
+pub mod postblit;
 pub mod prune;
+pub mod verify;
 
 /// A Client is a connection to the underlying package management systems
 pub struct Client {
@@ -51,7 +64,28 @@ pub struct Client {
 
     config: config::Manager,
     repositories: repository::Manager,
+    cobble: plugin::Cobble,
     scope: Scope,
+    /// Never touch the network, only using cached indexes and downloads
+    offline: bool,
+    /// Held for the lifetime of the client, upgraded to
+    /// exclusive for mutating operations
+    lock: lock::Lock,
+}
+
+/// Options for constructing a [`Client`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Never touch the network, only using cached indexes and downloads
+    pub offline: bool,
+    /// Lock to hold on the installation, exclusive by default. Mutating
+    /// operations of a shared client upgrade to an exclusive lock, failing
+    /// if another process changed the active state in between
+    pub lock: lock::Kind,
+    /// Fail instead of waiting if another process holds the installation lock
+    pub no_wait: bool,
+    /// Leave an interrupted transaction alone instead of recovering it
+    pub no_recover: bool,
 }
 
 impl Client {
@@ -60,7 +94,31 @@ impl Client {
         client_name: impl ToString,
         root: impl Into<PathBuf>,
     ) -> Result<Client, Error> {
-        Self::build(client_name, root, None).await
+        Self::build(client_name, root, None, Options::default()).await
+    }
+
+    /// Construct a new Client which never touches the network
+    ///
+    /// Resolution only uses repository indexes which have already been
+    /// fetched, and packages can only be installed if they're already cached
+    pub async fn new_offline(
+        client_name: impl ToString,
+        root: impl Into<PathBuf>,
+    ) -> Result<Client, Error> {
+        let options = Options {
+            offline: true,
+            ..Default::default()
+        };
+        Self::build(client_name, root, None, options).await
+    }
+
+    /// Construct a new Client with the provided [`Options`]
+    pub async fn with_options(
+        client_name: impl ToString,
+        root: impl Into<PathBuf>,
+        options: Options,
+    ) -> Result<Client, Error> {
+        Self::build(client_name, root, None, options).await
     }
 
     /// Construct a new Client with explicit repositories
@@ -69,13 +127,14 @@ impl Client {
         root: impl Into<PathBuf>,
         repositories: repository::Map,
     ) -> Result<Client, Error> {
-        Self::build(client_name, root, Some(repositories)).await
+        Self::build(client_name, root, Some(repositories), Options::default()).await
     }
 
     async fn build(
         client_name: impl ToString,
         root: impl Into<PathBuf>,
         repositories: Option<repository::Map>,
+        options: Options,
     ) -> Result<Client, Error> {
         let root = root.into();
 
@@ -83,9 +142,21 @@ impl Client {
             return Err(Error::RootInvalid);
         }
 
+        let Options {
+            offline,
+            lock: lock_kind,
+            no_wait,
+            no_recover,
+        } = options;
+
         let name = client_name.to_string();
         let config = config::Manager::system(&root, "moss");
-        let installation = Installation::open(root);
+        let mut installation = Installation::open(root);
+        // Lock before touching any of the databases. Initializing them
+        // may write, so a shared lock is only taken once it's done
+        let lock = lock::Lock::acquire(&installation, lock::Kind::Exclusive, !no_wait).await?;
+        // Another process may have changed the active state while we waited
+        installation.reload_active_state();
         let install_db =
             db::meta::Database::new(installation.db_path("install"), installation.read_only())
                 .await?;
@@ -97,31 +168,97 @@ impl Client {
         } else {
             repository::Manager::system(config.clone(), installation.clone()).await?
         };
-        repositories.ensure_all_initialized().await?;
+        if !offline {
+            repositories.ensure_all_initialized().await?;
+        }
 
-        let registry = build_registry(&installation, &repositories, &install_db, &state_db).await?;
+        let cobble = plugin::Cobble::default();
+        let registry = build_registry(
+            &config,
+            &installation,
+            &repositories,
+            &cobble,
+            &install_db,
+            &state_db,
+            &layout_db,
+        )
+        .await?;
 
-        Ok(Client {
+        let client = Client {
             name,
             config,
             installation,
             repositories,
+            cobble,
             registry,
             install_db,
             state_db,
             layout_db,
             scope: Scope::Stateful,
-        })
+            offline,
+            lock,
+        };
+
+        if !no_recover && Journal::load(&client.installation).await?.is_some() {
+            if client.installation.read_only() {
+                warn!("Unable to recover the interrupted transaction of a read-only installation");
+            } else if let Some(recovery) = client.recover().await? {
+                eprintln!("{recovery}");
+            }
+        }
+
+        if lock_kind == lock::Kind::Shared {
+            client.lock.shared().await?;
+        }
+
+        Ok(client)
     }
 
     pub fn is_ephemeral(&self) -> bool {
         matches!(self.scope, Scope::Ephemeral { .. })
     }
 
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Finish or roll back a transaction which was interrupted, i.e.
+    /// by a crash or power loss, returning what was done
+    pub async fn recover(&self) -> Result<Option<journal::Recovery>, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        self.lock_exclusive().await?;
+
+        journal::recover(self).await
+    }
+
+    /// Take an exclusive lock on the installation, waiting on (or failing
+    /// against) other moss processes using it. Mutating operations do
+    /// this themselves, it's only needed to guard reads preceding them
+    ///
+    /// Fails if the client was shared and another process changed the
+    /// active state before the lock was upgraded
+    pub async fn lock_exclusive(&self) -> Result<(), Error> {
+        if self.lock.exclusive().await?
+            && self.installation.read_active_state() != self.installation.active_state
+        {
+            return Err(Error::StateChanged);
+        }
+
+        Ok(())
+    }
+
     pub async fn install(&mut self, packages: &[&str], yes: bool) -> Result<(), install::Error> {
         install(self, packages, yes).await
     }
 
+    /// Resolve the [`Plan`](plan::Plan) of installing `packages` without applying it
+    pub async fn plan_install(&mut self, packages: &[&str]) -> Result<plan::Plan, install::Error> {
+        install::plan(self, packages).await
+    }
+
     /// Transition to an ephemeral client that doesn't record state changes
     /// and blits to a different root.
     ///
@@ -146,41 +283,112 @@ impl Client {
     /// Reload all configured repositories and refreshes their index file, then update
     /// registry with all active repositories.
     pub async fn refresh_repositories(&mut self) -> Result<(), Error> {
+        self.refresh_repositories_with(repository::Map::default())
+            .await
+    }
+
+    /// Like [`Client::refresh_repositories`], also using `unsaved` repositories
+    /// which aren't in the configuration, i.e. to plan against them before
+    /// they're saved
+    pub async fn refresh_repositories_with(
+        &mut self,
+        unsaved: repository::Map,
+    ) -> Result<(), Error> {
+        if self.offline {
+            return Err(Error::OfflineProhibitedOperation);
+        }
+
+        self.lock_exclusive().await?;
+
         // Reload manager if not explicit to pickup config changes
         // then refresh indexes
         if !self.repositories.is_explicit() {
             self.repositories =
                 repository::Manager::system(self.config.clone(), self.installation.clone()).await?
         };
+        for (id, repository) in unsaved {
+            self.repositories.use_repository(id, repository).await?;
+        }
         self.repositories.refresh_all().await?;
 
         // Rebuild registry
         self.registry = build_registry(
+            &self.config,
             &self.installation,
             &self.repositories,
+            &self.cobble,
             &self.install_db,
             &self.state_db,
+            &self.layout_db,
         )
         .await?;
 
         Ok(())
     }
 
-    /// Prune states with the provided [`prune::Strategy`]
-    pub async fn prune(&self, strategy: prune::Strategy) -> Result<(), Error> {
+    /// Load local stone files into the registry so they can be resolved
+    /// alongside the configured repositories, returning their ids
+    pub async fn load_local_packages(
+        &mut self,
+        paths: impl IntoIterator<Item = impl Into<PathBuf>>,
+    ) -> Result<Vec<package::Id>, Error> {
+        let mut ids = vec![];
+
+        for path in paths {
+            ids.push(self.cobble.add_package(path).await?.into());
+        }
+
+        // Rebuild registry
+        self.registry = build_registry(
+            &self.config,
+            &self.installation,
+            &self.repositories,
+            &self.cobble,
+            &self.install_db,
+            &self.state_db,
+            &self.layout_db,
+        )
+        .await?;
+
+        Ok(ids)
+    }
+
+    /// Prune states with the provided [`prune::Strategy`],
+    /// returning the bytes reclaimed from disk & states kept
+    pub async fn prune(&self, strategy: prune::Strategy) -> Result<prune::Pruned, Error> {
         if self.scope.is_ephemeral() {
             return Err(Error::EphemeralProhibitedOperation);
         }
 
-        prune(
+        self.lock_exclusive().await?;
+
+        Ok(prune(
             strategy,
             &self.state_db,
             &self.install_db,
             &self.layout_db,
             &self.installation,
         )
-        .await?;
-        Ok(())
+        .await?)
+    }
+
+    /// Verify all paths of the active state against the layout db
+    pub async fn verify(
+        &self,
+        progress: &ProgressBar,
+    ) -> Result<Vec<verify::Issue>, verify::Error> {
+        verify::verify(self, progress).await
+    }
+
+    /// Repair issues found by [`Client::verify`]
+    pub async fn repair(&self, issues: &[verify::Issue]) -> Result<(), verify::Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation.into());
+        }
+
+        self.lock_exclusive().await?;
+
+        verify::repair(self, issues).await
     }
 
     /// Resolves the provided id's with the underlying registry, returning
@@ -214,14 +422,45 @@ impl Client {
         selections: &[Selection],
         summary: impl ToString,
     ) -> Result<Option<State>, Error> {
+        self.lock_exclusive().await?;
+
         let old_state = self.installation.active_state;
 
+        let mut journal = match &self.scope {
+            Scope::Stateful => Some(
+                Journal::begin(
+                    &self.installation,
+                    &self.state_db,
+                    journal::Kind::Apply,
+                    None,
+                )
+                .await?,
+            ),
+            Scope::Ephemeral { .. } => None,
+        };
+
         self.blit_root(
             selections.iter().map(|s| &s.package),
             old_state.map(state::Id::next),
         )
         .await?;
 
+        // Compute triggers from the freshly blitted tree
+        let (staged, root) = match &self.scope {
+            Scope::Stateful => (
+                self.installation.staging_dir(),
+                self.installation.root.clone(),
+            ),
+            Scope::Ephemeral { blit_root } => (blit_root.clone(), blit_root.clone()),
+        };
+        let handlers = postblit::handlers(
+            &self.layout_db,
+            selections.iter().map(|s| &s.package),
+            &staged,
+            &root,
+        )
+        .await?;
+
         match &self.scope {
             Scope::Stateful => {
                 // Add to db
@@ -230,6 +469,13 @@ impl Client {
                     .add(selections, Some(summary.to_string()), None)
                     .await?;
 
+                if let Some(journal) = &mut journal {
+                    journal.to = Some(state.id);
+                    journal
+                        .advance(&self.installation, journal::Step::Recorded)
+                        .await?;
+                }
+
                 // Write state id
                 {
                     let usr = self.installation.staging_path("usr");
@@ -243,6 +489,12 @@ impl Client {
                 // Staging is only used with [`Scope::Stateful`]
                 self.promote_staging().await?;
 
+                if let Some(journal) = &mut journal {
+                    journal
+                        .advance(&self.installation, journal::Step::Swapped)
+                        .await?;
+                }
+
                 // Now we got it staged, we need working rootfs
                 create_root_links(&self.installation.root).await?;
 
@@ -250,16 +502,118 @@ impl Client {
                     self.archive_state(id).await?;
                 }
 
+                postblit::run(&root, handlers).await?;
+
+                Journal::finish(&self.installation).await?;
+
                 Ok(Some(state))
             }
             Scope::Ephemeral { blit_root } => {
                 record_os_release(blit_root, None).await?;
                 create_root_links(blit_root).await?;
+
+                postblit::run(blit_root, handlers).await?;
+
                 Ok(None)
             }
         }
     }
 
+    /// Activate a previously recorded state, swapping its archived
+    /// tree back into place or re-blitting it if no archive exists
+    pub async fn activate_state(&self, id: state::Id) -> Result<State, Error> {
+        if self.scope.is_ephemeral() {
+            return Err(Error::EphemeralProhibitedOperation);
+        }
+
+        self.lock_exclusive().await?;
+
+        let old_state = self.installation.active_state;
+
+        if old_state == Some(id) {
+            return Err(Error::StateAlreadyActive(id));
+        }
+
+        let state = self.state_db.get(&id).await?;
+
+        let mut journal = Journal::begin(
+            &self.installation,
+            &self.state_db,
+            journal::Kind::Activate,
+            Some(id),
+        )
+        .await?;
+
+        let archived = self.installation.root_path(id.to_string()).join("usr");
+        let staging = self.installation.staging_path("usr");
+
+        if archived.exists() {
+            // Clean out any leftovers from a previous transaction
+            if staging.exists() {
+                remove_dir_all(&staging).await?;
+            }
+            create_dir_all(self.installation.staging_dir()).await?;
+
+            // Journal first, so the tree is returned to its archive on rollback
+            journal.archived = true;
+            journal
+                .advance(&self.installation, journal::Step::Staging)
+                .await?;
+
+            rename(&archived, &staging).await?;
+        } else {
+            self.blit_root(state.selections.iter().map(|s| &s.package), Some(id))
+                .await?;
+        }
+
+        let handlers = postblit::handlers(
+            &self.layout_db,
+            state.selections.iter().map(|s| &s.package),
+            &self.installation.staging_dir(),
+            &self.installation.root,
+        )
+        .await?;
+
+        fs::create_dir_all(&staging).await?;
+        fs::write(staging.join(".stateID"), id.to_string()).await?;
+        record_os_release(&self.installation.staging_dir(), Some(id)).await?;
+
+        self.promote_staging().await?;
+
+        journal
+            .advance(&self.installation, journal::Step::Swapped)
+            .await?;
+
+        create_root_links(&self.installation.root).await?;
+
+        if let Some(old) = old_state {
+            self.archive_state(old).await?;
+        }
+
+        postblit::run(&self.installation.root, handlers).await?;
+
+        Journal::finish(&self.installation).await?;
+
+        Ok(state)
+    }
+
+    /// Activate the state preceding the currently active state
+    pub async fn rollback_state(&self) -> Result<State, Error> {
+        let active = self.installation.active_state.ok_or(Error::NoActiveState)?;
+
+        let previous = self
+            .state_db
+            .list_ids()
+            .await?
+            .into_iter()
+            .map(|(id, _)| i64::from(id))
+            .filter(|id| *id < i64::from(active))
+            .max()
+            .ok_or(Error::NoPreviousState(active))?;
+
+        self.activate_state(previous.into()).await
+    }
+
     /// Activate the given state
     async fn promote_staging(&self) -> Result<(), Error> {
         if self.scope.is_ephemeral() {
@@ -323,6 +677,30 @@ impl Client {
 
     /// Download & unpack the provided packages. Packages already cached will be validated & skipped.
     pub async fn cache_packages(&self, packages: &[&Package]) -> Result<(), Error> {
+        self.lock_exclusive().await?;
+
+        // Offline we can only use what's already in the cache, so make
+        // sure everything is there before starting
+        let mut offline_jobs = HashMap::new();
+        let mut unpacked = vec![];
+        if self.offline {
+            let mut missing = vec![];
+
+            for package in packages {
+                if let Some(job) = self.offline_job(package).await? {
+                    offline_jobs.insert(package.id.clone(), job);
+                } else if self.is_unpacked(package).await? {
+                    unpacked.push(package.id.clone());
+                } else {
+                    missing.push(package.meta.name.clone());
+                }
+            }
+
+            if !missing.is_empty() {
+                return Err(Error::NotCached(missing));
+            }
+        }
+
         // Setup progress bar
         let multi_progress = MultiProgress::new();
 
@@ -357,9 +735,39 @@ impl Client {
             );
             progress_bar.enable_steady_tick(Duration::from_millis(150));
 
-            // Download and update progress
-            let download = cache::fetch(&package.meta, &self.installation, |progress| {
-                progress_bar.inc(progress.delta);
+            // Assets are already in the store, so the layouts we have are still valid
+            if unpacked.contains(&package.id) {
+                self.install_db
+                    .add(package.id.clone(), package.meta.clone())
+                    .await?;
+
+                progress_bar.finish();
+                multi_progress.remove(&progress_bar);
+                multi_progress.println(format!(
+                    "{} {}{}",
+                    "Installed".green(),
+                    package.meta.name.to_string().bold(),
+                    " (cached)".dim(),
+                ))?;
+                total_progress.inc(1);
+
+                return Ok(());
+            }
+
+            // Download and update progress, the job may be a delta
+            // smaller than the package itself
+            let job = match offline_jobs.get(&package.id) {
+                Some(job) => job.clone(),
+                None => self
+                    .registry
+                    .fetch_item(&package.id)
+                    .await
+                    .ok_or(Error::MissingMetadata(package.id.clone()))?,
+            };
+            progress_bar.set_length(job.size);
+
+            let download = cache::fetch(&package.meta, &job, &self.installation, |progress| {
+                progress_bar.set_position(progress.completed);
             })
             .await?;
 
@@ -376,15 +784,46 @@ impl Client {
             progress_bar.set_position(0);
 
             // Unpack and update progress
-            let unpacked = download
-                .unpack({
-                    let progress_bar = progress_bar.clone();
+            let on_unpack = |progress_bar: ProgressBar| {
+                move |progress: cache::Progress| {
+                    progress_bar.set_position((progress.pct() * 1000.0) as u64);
+                }
+            };
+            let unpacked = match download.unpack(on_unpack(progress_bar.clone())).await {
+                // Assets the delta applies to were removed since it was
+                // chosen, so fall back to the full package
+                Err(cache::Error::MissingDeltaAssets(_)) if !self.offline => {
+                    let job = self
+                        .registry
+                        .fetch_package(&package.id)
+                        .await
+                        .ok_or(Error::MissingMetadata(package.id.clone()))?;
+
+                    progress_bar.set_message(format!(
+                        "{} {}",
+                        "Downloading".blue(),
+                        package_name.clone().bold(),
+                    ));
+                    progress_bar.set_length(job.size);
 
-                    move |progress| {
-                        progress_bar.set_position((progress.pct() * 1000.0) as u64);
-                    }
-                })
-                .await?;
+                    let download =
+                        cache::fetch(&package.meta, &job, &self.installation, |progress| {
+                            progress_bar.set_position(progress.completed);
+                        })
+                        .await?;
+
+                    progress_bar.set_message(format!(
+                        "{} {}",
+                        "Unpacking".yellow(),
+                        package_name.clone().bold(),
+                    ));
+                    progress_bar.set_length(1000);
+                    progress_bar.set_position(0);
+
+                    download.unpack(on_unpack(progress_bar.clone())).await?
+                }
+                result => result?,
+            };
 
             // Merge layoutdb
             progress_bar.set_message(format!(
@@ -439,7 +878,7 @@ impl Client {
         }))
         // Use max network concurrency since we download files here
         .buffer_unordered(environment::MAX_NETWORK_CONCURRENCY)
-        .try_collect()
+        .try_collect::<()>()
         .await?;
 
         // Remove progress
@@ -448,6 +887,68 @@ impl Client {
         Ok(())
     }
 
+    /// A job fetching `package` without the network, if it's
+    /// a local stone or its download is already cached
+    async fn offline_job(&self, package: &Package) -> Result<Option<Job>, Error> {
+        let job = self.registry.fetch_item(&package.id).await;
+
+        if let Some(job) = &job {
+            if matches!(job.origin, job::Origin::LocalFile(_)) {
+                return Ok(Some(job.clone()));
+            }
+        }
+
+        // The job may be a delta, but the full package could be cached too
+        let candidates = job
+            .into_iter()
+            .filter_map(|job| match job.check {
+                Some(job::CheckType::Sha256(hash)) => Some((job.domain, hash)),
+                _ => None,
+            })
+            .chain(
+                package
+                    .meta
+                    .hash
+                    .clone()
+                    .map(|hash| (job::Domain::Package(package.id.clone()), hash)),
+            );
+
+        for (domain, hash) in candidates {
+            if let Some(path) = cache::cached_download(&self.installation, &hash).await? {
+                let size = fs::metadata(&path).await?.len();
+
+                return Ok(Some(Job {
+                    domain,
+                    origin: job::Origin::LocalFile(path),
+                    mirrors: vec![],
+                    check: Some(job::CheckType::Sha256(hash)),
+                    size,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns true if the layouts of `package` are known and
+    /// all of its assets are in the store
+    async fn is_unpacked(&self, package: &Package) -> Result<bool, Error> {
+        let layouts = self.layout_db.query(&package.id).await?;
+        if layouts.is_empty() {
+            return Ok(false);
+        }
+
+        let digests = layouts
+            .iter()
+            .filter_map(|layout| match layout.entry {
+                layout::Entry::Regular(digest, _) => Some(digest),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        Ok(cache::assets_exist(digests, &self.installation).await)
+    }
+
     /// Blit the packages to a filesystem root
     async fn blit_root(
         &self,
@@ -463,10 +964,18 @@ impl Client {
         progress.enable_steady_tick(Duration::from_millis(150));
         progress.tick();
 
+        // Ownership can only be applied when running as root
+        let chown = Uid::effective().is_root();
+        let mut unowned = 0;
+
         let mut tbuild = TreeBuilder::new();
         for id in packages.into_iter() {
             let layouts = self.layout_db.query(id).await?;
             for layout in layouts {
+                if !chown && (layout.uid != 0 || layout.gid != 0) {
+                    unowned += 1;
+                }
+
                 tbuild.push(PendingFile {
                     id: id.clone(),
                     layout,
@@ -503,13 +1012,20 @@ impl Client {
 
             if let Element::Directory(_, _, children) = root {
                 for child in children {
-                    self.blit_element(root_dir, cache_fd, child, &progress)?;
+                    self.blit_element(root_dir, cache_fd, child, chown, &progress)?;
                 }
             }
 
             close(root_dir)?;
         }
 
+        if unowned > 0 {
+            progress.println(format!(
+                "{} not running as root, ownership of {unowned} path(s) was not applied",
+                "Warning".yellow()
+            ));
+        }
+
         Ok(())
     }
 
@@ -519,13 +1035,14 @@ impl Client {
         parent: RawFd,
         cache: RawFd,
         element: Element<PendingFile>,
+        chown: bool,
         progress: &ProgressBar,
     ) -> Result<(), Error> {
         progress.inc(1);
         match element {
             Element::Directory(name, item, children) => {
                 // Construct within the parent
-                self.blit_element_item(parent, cache, &name, item)?;
+                self.blit_element_item(parent, cache, &name, item, chown, progress)?;
 
                 // open the new dir
                 let newdir = fcntl::openat(
@@ -535,27 +1052,47 @@ impl Client {
                     Mode::empty(),
                 )?;
                 for child in children.into_iter() {
-                    self.blit_element(newdir, cache, child, progress)?;
+                    self.blit_element(newdir, cache, child, chown, progress)?;
                 }
                 close(newdir)?;
                 Ok(())
             }
             Element::Child(name, item) => {
-                self.blit_element_item(parent, cache, &name, item)?;
+                self.blit_element_item(parent, cache, &name, item, chown, progress)?;
                 Ok(())
             }
         }
     }
 
     /// Process the raw layout entry.
+    ///
+    /// When `chown` is set, ownership from the layout is applied
     fn blit_element_item(
         &self,
         parent: RawFd,
         cache: RawFd,
         subpath: &str,
         item: PendingFile,
+        chown: bool,
+        progress: &ProgressBar,
     ) -> Result<(), Error> {
-        match item.layout.entry {
+        let mode = Mode::from_bits_truncate(item.layout.mode);
+        // Changing owner clears setuid & setgid, so it must
+        // happen before any permissions are set
+        let set_owner = || -> Result<(), Error> {
+            if chown {
+                fchownat(
+                    Some(parent),
+                    subpath,
+                    Some(Uid::from_raw(item.layout.uid)),
+                    Some(Gid::from_raw(item.layout.gid)),
+                    FchownatFlags::NoFollowSymlink,
+                )?;
+            }
+            Ok(())
+        };
+
+        match &item.layout.entry {
             layout::Entry::Regular(id, _) => {
                 let hash = format!("{:02x}", id);
                 let directory = if hash.len() >= 10 {
@@ -566,40 +1103,125 @@ impl Client {
                     "".into()
                 };
 
-                // Link relative from cache to target
                 let fp = directory.join(hash);
-                linkat(
-                    Some(cache),
-                    fp.to_str().unwrap(),
-                    Some(parent),
-                    subpath,
-                    nix::unistd::LinkatFlags::NoSymlinkFollow,
-                )?;
+                let fp = fp.to_str().unwrap();
+
+                // Ownership belongs to the inode, shared by the asset and every
+                // file with the same content, so a file owned differently gets
+                // its own copy rather than changing the owner of them all
+                let owned = chown && {
+                    let asset = fstatat(cache, fp, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+                    asset.st_uid != item.layout.uid || asset.st_gid != item.layout.gid
+                };
 
-                // Fix permissions
+                if owned {
+                    Self::copy_asset(cache, fp, parent, subpath)?;
+                    set_owner()?;
+                } else {
+                    // Link relative from cache to target
+                    linkat(
+                        Some(cache),
+                        fp,
+                        Some(parent),
+                        subpath,
+                        nix::unistd::LinkatFlags::NoSymlinkFollow,
+                    )?;
+                }
+
+                // Fix permissions, which like ownership are shared with the
+                // asset when linked
                 fchmodat(
                     Some(parent),
                     subpath,
-                    Mode::from_bits_truncate(item.layout.mode),
+                    mode,
                     nix::sys::stat::FchmodatFlags::NoFollowSymlink,
                 )?;
             }
             layout::Entry::Symlink(source, _) => {
                 symlinkat(source.as_str(), Some(parent), subpath)?;
+                set_owner()?;
             }
             layout::Entry::Directory(_) => {
-                mkdirat(parent, subpath, Mode::from_bits_truncate(item.layout.mode))?;
+                mkdirat(parent, subpath, mode)?;
+
+                if chown {
+                    set_owner()?;
+                    // Restore any setgid bit for shared directories
+                    Self::set_permissions(parent, subpath, mode)?;
+                }
             }
+            layout::Entry::CharacterDevice(device, target)
+            | layout::Entry::BlockDevice(device, target) => {
+                let kind = if let layout::Entry::CharacterDevice(..) = &item.layout.entry {
+                    SFlag::S_IFCHR
+                } else {
+                    SFlag::S_IFBLK
+                };
 
-            // unimplemented
-            layout::Entry::CharacterDevice(_) => todo!(),
-            layout::Entry::BlockDevice(_) => todo!(),
-            layout::Entry::Fifo(_) => todo!(),
-            layout::Entry::Socket(_) => todo!(),
+                match mknodat(parent, subpath, kind, mode, *device) {
+                    Ok(()) => {
+                        set_owner()?;
+                        Self::set_permissions(parent, subpath, mode)?;
+                    }
+                    // Device nodes require CAP_MKNOD, so rootless we can't
+                    // do any better than leaving them out
+                    Err(Errno::EPERM) => progress.println(format!(
+                        "{} skipping device node /usr/{target}, requires root",
+                        "Warning".yellow()
+                    )),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            layout::Entry::Fifo(_) => {
+                mkfifoat(Some(parent), subpath, mode)?;
+                set_owner()?;
+                Self::set_permissions(parent, subpath, mode)?;
+            }
+            layout::Entry::Socket(_) => {
+                mknodat(parent, subpath, SFlag::S_IFSOCK, mode, 0)?;
+                set_owner()?;
+                Self::set_permissions(parent, subpath, mode)?;
+            }
         };
 
         Ok(())
     }
+
+    /// Copy the asset at `path` within `cache` to a new file at `subpath` within `parent`
+    fn copy_asset(cache: RawFd, path: &str, parent: RawFd, subpath: &str) -> Result<(), Error> {
+        let open = |dir, path, flags| -> Result<File, Error> {
+            let fd = fcntl::openat(
+                dir,
+                path,
+                flags | OFlag::O_CLOEXEC,
+                Mode::from_bits_truncate(0o600),
+            )?;
+            // Safety: the fd was just opened and is owned by nothing else
+            Ok(unsafe { File::from_raw_fd(fd) })
+        };
+
+        let mut source = open(cache, path, OFlag::O_RDONLY)?;
+        let mut target = open(
+            parent,
+            subpath,
+            OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_EXCL,
+        )?;
+        io::copy(&mut source, &mut target)?;
+
+        Ok(())
+    }
+
+    /// Nodes are created subject to the umask, so
+    /// apply the requested mode explicitly
+    fn set_permissions(parent: RawFd, subpath: &str, mode: Mode) -> Result<(), Error> {
+        fchmodat(
+            Some(parent),
+            subpath,
+            mode,
+            nix::sys::stat::FchmodatFlags::NoFollowSymlink,
+        )?;
+        Ok(())
+    }
 }
 
 /// Add root symlinks & os-release file
@@ -650,7 +1272,10 @@ BUG_REPORT_URL="https://github.com/serpent-os""#,
         tx = state_id.unwrap_or_default()
     );
 
-    fs::write(root.join("usr").join("lib").join("os-release"), os_release).await?;
+    // Not every package set ships `/usr/lib`
+    let lib = root.join("usr").join("lib");
+    fs::create_dir_all(&lib).await?;
+    fs::write(lib.join("os-release"), os_release).await?;
 
     Ok(())
 }
@@ -694,8 +1319,8 @@ impl BlitFile for PendingFile {
             layout::Entry::Regular(_, target) => target.clone(),
             layout::Entry::Symlink(_, target) => target.clone(),
             layout::Entry::Directory(target) => target.clone(),
-            layout::Entry::CharacterDevice(target) => target.clone(),
-            layout::Entry::BlockDevice(target) => target.clone(),
+            layout::Entry::CharacterDevice(_, target) => target.clone(),
+            layout::Entry::BlockDevice(_, target) => target.clone(),
             layout::Entry::Fifo(target) => target.clone(),
             layout::Entry::Socket(target) => target.clone(),
         };
@@ -710,8 +1335,10 @@ impl BlitFile for PendingFile {
             layout::Entry::Regular(source, _) => layout::Entry::Regular(*source, strpath),
             layout::Entry::Symlink(source, _) => layout::Entry::Symlink(source.clone(), strpath),
             layout::Entry::Directory(_) => layout::Entry::Directory(strpath),
-            layout::Entry::CharacterDevice(_) => layout::Entry::CharacterDevice(strpath),
-            layout::Entry::BlockDevice(_) => layout::Entry::BlockDevice(strpath),
+            layout::Entry::CharacterDevice(device, _) => {
+                layout::Entry::CharacterDevice(*device, strpath)
+            }
+            layout::Entry::BlockDevice(device, _) => layout::Entry::BlockDevice(*device, strpath),
             layout::Entry::Fifo(_) => layout::Entry::Fifo(strpath),
             layout::Entry::Socket(_) => layout::Entry::Socket(strpath),
         };
@@ -735,10 +1362,13 @@ impl From<PathBuf> for PendingFile {
 }
 
 async fn build_registry(
+    config: &config::Manager,
     installation: &Installation,
     repositories: &repository::Manager,
+    cobble: &plugin::Cobble,
     installdb: &db::meta::Database,
     statedb: &db::state::Database,
+    layoutdb: &db::layout::Database,
 ) -> Result<Registry, Error> {
     let state = match installation.active_state {
         Some(id) => Some(statedb.get(&id).await?),
@@ -747,16 +1377,23 @@ async fn build_registry(
 
     let mut registry = Registry::default();
 
-    registry.add_plugin(Plugin::Cobble(plugin::Cobble::default()));
+    registry.add_plugin(Plugin::Cobble(cobble.clone()));
     registry.add_plugin(Plugin::Active(plugin::Active::new(
         state,
         installdb.clone(),
     )));
 
     for repo in repositories.active() {
-        registry.add_plugin(Plugin::Repository(plugin::Repository::new(repo)));
+        registry.add_plugin(Plugin::Repository(plugin::Repository::new(
+            repo,
+            installation.clone(),
+            layoutdb.clone(),
+        )));
     }
 
+    // Ignoring broken pins could silently replace held packages
+    registry.set_pins(config.try_load::<pin::Rules>().await?.unwrap_or_default());
+
     Ok(registry)
 }
 
@@ -772,10 +1409,26 @@ pub enum Error {
     EphemeralInstallationRoot,
     #[error("Operation not allowed with ephemeral client")]
     EphemeralProhibitedOperation,
+    #[error("Operation requires the network, which isn't allowed when offline")]
+    OfflineProhibitedOperation,
+    #[error("Offline and not cached: {}", .0.iter().join(", "))]
+    NotCached(Vec<package::Name>),
+    #[error("State {0} is already active")]
+    StateAlreadyActive(state::Id),
+    #[error("No active state")]
+    NoActiveState,
+    #[error("Active state was changed by another moss process, try again")]
+    StateChanged,
+    #[error("No state found prior to state {0}")]
+    NoPreviousState(state::Id),
     #[error("cache")]
     Cache(#[from] cache::Error),
     #[error("repository manager")]
     Repository(#[from] repository::manager::Error),
+    #[error("load config")]
+    LoadConfig(#[from] config::LoadError),
+    #[error("local package")]
+    Cobble(#[from] plugin::cobble::Error),
     #[error("meta db")]
     Meta(#[from] db::meta::Error),
     #[error("layout db")]
@@ -784,6 +1437,12 @@ pub enum Error {
     State(#[from] db::state::Error),
     #[error("prune")]
     Prune(#[from] prune::Error),
+    #[error("lock")]
+    Lock(#[from] lock::Error),
+    #[error("journal")]
+    Journal(#[from] journal::Error),
+    #[error("triggers")]
+    Postblit(#[from] postblit::Error),
     #[error("io")]
     Io(#[from] io::Error),
     #[error("filesystem")]
@@ -791,3 +1450,108 @@ pub enum Error {
     #[error("blit")]
     Blit(#[from] Errno),
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::path::PathBuf;
+
+    use super::Client;
+
+    pub const PACKAGE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../test/bash-completion-2.11-1-1-x86_64.stone"
+    );
+
+    /// A temporary installation root, removed once dropped
+    pub struct Root(pub PathBuf);
+
+    impl Root {
+        pub fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("moss-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        /// Open an offline client on the root
+        pub async fn client(&self) -> Client {
+            Client::new_offline("test", &self.0).await.unwrap()
+        }
+
+        /// Install the test package, returning a client reopened on the new
+        /// state which can still fetch the package
+        pub async fn installed(&self) -> Client {
+            self.client().await.install(&[PACKAGE], true).await.unwrap();
+
+            let mut client = self.client().await;
+            client.load_local_packages([PACKAGE]).await.unwrap();
+            client
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blit_owners() {
+        use std::os::unix::fs::MetadataExt;
+
+        use nix::{
+            fcntl::{self, OFlag},
+            sys::stat::Mode,
+            unistd::Uid,
+        };
+        use stone::payload::layout;
+        use tui::ProgressBar;
+
+        use super::PendingFile;
+        use crate::package;
+
+        // Ownership is only applied as root
+        if !Uid::effective().is_root() {
+            return;
+        }
+
+        let root = Root::new("blit-owners");
+        let client = root.client().await;
+
+        let cache = root.0.join("cache");
+        let out = root.0.join("out");
+        std::fs::create_dir_all(&cache).unwrap();
+        std::fs::create_dir_all(&out).unwrap();
+        std::fs::write(cache.join("ab"), "content").unwrap();
+
+        let open = |path| fcntl::open(path, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty());
+        let (cache_fd, out_fd) = (open(&cache).unwrap(), open(&out).unwrap());
+
+        // Two files of the same content, owned differently
+        for (name, owner) in [("root", 0), ("service", 1000)] {
+            let item = PendingFile {
+                id: package::Id::from(name.to_string()),
+                layout: layout::Layout {
+                    uid: owner,
+                    gid: owner,
+                    mode: 0o644,
+                    tag: 0,
+                    entry: layout::Entry::Regular(0xab, name.to_string()),
+                },
+            };
+            client
+                .blit_element_item(out_fd, cache_fd, name, item, true, &ProgressBar::hidden())
+                .unwrap();
+        }
+
+        let asset = std::fs::metadata(cache.join("ab")).unwrap();
+        let linked = std::fs::metadata(out.join("root")).unwrap();
+        let copied = std::fs::metadata(out.join("service")).unwrap();
+
+        assert_eq!(linked.ino(), asset.ino());
+        assert_ne!(copied.ino(), asset.ino());
+        assert_eq!((asset.uid(), asset.gid()), (0, 0));
+        assert_eq!((copied.uid(), copied.gid()), (1000, 1000));
+        assert_eq!(std::fs::read(out.join("service")).unwrap(), b"content");
+    }
+}
@@ -0,0 +1,293 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use serde::Serialize;
+use tui::{BinaryBytes, Stylize};
+
+use crate::{package, Package};
+
+/// The changes a transaction makes when moving from one
+/// package set to another
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    /// Bytes to download for packages not yet installed, at most. Some
+    /// may already be cached or fetched as a smaller delta
+    pub download_size: u64,
+    /// Bytes the installation grows (or shrinks) by
+    pub installed_size: i64,
+    /// Number of packages which didn't record their sizes
+    pub unknown_sizes: usize,
+}
+
+/// A single package change, by name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    pub name: package::Name,
+    pub kind: Kind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Added,
+    Removed,
+    Upgraded,
+    Downgraded,
+    /// Same version, different build (i.e. a local rebuild)
+    Reinstalled,
+}
+
+impl Kind {
+    fn label(&self) -> &'static str {
+        match self {
+            Kind::Added => "Added",
+            Kind::Removed => "Removed",
+            Kind::Upgraded => "Upgraded",
+            Kind::Downgraded => "Downgraded",
+            Kind::Reinstalled => "Reinstalled",
+        }
+    }
+}
+
+impl Plan {
+    /// Compare the `previous` package set against the `next` one
+    pub fn new(previous: &[Package], next: &[Package]) -> Self {
+        let previous_names = by_name(previous);
+        let next_names = by_name(next);
+
+        let mut changes = vec![];
+
+        for (name, old) in &previous_names {
+            if !next_names.contains_key(name) {
+                changes.push(Change {
+                    name: name.clone(),
+                    kind: Kind::Removed,
+                    from: Some(version(old)),
+                    to: None,
+                });
+            }
+        }
+        for (name, new) in &next_names {
+            let Some(old) = previous_names.get(name) else {
+                changes.push(Change {
+                    name: name.clone(),
+                    kind: Kind::Added,
+                    from: None,
+                    to: Some(version(new)),
+                });
+                continue;
+            };
+            if old.id == new.id {
+                continue;
+            }
+
+            let kind = match (new.meta.source_release, new.meta.build_release)
+                .cmp(&(old.meta.source_release, old.meta.build_release))
+            {
+                Ordering::Greater => Kind::Upgraded,
+                Ordering::Less => Kind::Downgraded,
+                Ordering::Equal => Kind::Reinstalled,
+            };
+
+            changes.push(Change {
+                name: name.clone(),
+                kind,
+                from: Some(version(old)),
+                to: Some(version(new)),
+            });
+        }
+
+        changes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let is_previous = |p: &Package| previous.iter().any(|o| o.id == p.id);
+        let is_next = |p: &Package| next.iter().any(|n| n.id == p.id);
+
+        let fetched = next.iter().filter(|p| !is_previous(p)).collect::<Vec<_>>();
+        let dropped = previous.iter().filter(|p| !is_next(p)).collect::<Vec<_>>();
+
+        let download_size = fetched.iter().filter_map(|p| p.meta.download_size).sum();
+        let installed_size = fetched
+            .iter()
+            .filter_map(|p| p.meta.installed_size)
+            .sum::<u64>() as i64
+            - dropped
+                .iter()
+                .filter_map(|p| p.meta.installed_size)
+                .sum::<u64>() as i64;
+        let unknown_sizes = fetched
+            .iter()
+            .filter(|p| p.meta.download_size.is_none() || p.meta.installed_size.is_none())
+            .chain(dropped.iter().filter(|p| p.meta.installed_size.is_none()))
+            .count();
+
+        Self {
+            changes,
+            download_size,
+            installed_size,
+            unknown_sizes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Print the plan to stdout
+    pub fn print(&self) {
+        if self.is_empty() {
+            println!("No changes");
+            return;
+        }
+
+        self.print_changes();
+
+        let sign = if self.installed_size < 0 { "-" } else { "+" };
+
+        println!();
+        println!("Download size: {}", BinaryBytes(self.download_size));
+        println!(
+            "Net installed size: {sign}{}",
+            BinaryBytes(self.installed_size.unsigned_abs())
+        );
+        if self.unknown_sizes > 0 {
+            println!(
+                "{} {} package(s) didn't record their sizes and aren't included",
+                "Warning".yellow(),
+                self.unknown_sizes
+            );
+        }
+    }
+
+    /// Print each change to stdout, one per line
+    pub fn print_changes(&self) {
+        let width = self
+            .changes
+            .iter()
+            .map(|change| change.name.to_string().len())
+            .max()
+            .unwrap_or_default();
+
+        for change in &self.changes {
+            let label = format!("{:<11}", change.kind.label());
+            let label = match change.kind {
+                Kind::Added => label.green(),
+                Kind::Removed => label.red(),
+                Kind::Upgraded => label.cyan(),
+                Kind::Downgraded => label.yellow(),
+                Kind::Reinstalled => label.blue(),
+            };
+            let name = format!("{:<width$}", change.name.to_string());
+
+            let versions = match (&change.from, &change.to) {
+                (Some(from), Some(to)) => {
+                    format!("{} -> {}", from.clone().dim(), to.clone().magenta())
+                }
+                (Some(version), None) => version.clone().dim().to_string(),
+                (None, Some(version)) => version.clone().magenta().to_string(),
+                (None, None) => String::new(),
+            };
+
+            println!("{label} {} {versions}", name.bold());
+        }
+    }
+}
+
+fn by_name(packages: &[Package]) -> BTreeMap<package::Name, &Package> {
+    packages.iter().map(|p| (p.meta.name.clone(), p)).collect()
+}
+
+/// Full version of a package
+fn version(package: &Package) -> String {
+    format!(
+        "{}-{}-{}",
+        package.meta.version_identifier, package.meta.source_release, package.meta.build_release
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn package(name: &str, source_release: u64, build_release: u64, size: u64) -> Package {
+        Package {
+            id: package::Id::from(format!("{name}-{source_release}-{build_release}")),
+            meta: package::Meta {
+                name: package::Name::from(name.to_string()),
+                version_identifier: "1.0".to_string(),
+                source_release,
+                build_release,
+                architecture: Default::default(),
+                summary: Default::default(),
+                description: Default::default(),
+                source_id: Default::default(),
+                homepage: Default::default(),
+                licenses: Default::default(),
+                dependencies: Default::default(),
+                providers: Default::default(),
+                conflicts: Default::default(),
+                uri: Default::default(),
+                hash: Default::default(),
+                download_size: Some(size / 2),
+                installed_size: Some(size),
+            },
+            flags: package::Flags::NONE,
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let previous = vec![
+            package("kept", 1, 1, 10),
+            package("removed", 1, 1, 100),
+            package("upgraded", 1, 1, 20),
+            package("downgraded", 2, 1, 30),
+            package("rebuilt", 1, 1, 40),
+        ];
+        let next = vec![
+            package("kept", 1, 1, 10),
+            package("added", 1, 1, 50),
+            package("upgraded", 1, 2, 24),
+            package("downgraded", 1, 1, 30),
+            // Same version, different build
+            Package {
+                id: package::Id::from("rebuilt-1-1-local".to_string()),
+                ..package("rebuilt", 1, 1, 40)
+            },
+        ];
+
+        let plan = Plan::new(&previous, &next);
+
+        let kinds = plan
+            .changes
+            .iter()
+            .map(|c| (c.name.to_string(), c.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ("added".to_string(), Kind::Added),
+                ("downgraded".to_string(), Kind::Downgraded),
+                ("rebuilt".to_string(), Kind::Reinstalled),
+                ("removed".to_string(), Kind::Removed),
+                ("upgraded".to_string(), Kind::Upgraded),
+            ]
+        );
+        assert_eq!(plan.changes[4].from.as_deref(), Some("1.0-1-1"));
+        assert_eq!(plan.changes[4].to.as_deref(), Some("1.0-1-2"));
+        // added + upgraded + downgraded + rebuilt
+        assert_eq!(plan.download_size, 25 + 12 + 15 + 20);
+        assert_eq!(
+            plan.installed_size,
+            (50 + 24 + 30 + 40) - (100 + 20 + 30 + 40)
+        );
+        assert_eq!(plan.unknown_sizes, 0);
+    }
+}
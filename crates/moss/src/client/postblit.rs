@@ -0,0 +1,160 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Operations that happen post-blit, primarily running the
+//! system triggers against the newly blitted `/usr`
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use container::Container;
+use stone::payload::layout;
+use thiserror::Error;
+use tokio::{fs, task};
+use triggers::{
+    format::{PathKind, Trigger},
+    CompiledHandler,
+};
+use tui::Stylize;
+
+use crate::{db, package};
+
+/// Trigger definitions, relative to the root of the tree
+const TRIGGER_DIR: &str = "usr/share/moss/triggers";
+
+/// Compute the handlers to run for the tree blitted from `packages`
+///
+/// Trigger definitions are loaded from the blitted tree at `staged`, while
+/// inhibitors are checked against the `root` the handlers will run in.
+pub async fn handlers(
+    layout_db: &db::layout::Database,
+    packages: impl IntoIterator<Item = &package::Id>,
+    staged: &Path,
+    root: &Path,
+) -> Result<Vec<CompiledHandler>, Error> {
+    let triggers = load_triggers(&staged.join(TRIGGER_DIR)).await?;
+
+    if triggers.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut manager = triggers::Manager::new(triggers)?;
+
+    for id in packages {
+        for layout in layout_db.query(id).await? {
+            let (target, kind) = match &layout.entry {
+                layout::Entry::Directory(target) => (target, Some(PathKind::Directory)),
+                layout::Entry::Symlink(_, target) => (target, Some(PathKind::Symlink)),
+                layout::Entry::Regular(_, target)
+                | layout::Entry::CharacterDevice(_, target)
+                | layout::Entry::BlockDevice(_, target)
+                | layout::Entry::Fifo(target)
+                | layout::Entry::Socket(target) => (target, None),
+            };
+
+            manager.push_path(&format!("/usr/{target}"), kind);
+        }
+    }
+
+    Ok(manager.handlers(root, &environment(root)))
+}
+
+/// Run the handlers against `root`
+///
+/// Handlers for any root other than the host `/` are isolated within a
+/// container of that root. A failing handler is reported but doesn't
+/// prevent the remaining handlers from running.
+pub async fn run(root: &Path, handlers: Vec<CompiledHandler>) -> Result<(), Error> {
+    if handlers.is_empty() {
+        return Ok(());
+    }
+
+    let root = root.to_owned();
+
+    task::spawn_blocking(move || {
+        let run_all = || {
+            for handler in &handlers {
+                if let Err(error) = handler.run(Path::new("/")) {
+                    eprintln!(
+                        "{} trigger {} failed: {error}",
+                        "Warning".yellow(),
+                        handler.trigger().bold()
+                    );
+                }
+            }
+
+            Ok(()) as Result<(), triggers::Error>
+        };
+
+        if is_host_root(&root) {
+            run_all()?;
+        } else {
+            Container::new(&root).run(run_all)?;
+        }
+
+        Ok(())
+    })
+    .await
+    .expect("join handle")
+}
+
+/// Load all `.yaml` trigger definitions within `dir`
+async fn load_triggers(dir: &Path) -> Result<Vec<Trigger>, Error> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut paths = vec![];
+    let mut read_dir = fs::read_dir(dir).await?;
+
+    while let Some(entry) = read_dir.next_entry().await? {
+        let path = entry.path();
+
+        if path.extension().and_then(|s| s.to_str()) == Some("yaml") {
+            paths.push(path);
+        }
+    }
+
+    // Stable handler ordering when triggers don't specify constraints
+    paths.sort();
+
+    let mut triggers = vec![];
+
+    for path in paths {
+        let bytes = fs::read(&path).await?;
+        let trigger = serde_yaml::from_slice(&bytes).map_err(|e| Error::Deserialize(path, e))?;
+        triggers.push(trigger);
+    }
+
+    Ok(triggers)
+}
+
+/// Environment names used to match trigger inhibitors
+fn environment(root: &Path) -> Vec<&'static str> {
+    if is_host_root(root) {
+        vec![]
+    } else {
+        vec!["chroot"]
+    }
+}
+
+fn is_host_root(root: &Path) -> bool {
+    root.canonicalize().ok() == Some(PathBuf::from("/"))
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("deserialize trigger {0:?}")]
+    Deserialize(PathBuf, #[source] serde_yaml::Error),
+    #[error("triggers")]
+    Triggers(#[from] triggers::Error),
+    #[error("container")]
+    Container(#[from] container::Error),
+    #[error("layout db")]
+    Layout(#[from] db::layout::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
@@ -5,6 +5,7 @@
 use std::{
     collections::{HashMap, HashSet},
     io,
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
 };
 
@@ -46,19 +47,29 @@ impl Status {
     }
 }
 
+/// Outcome of pruning states
+#[derive(Debug, Default)]
+pub struct Pruned {
+    /// Bytes reclaimed from disk
+    pub reclaimed: u64,
+    /// States which were due for removal but had to be kept, and why
+    pub kept: Vec<(state::Id, Error)>,
+}
+
 /// Prune old states using [`Strategy`] and garbage collect
-/// all cached data related to those states being removed
+/// all cached data related to those states being removed,
+/// including their archived trees
 pub async fn prune(
     strategy: Strategy,
     state_db: &db::state::Database,
     install_db: &db::meta::Database,
     layout_db: &db::layout::Database,
     installation: &Installation,
-) -> Result<(), Error> {
+) -> Result<Pruned, Error> {
     let state_ids = state_db.list_ids().await?;
 
     // Define each state as either Keep or Remove
-    let states_by_status = match strategy {
+    let mut states_by_status = match strategy {
         Strategy::KeepRecent(keep) => {
             // Calculate how many states over the limit we are
             let num_to_remove = state_ids.len().saturating_sub(keep as usize);
@@ -85,10 +96,30 @@ pub async fn prune(
             .collect(),
     };
 
+    let mut kept = vec![];
+
+    // Never touch the active state or a state we can't safely
+    // remove the archived tree of
+    for status in states_by_status.iter_mut() {
+        let Status::Remove(id) = *status else {
+            continue;
+        };
+
+        if let Err(error) = check_removable(installation, id).await {
+            match strategy {
+                Strategy::KeepRecent(_) => {
+                    kept.push((id, error));
+                    *status = Status::Keep(id);
+                }
+                Strategy::Remove(_) => return Err(error),
+            }
+        }
+    }
+
     // Bail if there's no states to remove
     if !states_by_status.iter().any(Status::is_removal) {
         // TODO: Print no states to be removed
-        return Ok(());
+        return Ok(Pruned { reclaimed: 0, kept });
     }
 
     // Keep track of how many active states are using a package
@@ -148,8 +179,16 @@ pub async fn prune(
     )
     .await?;
 
+    let mut reclaimed = 0;
+
+    // Remove archived trees. This has to happen before removing
+    // orphaned assets, as the trees hold hardlinks to them
+    for state in &removals {
+        reclaimed += remove_tree(installation.root_path(state.id.to_string())).await?;
+    }
+
     // Remove orphaned downloads
-    remove_orphaned_files(
+    reclaimed += remove_orphaned_files(
         // root
         installation.cache_path("downloads").join("v1"),
         // final set of hashes to compare against
@@ -164,7 +203,7 @@ pub async fn prune(
     .await?;
 
     // Remove orphaned assets
-    remove_orphaned_files(
+    reclaimed += remove_orphaned_files(
         // root
         installation.assets_path("v2"),
         // final set of hashes to compare against
@@ -174,9 +213,61 @@ pub async fn prune(
     )
     .await?;
 
+    Ok(Pruned { reclaimed, kept })
+}
+
+/// Ensure state `id` isn't active and its archived tree,
+/// `.moss/root/{id}/usr`, is intact
+async fn check_removable(installation: &Installation, id: state::Id) -> Result<(), Error> {
+    if installation.active_state == Some(id) {
+        return Err(Error::ActiveState(id));
+    }
+
+    let root = installation.root_path(id.to_string());
+    let usr = root.join("usr");
+
+    // Don't follow symlinks out of the archive
+    for path in [&root, &usr] {
+        match fs::symlink_metadata(path).await {
+            Ok(meta) if meta.is_dir() => {}
+            Ok(_) => return Err(Error::CorruptTree(id)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(Error::MissingTree(id)),
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+
+    // Archived trees record the state they belong to
+    let recorded = fs::read_to_string(usr.join(".stateID"))
+        .await
+        .ok()
+        .and_then(|s| s.trim().parse::<i64>().ok());
+    if recorded != Some(i64::from(id)) {
+        return Err(Error::CorruptTree(id));
+    }
+
     Ok(())
 }
 
+/// Removes the tree at `root`, returning the bytes freed
+async fn remove_tree(root: PathBuf) -> Result<u64, Error> {
+    let freed = enumerate_files(&root)
+        .await?
+        .iter()
+        .map(|file| unlinked_size(file))
+        .sum::<Result<u64, _>>()?;
+
+    fs::remove_dir_all(&root).await?;
+
+    Ok(freed)
+}
+
+/// Size of `file` if removing it frees its data, i.e.
+/// it's not hardlinked elsewhere
+fn unlinked_size(file: &Path) -> Result<u64, io::Error> {
+    let meta = std::fs::symlink_metadata(file)?;
+    Ok(if meta.nlink() == 1 { meta.len() } else { 0 })
+}
+
 /// Removes the provided states & packages from the databases
 async fn prune_databases(
     states: &[State],
@@ -205,12 +296,13 @@ async fn prune_databases(
     Ok(())
 }
 
-/// Removes all files under `root` that no longer exist in the provided `final_hashes` set
+/// Removes all files under `root` that no longer exist in the provided `final_hashes` set,
+/// returning the bytes freed
 async fn remove_orphaned_files<F>(
     root: PathBuf,
     final_hashes: HashSet<String>,
     compute_path: impl Fn(String) -> F,
-) -> Result<(), Error>
+) -> Result<u64, Error>
 where
     F: Future<Output = Option<PathBuf>>,
 {
@@ -223,11 +315,13 @@ where
         .map(|hash| async {
             // Compute path to file using hash
             let Some(file) = compute_path(hash.clone()).await else {
-                return Ok(());
+                return Ok(0);
             };
 
             // Remove if it exists
+            let mut freed = 0;
             if fs::try_exists(&file).await? {
+                freed = unlinked_size(&file)?;
                 fs::remove_file(&file).await?;
             }
 
@@ -237,14 +331,12 @@ where
                 let _ = remove_empty_dirs(parent, &root).await;
             }
 
-            Ok(()) as Result<(), Error>
+            Ok(freed) as Result<u64, Error>
         })
         // Remove w/ concurrency!
         .buffer_unordered(environment::MAX_DISK_CONCURRENCY)
-        .try_collect::<()>()
-        .await?;
-
-    Ok(())
+        .try_fold(0, |total, freed| async move { Ok(total + freed) })
+        .await
 }
 
 /// Returns all nested files under `root` and parses the file name as a hash
@@ -343,4 +435,75 @@ pub enum Error {
     StateDB(#[from] db::state::Error),
     #[error("io")]
     Io(#[from] io::Error),
+    #[error("state {0} is active")]
+    ActiveState(state::Id),
+    #[error("archived tree of state {0} is missing")]
+    MissingTree(state::Id),
+    #[error("archived tree of state {0} is corrupt")]
+    CorruptTree(state::Id),
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::client::test::Root;
+
+    /// Archive a tree for state `id`, recording `recorded` as its id
+    fn archive(installation: &Installation, id: i64, recorded: i64) -> PathBuf {
+        let usr = installation.root_path(id.to_string()).join("usr");
+        std::fs::create_dir_all(&usr).unwrap();
+        std::fs::write(usr.join(".stateID"), recorded.to_string()).unwrap();
+        usr
+    }
+
+    #[tokio::test]
+    async fn test_check_removable() {
+        let root = Root::new("prune-check");
+        let mut installation = Installation::open(&root.0);
+        installation.active_state = Some(1.into());
+
+        archive(&installation, 1, 1);
+        archive(&installation, 2, 2);
+        archive(&installation, 3, 4);
+        // Tree outside of the archive
+        let outside = root.0.join("outside");
+        archive(&installation, 5, 5);
+        std::fs::rename(installation.root_path("5"), &outside).unwrap();
+        symlink(&outside, installation.root_path("5")).unwrap();
+        // `usr` outside of the archive
+        std::fs::create_dir_all(installation.root_path("6")).unwrap();
+        symlink(outside.join("usr"), installation.root_path("6").join("usr")).unwrap();
+
+        let check = |id: i64| check_removable(&installation, id.into());
+
+        assert!(matches!(check(1).await, Err(Error::ActiveState(_))));
+        assert!(check(2).await.is_ok());
+        assert!(matches!(check(3).await, Err(Error::CorruptTree(_))));
+        assert!(matches!(check(4).await, Err(Error::MissingTree(_))));
+        assert!(matches!(check(5).await, Err(Error::CorruptTree(_))));
+        assert!(matches!(check(6).await, Err(Error::CorruptTree(_))));
+    }
+
+    #[tokio::test]
+    async fn test_remove_tree() {
+        let root = Root::new("prune-remove");
+        let installation = Installation::open(&root.0);
+
+        let usr = archive(&installation, 2, 2);
+        std::fs::write(usr.join("unique"), vec![0; 1000]).unwrap();
+
+        // Assets are hardlinked into trees, so removing the tree frees nothing
+        let asset = installation.assets_path("v2").join("asset");
+        std::fs::create_dir_all(asset.parent().unwrap()).unwrap();
+        std::fs::write(&asset, vec![0; 500]).unwrap();
+        std::fs::hard_link(&asset, usr.join("linked")).unwrap();
+
+        let reclaimed = remove_tree(installation.root_path("2")).await.unwrap();
+
+        assert_eq!(reclaimed, 1000 + "2".len() as u64);
+        assert!(!installation.root_path("2").exists());
+        assert!(asset.exists());
+    }
 }
@@ -0,0 +1,442 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    fs::{self, File},
+    io::{self, Read},
+    os::{
+        fd::RawFd,
+        unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use nix::{
+    fcntl::{self, OFlag},
+    sys::stat::Mode,
+    unistd::{close, Uid},
+};
+use rayon::prelude::*;
+use stone::payload::layout;
+use thiserror::Error;
+use tokio::task;
+use tui::ProgressBar;
+use url::Url;
+use vfs::tree::BlitFile;
+use xxhash_rust::xxh3::Xxh3;
+
+use super::{cache, Client, PendingFile};
+use crate::{
+    db, package,
+    registry::job::{self, Job},
+    Installation, Package,
+};
+
+/// A path of the active state which no longer matches its layout
+#[derive(Debug, Clone)]
+pub struct Issue {
+    /// Package which owns the path
+    pub package: package::Id,
+    /// Absolute path within `/usr`
+    pub path: PathBuf,
+    pub layout: layout::Layout,
+    pub kind: Kind,
+}
+
+/// What is wrong with a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// The path doesn't exist
+    Missing,
+    /// The path exists as a different type of file
+    WrongType,
+    /// File contents don't match the layout digest
+    Corrupt,
+    /// Permissions differ from the layout
+    Mode { expected: u32, actual: u32 },
+    /// Symlink points somewhere else
+    Symlink { expected: String, actual: PathBuf },
+    /// File is intact but its asset is missing from the store
+    MissingAsset,
+    /// File is intact but its asset in the store is corrupt
+    CorruptAsset,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Missing => write!(f, "missing"),
+            Kind::WrongType => write!(f, "wrong file type"),
+            Kind::Corrupt => write!(f, "contents modified"),
+            Kind::Mode { expected, actual } => {
+                write!(f, "mode is {actual:04o}, expected {expected:04o}")
+            }
+            Kind::Symlink { expected, actual } => {
+                write!(f, "links to {}, expected {expected}", actual.display())
+            }
+            Kind::MissingAsset => write!(f, "missing from asset store"),
+            Kind::CorruptAsset => write!(f, "corrupt in asset store"),
+        }
+    }
+}
+
+/// Verify every path of the active state against the layout db
+pub async fn verify(client: &Client, progress: &ProgressBar) -> Result<Vec<Issue>, Error> {
+    let state_id = client
+        .installation
+        .active_state
+        .ok_or(Error::NoActiveState)?;
+    let state = client.state_db.get(&state_id).await?;
+
+    let mut files = vec![];
+    for selection in &state.selections {
+        for layout in client.layout_db.query(&selection.package).await? {
+            files.push(PendingFile {
+                id: selection.package.clone(),
+                layout,
+            });
+        }
+    }
+
+    // Directories are shared between packages, only check each path once
+    let files = files
+        .into_iter()
+        .unique_by(|file| file.path())
+        .collect::<Vec<_>>();
+
+    progress.set_length(files.len() as u64);
+
+    let installation = client.installation.clone();
+    let progress = progress.clone();
+
+    task::spawn_blocking(move || {
+        let mut issues = files
+            .par_iter()
+            .map(|file| {
+                let result = check(&installation, file);
+                progress.inc(1);
+                result.map(|kind| {
+                    kind.map(|kind| Issue {
+                        package: file.id.clone(),
+                        path: file.path(),
+                        layout: file.layout.clone(),
+                        kind,
+                    })
+                })
+            })
+            .filter_map(Result::transpose)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        issues.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(issues)
+    })
+    .await
+    .expect("join handle")
+}
+
+/// Repair the given issues, relinking from the asset store and
+/// refetching any packages whose assets are missing or corrupt
+pub async fn repair(client: &Client, issues: &[Issue]) -> Result<(), Error> {
+    let installation = &client.installation;
+
+    // Assets which can't be relinked require their package to be unpacked again
+    let mut refetch = BTreeSet::new();
+    for issue in issues {
+        if let layout::Entry::Regular(digest, _) = &issue.layout.entry {
+            let path = cache::asset_path(installation, &format!("{digest:02x}")).await?;
+
+            if !path.exists() {
+                refetch.insert(issue.package.clone());
+            } else if hash_file(&path)? != *digest {
+                fs::remove_file(&path)?;
+                refetch.insert(issue.package.clone());
+            }
+        }
+    }
+
+    for package in client.resolve_packages(&refetch).await? {
+        // Packages which didn't come from a repository can still be
+        // refetched from where they were originally installed
+        let job = if client.is_offline() {
+            client
+                .offline_job(&package)
+                .await?
+                .ok_or_else(|| super::Error::NotCached(vec![package.meta.name.clone()]))?
+        } else {
+            match client.registry.fetch_item(&package.id).await {
+                Some(job) => job,
+                None => original_job(&package).ok_or(Error::Unfetchable(package.id.clone()))?,
+            }
+        };
+
+        cache::fetch(&package.meta, &job, installation, |_| {})
+            .await?
+            .unpack(|_| {})
+            .await?;
+    }
+
+    let chown = Uid::effective().is_root();
+    let cache_fd = fcntl::open(
+        &installation.assets_path("v2"),
+        OFlag::O_DIRECTORY | OFlag::O_RDONLY,
+        Mode::empty(),
+    )?;
+
+    // Issues are sorted by path so parents are restored before their children
+    let result = issues
+        .iter()
+        .try_for_each(|issue| restore(client, cache_fd, issue, chown));
+
+    close(cache_fd)?;
+
+    result
+}
+
+/// A job fetching the package from the uri it was installed from
+fn original_job(package: &Package) -> Option<Job> {
+    Some(Job {
+        domain: job::Domain::Package(package.id.clone()),
+        origin: job::Origin::RemoteFile(package.meta.uri.as_ref()?.parse::<Url>().ok()?),
+        mirrors: vec![],
+        check: Some(job::CheckType::Sha256(package.meta.hash.clone()?)),
+        size: package.meta.download_size.unwrap_or_default(),
+    })
+}
+
+/// Check a single path against its layout
+fn check(installation: &Installation, file: &PendingFile) -> Result<Option<Kind>, Error> {
+    let relative = file.path();
+    let path = installation
+        .root
+        .join(relative.strip_prefix("/").unwrap_or(&relative));
+
+    let metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        // Device nodes are skipped when blitting without root
+        Err(e)
+            if e.kind() == io::ErrorKind::NotFound
+                && matches!(
+                    file.layout.entry,
+                    layout::Entry::CharacterDevice(..) | layout::Entry::BlockDevice(..)
+                )
+                && !Uid::effective().is_root() =>
+        {
+            return Ok(None)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some(Kind::Missing)),
+        Err(e) => return Err(e.into()),
+    };
+    let file_type = metadata.file_type();
+
+    let is_expected_type = match &file.layout.entry {
+        layout::Entry::Regular(..) => file_type.is_file(),
+        layout::Entry::Symlink(..) => file_type.is_symlink(),
+        layout::Entry::Directory(_) => file_type.is_dir(),
+        layout::Entry::CharacterDevice(..) => file_type.is_char_device(),
+        layout::Entry::BlockDevice(..) => file_type.is_block_device(),
+        layout::Entry::Fifo(_) => file_type.is_fifo(),
+        layout::Entry::Socket(_) => file_type.is_socket(),
+    };
+    if !is_expected_type {
+        return Ok(Some(Kind::WrongType));
+    }
+
+    match &file.layout.entry {
+        layout::Entry::Regular(digest, _) => {
+            if hash_file(&path)? != *digest {
+                return Ok(Some(Kind::Corrupt));
+            }
+
+            // Files are hardlinked from the store, so an intact link
+            // means the asset is intact too
+            let asset = cache::asset_location(installation, &format!("{digest:02x}"));
+            match fs::metadata(&asset) {
+                Ok(asset_metadata)
+                    if asset_metadata.dev() == metadata.dev()
+                        && asset_metadata.ino() == metadata.ino() => {}
+                Ok(_) => {
+                    if hash_file(&asset)? != *digest {
+                        return Ok(Some(Kind::CorruptAsset));
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(Some(Kind::MissingAsset))
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        layout::Entry::Symlink(source, _) => {
+            let actual = fs::read_link(&path)?;
+            if actual.as_os_str() != source.as_str() {
+                return Ok(Some(Kind::Symlink {
+                    expected: source.clone(),
+                    actual,
+                }));
+            }
+            // Symlink permissions are meaningless
+            return Ok(None);
+        }
+        _ => {}
+    }
+
+    let expected = file.layout.mode & 0o7777;
+    let actual = metadata.mode() & 0o7777;
+    if expected != actual {
+        return Ok(Some(Kind::Mode { expected, actual }));
+    }
+
+    Ok(None)
+}
+
+/// Restore a single path from its layout
+fn restore(client: &Client, cache: RawFd, issue: &Issue, chown: bool) -> Result<(), Error> {
+    let relative = issue.path.strip_prefix("/").unwrap_or(&issue.path);
+    let path = client.installation.root.join(relative);
+
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str()))
+    else {
+        return Ok(());
+    };
+
+    // Replace whatever is there now, except for directories which
+    // only need their permissions restored
+    match fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => {
+            if matches!(issue.layout.entry, layout::Entry::Directory(_)) {
+                fs::set_permissions(&path, fs::Permissions::from_mode(issue.layout.mode))?;
+                return Ok(());
+            }
+            fs::remove_dir_all(&path)?;
+        }
+        Ok(_) => fs::remove_file(&path)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    fs::create_dir_all(parent)?;
+    let parent_fd = fcntl::open(parent, OFlag::O_DIRECTORY | OFlag::O_RDONLY, Mode::empty())?;
+
+    let result = client.blit_element_item(
+        parent_fd,
+        cache,
+        name,
+        PendingFile {
+            id: issue.package.clone(),
+            layout: issue.layout.clone(),
+        },
+        chown,
+        &ProgressBar::hidden(),
+    );
+
+    close(parent_fd)?;
+
+    Ok(result?)
+}
+
+/// Compute the xxh3 128 digest of the file at `path`
+fn hash_file(path: &Path) -> Result<u128, io::Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Xxh3::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hasher.digest128())
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No active state")]
+    NoActiveState,
+    #[error("No source to refetch package {0:?} from")]
+    Unfetchable(package::Id),
+    #[error("client")]
+    Client(#[from] super::Error),
+    #[error("cache")]
+    Cache(#[from] cache::Error),
+    #[error("layout db")]
+    LayoutDB(#[from] db::layout::Error),
+    #[error("state db")]
+    StateDB(#[from] db::state::Error),
+    #[error("blit")]
+    Blit(#[from] nix::Error),
+    #[error("io")]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::client::test::Root;
+
+    #[tokio::test]
+    async fn test_verify_repair() {
+        let root = Root::new("verify");
+        let client = root.installed().await;
+        let progress = ProgressBar::hidden();
+
+        assert!(verify(&client, &progress).await.unwrap().is_empty());
+
+        let completions = root.0.join("usr/share/bash-completion/completions");
+
+        // Replace rather than write to files, which would modify the linked asset
+        fs::remove_file(completions.join("7z")).unwrap();
+        fs::remove_file(completions.join("_adb")).unwrap();
+        fs::write(completions.join("_adb"), "modified").unwrap();
+        fs::remove_file(completions.join("7za")).unwrap();
+        symlink("_adb", completions.join("7za")).unwrap();
+        fs::set_permissions(completions.join("_cal"), fs::Permissions::from_mode(0o600)).unwrap();
+        let digest = hash_file(&completions.join("_chfn")).unwrap();
+        fs::remove_file(cache::asset_location(
+            &client.installation,
+            &format!("{digest:02x}"),
+        ))
+        .unwrap();
+
+        let issues = verify(&client, &progress).await.unwrap();
+        let kind = |name: &str| {
+            issues
+                .iter()
+                .find(|issue| issue.path.ends_with(name))
+                .map(|issue| issue.kind.clone())
+        };
+
+        assert_eq!(kind("completions/7z"), Some(Kind::Missing));
+        assert_eq!(kind("completions/_adb"), Some(Kind::Corrupt));
+        assert_eq!(
+            kind("completions/7za"),
+            Some(Kind::Symlink {
+                expected: "7z".into(),
+                actual: "_adb".into()
+            })
+        );
+        assert_eq!(
+            kind("completions/_cal"),
+            Some(Kind::Mode {
+                expected: 0o644,
+                actual: 0o600
+            })
+        );
+
+        assert_eq!(kind("completions/_chfn"), Some(Kind::MissingAsset));
+        assert_eq!(issues.len(), 5);
+
+        // The missing asset is refetched from the local stone
+        repair(&client, &issues).await.unwrap();
+
+        assert!(verify(&client, &progress).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE layout ADD COLUMN path TEXT NULL;
+UPDATE layout
+SET path = '/usr/' || CASE
+    WHEN entry_type IN ('regular', 'symlink') THEN entry_value2
+    ELSE entry_value1
+END;
+CREATE INDEX IF NOT EXISTS layout_path ON layout (path);
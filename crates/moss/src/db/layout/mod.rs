@@ -14,7 +14,7 @@ use crate::Installation;
 
 use super::Encoding;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Database {
     pool: Pool<Sqlite>,
 }
@@ -59,34 +59,35 @@ impl Database {
         .fetch_all(&self.pool)
         .await?;
 
-        Ok(layouts
-            .into_iter()
-            .filter_map(|layout| {
-                let encoding::Layout {
-                    package_id,
-                    uid,
-                    gid,
-                    mode,
-                    tag,
-                    entry_type,
-                    entry_value1,
-                    entry_value2,
-                } = layout;
+        Ok(layouts.into_iter().filter_map(encoding::decode).collect())
+    }
 
-                let entry = encoding::decode_entry(entry_type, entry_value1, entry_value2)?;
+    /// Retrieve all entries with an installed path starting with `prefix`
+    pub async fn query_path_prefix(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(package::Id, payload::Layout)>, Error> {
+        // Range over the path index rather than LIKE, which can't use it
+        let layouts = sqlx::query_as::<_, encoding::Layout>(
+            "
+            SELECT package_id,
+                   uid,
+                   gid,
+                   mode,
+                   tag,
+                   entry_type,
+                   entry_value1,
+                   entry_value2
+            FROM layout
+            WHERE path >= ? AND path < ?;
+            ",
+        )
+        .bind(prefix)
+        .bind(format!("{prefix}{}", char::MAX))
+        .fetch_all(&self.pool)
+        .await?;
 
-                Some((
-                    package_id.0,
-                    payload::Layout {
-                        uid,
-                        gid,
-                        mode,
-                        tag,
-                        entry,
-                    },
-                ))
-            })
-            .collect())
+        Ok(layouts.into_iter().filter_map(encoding::decode).collect())
     }
 
     pub async fn file_hashes(&self) -> Result<HashSet<String>, Error> {
@@ -125,7 +126,8 @@ impl Database {
                 tag,
                 entry_type,
                 entry_value1,
-                entry_value2
+                entry_value2,
+                path
             )
             ",
         )
@@ -138,6 +140,7 @@ impl Database {
                 entry,
             } = layout;
 
+            let path = path(&entry);
             let (entry_type, entry_value1, entry_value2) = encoding::encode_entry(entry);
 
             b.push_bind(id.encode().to_owned())
@@ -147,7 +150,8 @@ impl Database {
                 .push_bind(tag)
                 .push_bind(entry_type)
                 .push_bind(entry_value1)
-                .push_bind(entry_value2);
+                .push_bind(entry_value2)
+                .push_bind(path);
         })
         .build()
         .execute(&self.pool)
@@ -227,6 +231,23 @@ impl Database {
     }
 }
 
+/// Absolute path of a layout entry once installed
+pub fn path(entry: &payload::layout::Entry) -> String {
+    use payload::layout::Entry;
+
+    let target = match entry {
+        Entry::Regular(_, target)
+        | Entry::Symlink(_, target)
+        | Entry::Directory(target)
+        | Entry::CharacterDevice(_, target)
+        | Entry::BlockDevice(_, target)
+        | Entry::Fifo(target)
+        | Entry::Socket(target) => target,
+    };
+
+    format!("/usr/{target}")
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("sqlx")]
@@ -253,6 +274,32 @@ mod encoding {
         pub entry_value2: Option<String>,
     }
 
+    pub fn decode(layout: Layout) -> Option<(package::Id, payload::Layout)> {
+        let Layout {
+            package_id,
+            uid,
+            gid,
+            mode,
+            tag,
+            entry_type,
+            entry_value1,
+            entry_value2,
+        } = layout;
+
+        let entry = decode_entry(entry_type, entry_value1, entry_value2)?;
+
+        Some((
+            package_id.0,
+            payload::Layout {
+                uid,
+                gid,
+                mode,
+                tag,
+                entry,
+            },
+        ))
+    }
+
     pub fn decode_entry(
         entry_type: String,
         entry_value1: Option<String>,
@@ -269,14 +316,21 @@ mod encoding {
             }
             "symlink" => Some(Entry::Symlink(entry_value1?, entry_value2?)),
             "directory" => Some(Entry::Directory(entry_value1?)),
-            "character-device" => Some(Entry::CharacterDevice(entry_value1?)),
-            "block-device" => Some(Entry::BlockDevice(entry_value1?)),
+            "character-device" => {
+                Some(Entry::CharacterDevice(device(entry_value2)?, entry_value1?))
+            }
+            "block-device" => Some(Entry::BlockDevice(device(entry_value2)?, entry_value1?)),
             "fifo" => Some(Entry::Fifo(entry_value1?)),
             "socket" => Some(Entry::Socket(entry_value1?)),
             _ => None,
         }
     }
 
+    /// Device numbers weren't always recorded
+    fn device(value: Option<String>) -> Option<u64> {
+        value.map_or(Some(0), |device| device.parse().ok())
+    }
+
     pub fn encode_entry(
         entry: payload::layout::Entry,
     ) -> (&'static str, Option<String>, Option<String>) {
@@ -286,8 +340,12 @@ mod encoding {
             Entry::Regular(hash, name) => ("regular", Some(hash.to_string()), Some(name)),
             Entry::Symlink(a, b) => ("symlink", Some(a), Some(b)),
             Entry::Directory(name) => ("directory", Some(name), None),
-            Entry::CharacterDevice(name) => ("character-device", Some(name), None),
-            Entry::BlockDevice(name) => ("block-device", Some(name), None),
+            Entry::CharacterDevice(device, name) => {
+                ("character-device", Some(name), Some(device.to_string()))
+            }
+            Entry::BlockDevice(device, name) => {
+                ("block-device", Some(name), Some(device.to_string()))
+            }
             Entry::Fifo(name) => ("fifo", Some(name), None),
             Entry::Socket(name) => ("socket", Some(name), None),
         }
@@ -334,5 +392,15 @@ mod test {
         let all = database.all().await.unwrap();
 
         assert_eq!(count, all.len());
+
+        let completions = database
+            .query_path_prefix("/usr/share/bash-completion/completions/")
+            .await
+            .unwrap();
+
+        assert!(!completions.is_empty());
+        assert!(completions.len() < count);
+        assert!(completions.iter().all(|(_, layout)| path(&layout.entry)
+            .starts_with("/usr/share/bash-completion/completions/")));
     }
 }
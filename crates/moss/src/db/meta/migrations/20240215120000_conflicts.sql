@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS meta_conflicts (
+    package TEXT NOT NULL,
+    conflict TEXT NOT NULL,
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
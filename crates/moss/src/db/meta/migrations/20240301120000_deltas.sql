@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS meta_deltas (
+    package TEXT NOT NULL,
+    base TEXT NOT NULL,
+    uri TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    download_size INT NOT NULL,
+    FOREIGN KEY (package) REFERENCES meta(package) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE meta ADD COLUMN installed_size INT NULL;
@@ -19,6 +19,7 @@ enum Table {
     Licenses,
     Dependencies,
     Providers,
+    Conflicts,
 }
 
 #[derive(Debug)]
@@ -26,6 +27,9 @@ pub enum Filter {
     Provider(Provider),
     Dependency(Dependency),
     Name(package::Name),
+    /// Case insensitive substring match of name, summary,
+    /// description or providers
+    Search(String),
 }
 
 impl Filter {
@@ -94,10 +98,40 @@ impl Filter {
                         .push(")");
                 }
             }
+            Filter::Search(term) => {
+                let pattern = format!("%{}%", escape_like(term));
+
+                query
+                    .push(
+                        "
+                        where package in
+                            (select package from meta where name like 
+                        ",
+                    )
+                    .push_bind(pattern.clone())
+                    .push(" escape '\\' or summary like ")
+                    .push_bind(pattern.clone())
+                    .push(" escape '\\' or description like ")
+                    .push_bind(pattern.clone())
+                    .push(
+                        " escape '\\'
+                            union select package from meta_providers where provider like 
+                        ",
+                    )
+                    .push_bind(pattern)
+                    .push(" escape '\\')");
+            }
         }
     }
 }
 
+/// Escape `LIKE` wildcards so `term` is matched literally
+fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 #[derive(Debug, Clone)]
 pub struct Database {
     pool: Pool<Sqlite>,
@@ -143,7 +177,8 @@ impl Database {
                    homepage,
                    uri,
                    hash,
-                   download_size
+                   download_size,
+                   installed_size
             FROM meta
             ",
         );
@@ -169,14 +204,22 @@ impl Database {
             ",
         );
 
+        let mut conflicts_query = sqlx::QueryBuilder::new(
+            "
+            SELECT package, conflict
+            FROM meta_conflicts
+            ",
+        );
+
         if let Some(filter) = filter {
             filter.append(Table::Meta, &mut entry_query);
             filter.append(Table::Licenses, &mut licenses_query);
             filter.append(Table::Dependencies, &mut dependencies_query);
             filter.append(Table::Providers, &mut providers_query);
+            filter.append(Table::Conflicts, &mut conflicts_query);
         }
 
-        let (entries, licenses, dependencies, providers) = futures::try_join!(
+        let (entries, licenses, dependencies, providers, conflicts) = futures::try_join!(
             entry_query
                 .build_query_as::<encoding::Entry>()
                 .fetch_all(&self.pool),
@@ -189,6 +232,9 @@ impl Database {
             providers_query
                 .build_query_as::<encoding::Provider>()
                 .fetch_all(&self.pool),
+            conflicts_query
+                .build_query_as::<encoding::Conflict>()
+                .fetch_all(&self.pool),
         )?;
 
         Ok(entries
@@ -221,9 +267,15 @@ impl Database {
                             .filter(|l| l.id.0 == entry.id.0)
                             .map(|p| p.provider.0.clone())
                             .collect(),
+                        conflicts: conflicts
+                            .iter()
+                            .filter(|l| l.id.0 == entry.id.0)
+                            .map(|c| c.conflict.0.clone())
+                            .collect(),
                         uri: entry.uri,
                         hash: entry.hash,
                         download_size: entry.download_size.map(|i| i as u64),
+                        installed_size: entry.installed_size.map(|i| i as u64),
                     },
                 )
             })
@@ -245,7 +297,8 @@ impl Database {
                    homepage,
                    uri,
                    hash,
-                   download_size
+                   download_size,
+                   installed_size
             FROM meta
             WHERE package = ?;
             ",
@@ -279,11 +332,21 @@ impl Database {
         )
         .bind(package.encode());
 
-        let (entry, licenses, dependencies, providers) = futures::try_join!(
+        let conflicts_query = sqlx::query_as::<_, encoding::Conflict>(
+            "
+            SELECT package, conflict
+            FROM meta_conflicts
+            WHERE package = ?;
+            ",
+        )
+        .bind(package.encode());
+
+        let (entry, licenses, dependencies, providers, conflicts) = futures::try_join!(
             entry_query.fetch_one(&self.pool),
             licenses_query.fetch_all(&self.pool),
             dependencies_query.fetch_all(&self.pool),
             providers_query.fetch_all(&self.pool),
+            conflicts_query.fetch_all(&self.pool),
         )?;
 
         Ok(Meta {
@@ -299,9 +362,11 @@ impl Database {
             licenses: licenses.into_iter().map(|l| l.license).collect(),
             dependencies: dependencies.into_iter().map(|d| d.dependency.0).collect(),
             providers: providers.into_iter().map(|p| p.provider.0).collect(),
+            conflicts: conflicts.into_iter().map(|c| c.conflict.0).collect(),
             uri: entry.uri,
             hash: entry.hash,
             download_size: entry.download_size.map(|i| i as u64),
+            installed_size: entry.installed_size.map(|i| i as u64),
         })
     }
 
@@ -349,7 +414,8 @@ impl Database {
                 homepage,
                 uri,
                 hash,
-                download_size                
+                download_size,
+                installed_size
             )
             ",
         )
@@ -367,6 +433,7 @@ impl Database {
                 uri,
                 hash,
                 download_size,
+                installed_size,
                 ..
             } = meta;
 
@@ -382,7 +449,8 @@ impl Database {
                 .push_bind(homepage)
                 .push_bind(uri)
                 .push_bind(hash)
-                .push_bind(download_size.map(|i| i as i64));
+                .push_bind(download_size.map(|i| i as i64))
+                .push_bind(installed_size.map(|i| i as i64));
         })
         .build()
         .execute(transaction.acquire().await?)
@@ -449,11 +517,80 @@ impl Database {
             .await?;
         }
 
+        // Conflicts
+        let conflicts = packages
+            .iter()
+            .flat_map(|(id, meta)| meta.conflicts.iter().map(move |conflict| (id, conflict)))
+            .collect::<Vec<_>>();
+        if !conflicts.is_empty() {
+            sqlx::QueryBuilder::new(
+                "
+                INSERT INTO meta_conflicts (package, conflict)
+                ",
+            )
+            .push_values(conflicts, |mut b, (id, conflict)| {
+                b.push_bind(id.encode()).push_bind(conflict.encode());
+            })
+            .build()
+            .execute(transaction.acquire().await?)
+            .await?;
+        }
+
         transaction.commit().await?;
 
         Ok(())
     }
 
+    /// Returns all deltas which produce `package`
+    pub async fn deltas(&self, package: &package::Id) -> Result<Vec<package::Delta>, Error> {
+        let deltas = sqlx::query_as::<_, encoding::Delta>(
+            "
+            SELECT package, base, uri, hash, download_size
+            FROM meta_deltas
+            WHERE package = ?;
+            ",
+        )
+        .bind(package.encode())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(deltas
+            .into_iter()
+            .map(|delta| package::Delta {
+                from: delta.base.0,
+                to: delta.id.0,
+                uri: delta.uri,
+                hash: delta.hash,
+                download_size: delta.download_size as u64,
+            })
+            .collect())
+    }
+
+    /// Add deltas, the packages they produce must already exist
+    pub async fn batch_add_deltas(&self, deltas: Vec<package::Delta>) -> Result<(), Error> {
+        if deltas.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::QueryBuilder::new(
+            "
+            INSERT INTO meta_deltas (package, base, uri, hash, download_size)
+            ",
+        )
+        .push_values(deltas, |mut b, delta| {
+            b.push_bind(String::from(delta.to))
+                .push_bind(String::from(delta.from))
+                .push_bind(delta.uri)
+                .push_bind(delta.hash)
+                .push_bind(delta.download_size as i64);
+        })
+        .build()
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn remove(&self, package: &package::Id) -> Result<(), Error> {
         self.batch_remove(Some(package)).await
     }
@@ -529,6 +666,7 @@ mod encoding {
         pub uri: Option<String>,
         pub hash: Option<String>,
         pub download_size: Option<i64>,
+        pub installed_size: Option<i64>,
     }
 
     #[derive(FromRow)]
@@ -552,6 +690,23 @@ mod encoding {
         pub provider: Decoder<crate::Provider>,
     }
 
+    #[derive(FromRow)]
+    pub struct Conflict {
+        #[sqlx(rename = "package")]
+        pub id: Decoder<package::Id>,
+        pub conflict: Decoder<crate::Provider>,
+    }
+
+    #[derive(FromRow)]
+    pub struct Delta {
+        #[sqlx(rename = "package")]
+        pub id: Decoder<package::Id>,
+        pub base: Decoder<package::Id>,
+        pub uri: String,
+        pub hash: String,
+        pub download_size: i64,
+    }
+
     #[derive(FromRow)]
     pub struct ProviderPackage {
         pub package: Decoder<package::Id>,
@@ -602,6 +757,18 @@ mod test {
         let fetched = database.query(Some(lookup)).await.unwrap();
         assert_eq!(fetched.len(), 1);
 
+        // Search is case insensitive & matches literally
+        let fetched = database
+            .query(Some(Filter::Search("COMPLETION".into())))
+            .await
+            .unwrap();
+        assert_eq!(fetched.len(), 1);
+        let fetched = database
+            .query(Some(Filter::Search("bash%completion".into())))
+            .await
+            .unwrap();
+        assert!(fetched.is_empty());
+
         batch_remove_impl([&id], &database.pool).await.unwrap();
 
         let result = database.get(&id).await;
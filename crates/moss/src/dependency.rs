@@ -4,6 +4,7 @@
 
 use std::{fmt, str::FromStr};
 
+use serde::Serialize;
 use stone::payload;
 use thiserror::Error;
 
@@ -109,7 +110,8 @@ impl From<Kind> for payload::meta::Dependency {
 
 /// A Dependency in moss is simplistic in that it only contains
 /// a target and a Kind, ie. `pkgconfig(zlib)`
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(into = "String")]
 pub struct Dependency {
     /// Tag for the table-type of dependency
     pub kind: Kind,
@@ -125,6 +127,12 @@ impl fmt::Display for Dependency {
     }
 }
 
+impl From<Dependency> for String {
+    fn from(dependency: Dependency) -> Self {
+        dependency.to_string()
+    }
+}
+
 impl FromStr for Dependency {
     type Err = ParseError;
 
@@ -135,7 +143,8 @@ impl FromStr for Dependency {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(into = "String")]
 pub struct Provider {
     pub kind: Kind,
     pub name: String,
@@ -160,6 +169,12 @@ impl fmt::Display for Provider {
     }
 }
 
+impl From<Provider> for String {
+    fn from(provider: Provider) -> Self {
+        provider.to_string()
+    }
+}
+
 impl FromStr for Provider {
     type Err = ParseError;
 
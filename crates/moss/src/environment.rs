@@ -15,3 +15,7 @@ pub const FILE_READ_BUFFER_SIZE: usize = 4 * 1024 * 1024;
 pub const FILE_READ_CHUNK_THRESHOLD: usize = 16 * 1024;
 /// DB batch size
 pub const DB_BATCH_SIZE: usize = 1000;
+/// Number of times a failed download is retried from the same source
+pub const DOWNLOAD_RETRIES: u32 = 3;
+/// Delay before retrying a download, doubled after each attempt
+pub const DOWNLOAD_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
@@ -86,6 +86,17 @@ impl Installation {
         }
     }
 
+    /// Re-read the active state, i.e. once another process may have changed it
+    pub fn reload_active_state(&mut self) {
+        self.active_state = self.read_active_state();
+    }
+
+    /// Read the active state from disk, which may differ from [`Self::active_state`]
+    /// if another process changed it since
+    pub fn read_active_state(&self) -> Option<state::Id> {
+        read_state_id(&self.root)
+    }
+
     /// Return true if we lack write access
     pub fn read_only(&self) -> bool {
         matches!(self.mutability, Mutability::ReadOnly)
@@ -116,6 +127,16 @@ impl Installation {
         self.moss_path("repo").join(path)
     }
 
+    /// Path of the advisory lock guarding the installation
+    pub fn lock_path(&self) -> PathBuf {
+        self.moss_path("lock")
+    }
+
+    /// Path of the journal recording an in-progress transaction
+    pub fn journal_path(&self) -> PathBuf {
+        self.moss_path("journal")
+    }
+
     /// Build a path relative to the moss system roots tree
     pub fn root_path(&self, path: impl AsRef<Path>) -> PathBuf {
         self.moss_path("root").join(path)
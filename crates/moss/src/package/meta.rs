@@ -4,6 +4,7 @@
 
 use std::{collections::HashSet, fmt};
 
+use serde::{Deserialize, Serialize, Serializer};
 use stone::payload;
 use thiserror::Error;
 
@@ -20,7 +21,7 @@ impl fmt::Display for Id {
 }
 
 /// The name of a [`Package`]
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Name(String);
 
 impl From<String> for Name {
@@ -48,7 +49,7 @@ impl fmt::Display for Name {
 }
 
 /// The metadata of a [`Package`]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Meta {
     /// Package name
     pub name: Name,
@@ -71,15 +72,22 @@ pub struct Meta {
     /// Licenses this is available under
     pub licenses: Vec<String>,
     /// All dependencies
+    #[serde(serialize_with = "sorted")]
     pub dependencies: HashSet<Dependency>,
     /// All providers, including name()
+    #[serde(serialize_with = "sorted")]
     pub providers: HashSet<Provider>,
+    /// Providers this package cannot be installed alongside
+    #[serde(serialize_with = "sorted")]
+    pub conflicts: HashSet<Provider>,
     /// If relevant: uri to fetch from
     pub uri: Option<String>,
     /// If relevant: hash for the download
     pub hash: Option<String>,
     /// How big is this package in the repo..?
     pub download_size: Option<u64>,
+    /// How big are the contents of this package once installed?
+    pub installed_size: Option<u64>,
 }
 
 impl Meta {
@@ -98,6 +106,7 @@ impl Meta {
         let uri = find_meta_string(payload, payload::meta::Tag::PackageURI).ok();
         let hash = find_meta_string(payload, payload::meta::Tag::PackageHash).ok();
         let download_size = find_meta_u64(payload, payload::meta::Tag::PackageSize).ok();
+        let installed_size = find_meta_u64(payload, payload::meta::Tag::InstalledSize).ok();
 
         let licenses = payload
             .iter()
@@ -106,13 +115,17 @@ impl Meta {
         let dependencies = payload.iter().filter_map(meta_dependency).collect();
         let providers = payload
             .iter()
-            .filter_map(meta_provider)
+            .filter_map(|meta| meta_provider(meta, payload::meta::Tag::Provides))
             // Add package name as provider
             .chain(Some(Provider {
                 kind: dependency::Kind::PackageName,
                 name: name.clone(),
             }))
             .collect();
+        let conflicts = payload
+            .iter()
+            .filter_map(|meta| meta_provider(meta, payload::meta::Tag::Conflicts))
+            .collect();
 
         Ok(Meta {
             name: Name::from(name),
@@ -127,9 +140,11 @@ impl Meta {
             licenses,
             dependencies,
             providers,
+            conflicts,
             uri,
             hash,
             download_size,
+            installed_size,
         })
     }
 
@@ -154,6 +169,10 @@ impl Meta {
             self.download_size
                 .map(|size| (Tag::PackageSize, Kind::Uint64(size))),
         )
+        .chain(
+            self.installed_size
+                .map(|size| (Tag::InstalledSize, Kind::Uint64(size))),
+        )
         .chain(
             self.licenses
                 .into_iter()
@@ -176,10 +195,31 @@ impl Meta {
                     )
                 }),
         )
+        .chain(self.conflicts.into_iter().map(|conflict| {
+            (
+                Tag::Conflicts,
+                Kind::Provider(conflict.kind.into(), conflict.name),
+            )
+        }))
         .map(|(tag, kind)| payload::Meta { tag, kind })
         .collect()
     }
 
+    /// Returns true if `term` is a case insensitive substring of the
+    /// name, summary, description or any provider
+    pub fn matches(&self, term: &str) -> bool {
+        let term = term.to_lowercase();
+        let contains = |value: &str| value.to_lowercase().contains(&term);
+
+        contains(self.name.as_ref())
+            || contains(&self.summary)
+            || contains(&self.description)
+            || self
+                .providers
+                .iter()
+                .any(|provider| contains(&provider.to_string()))
+    }
+
     /// Return a reusable ID
     pub fn id(&self) -> Id {
         Id(format!(
@@ -189,6 +229,71 @@ impl Meta {
     }
 }
 
+/// Serialize a set in a stable order
+fn sorted<S, T>(set: &HashSet<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: fmt::Display,
+{
+    let mut items = set.iter().map(T::to_string).collect::<Vec<_>>();
+    items.sort();
+    serializer.collect_seq(items)
+}
+
+/// A delta between two releases of a package, containing only the
+/// content missing from the package it applies to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Delta {
+    /// Package this delta applies to
+    pub from: super::Id,
+    /// Package this delta produces
+    pub to: super::Id,
+    /// uri to fetch from
+    pub uri: String,
+    /// hash for the download
+    pub hash: String,
+    /// How big is this delta in the repo..?
+    pub download_size: u64,
+}
+
+impl Delta {
+    /// Returns `None` if the payload doesn't describe a delta
+    pub fn from_stone_payload(
+        payload: &[stone::payload::Meta],
+    ) -> Result<Option<Self>, MissingMetaFieldError> {
+        let Ok(to) = find_meta_string(payload, payload::meta::Tag::DeltaTo) else {
+            return Ok(None);
+        };
+        let from = find_meta_string(payload, payload::meta::Tag::DeltaFrom)?;
+        let uri = find_meta_string(payload, payload::meta::Tag::PackageURI)?;
+        let hash = find_meta_string(payload, payload::meta::Tag::PackageHash)?;
+        let download_size = find_meta_u64(payload, payload::meta::Tag::PackageSize)?;
+
+        Ok(Some(Delta {
+            from: from.into(),
+            to: to.into(),
+            uri,
+            hash,
+            download_size,
+        }))
+    }
+
+    pub fn to_stone_payload(self) -> Vec<payload::Meta> {
+        use payload::meta::{Kind, Tag};
+
+        vec![
+            (Tag::DeltaFrom, Kind::String(self.from.into())),
+            (Tag::DeltaTo, Kind::String(self.to.into())),
+            (Tag::PackageURI, Kind::String(self.uri)),
+            (Tag::PackageHash, Kind::String(self.hash)),
+            (Tag::PackageSize, Kind::Uint64(self.download_size)),
+        ]
+        .into_iter()
+        .map(|(tag, kind)| payload::Meta { tag, kind })
+        .collect()
+    }
+}
+
 fn find_meta_string(
     meta: &[payload::Meta],
     tag: payload::meta::Tag,
@@ -243,7 +348,11 @@ fn meta_dependency(meta: &payload::Meta) -> Option<Dependency> {
     }
 }
 
-fn meta_provider(meta: &payload::Meta) -> Option<Provider> {
+fn meta_provider(meta: &payload::Meta, tag: payload::meta::Tag) -> Option<Provider> {
+    if meta.tag != tag {
+        return None;
+    }
+
     if let payload::meta::Kind::Provider(kind, name) = meta.kind.clone() {
         Some(Provider {
             kind: dependency::Kind::from(kind),
@@ -257,3 +366,40 @@ fn meta_provider(meta: &payload::Meta) -> Option<Provider> {
 #[derive(Debug, Error)]
 #[error("Missing metadata field: {0:?}")]
 pub struct MissingMetaFieldError(pub payload::meta::Tag);
+
+#[cfg(test)]
+mod test {
+    use stone::{header::v1::FileType, read::PayloadKind};
+
+    use super::*;
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let delta = Delta {
+            from: "aaaa".to_string().into(),
+            to: "bbbb".to_string().into(),
+            uri: "deltas/foo-aaaa-bbbb.delta.stone".into(),
+            hash: "cccc".into(),
+            download_size: 1234,
+        };
+
+        let mut bytes = vec![];
+        let mut writer = stone::Writer::new(&mut bytes, FileType::Repository).unwrap();
+        writer
+            .add_payload(delta.clone().to_stone_payload().as_slice())
+            .unwrap();
+        writer.finalize().unwrap();
+
+        let payloads = stone::read_bytes(&bytes)
+            .unwrap()
+            .payloads()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let meta = payloads.iter().find_map(PayloadKind::meta).unwrap();
+
+        assert_eq!(Delta::from_stone_payload(&meta.body).unwrap(), Some(delta));
+        // Regular package metadata isn't a delta
+        assert_eq!(Delta::from_stone_payload(&[]).unwrap(), None);
+    }
+}
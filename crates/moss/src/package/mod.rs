@@ -4,14 +4,15 @@
 
 use bitflags::bitflags;
 use itertools::Itertools;
+use serde::Serialize;
 
-pub use self::meta::{Meta, MissingMetaFieldError, Name};
+pub use self::meta::{Delta, Meta, MissingMetaFieldError, Name};
 
 pub mod meta;
 pub mod render;
 
 /// Unique ID of a [`Package`]
-#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
 pub struct Id(String);
 
 impl From<String> for Id {
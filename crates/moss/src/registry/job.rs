@@ -41,6 +41,9 @@ pub struct Job {
     /// Where are we getting this from.. ?
     pub origin: Origin,
 
+    /// Alternate locations of a remote origin, tried in order should it fail
+    pub mirrors: Vec<Url>,
+
     /// How do we verify the download?
     pub check: Option<CheckType>,
 
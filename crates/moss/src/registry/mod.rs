@@ -15,6 +15,7 @@ pub use self::plugin::Plugin;
 pub use self::transaction::Transaction;
 
 pub mod job;
+pub mod pin;
 pub mod plugin;
 pub mod transaction;
 
@@ -24,6 +25,8 @@ pub mod transaction;
 pub struct Registry {
     /// Ordered set of plugins
     plugins: Vec<Plugin>,
+    /// Rules constraining which candidates can be selected
+    pins: pin::Rules,
 }
 
 impl Registry {
@@ -32,10 +35,31 @@ impl Registry {
         self.plugins.push(plugin);
     }
 
+    /// Set the [`pin::Rules`] honoured when selecting candidates
+    pub fn set_pins(&mut self, pins: pin::Rules) {
+        self.pins = pins;
+    }
+
+    pub fn pins(&self) -> &pin::Rules {
+        &self.pins
+    }
+
     fn query<'a: 'b, 'b, F, I>(
         &'a self,
         query: impl Fn(&'b Plugin) -> F + Copy + 'b,
     ) -> impl Stream<Item = Package> + 'b
+    where
+        F: Future<Output = I>,
+        I: IntoIterator<Item = Package>,
+    {
+        self.query_plugins(query).map(|(_, package)| package)
+    }
+
+    /// Query each plugin, returning packages along with the plugin which provided them
+    fn query_plugins<'a: 'b, 'b, F, I>(
+        &'a self,
+        query: impl Fn(&'b Plugin) -> F + Copy + 'b,
+    ) -> impl Stream<Item = (&'b Plugin, Package)> + 'b
     where
         F: Future<Output = I>,
         I: IntoIterator<Item = Package>,
@@ -48,7 +72,7 @@ impl Registry {
                     stream::once(async move {
                         let packages = query(p).await;
 
-                        stream::iter(packages)
+                        stream::iter(packages.into_iter().map(move |package| (p, package)))
                     })
                     .flatten()
                 }),
@@ -56,6 +80,37 @@ impl Registry {
         .flatten()
     }
 
+    /// Like [`Registry::query`], excluding packages which
+    /// can't be selected due to the [`pin::Rules`]
+    fn query_allowed<'a: 'b, 'b, F, I>(
+        &'a self,
+        query: impl Fn(&'b Plugin) -> F + Copy + 'b,
+    ) -> impl Stream<Item = Package> + 'b
+    where
+        F: Future<Output = I>,
+        I: IntoIterator<Item = Package>,
+    {
+        self.query_plugins(query)
+            .filter_map(move |(plugin, package)| async move {
+                self.is_allowed(plugin, &package).await.then_some(package)
+            })
+    }
+
+    /// Returns true if `package` provided by `plugin` can be selected
+    async fn is_allowed(&self, plugin: &Plugin, package: &Package) -> bool {
+        let Some(rule) = self.pins.get(&package.meta.name) else {
+            return true;
+        };
+
+        let installed = self
+            .by_name(&package.meta.name, package::Flags::INSTALLED)
+            .boxed()
+            .next()
+            .await;
+
+        rule.allows(package, plugin.repository(), installed.as_ref())
+    }
+
     /// Return a sorted stream of [`Package`] by provider
     pub fn by_provider<'a: 'b, 'b>(
         &'a self,
@@ -65,6 +120,16 @@ impl Registry {
         self.query(move |plugin| plugin.query_provider(provider, flags))
     }
 
+    /// Return a sorted stream of [`Package`] by provider which
+    /// can be selected according to the [`pin::Rules`]
+    pub fn allowed_by_provider<'a: 'b, 'b>(
+        &'a self,
+        provider: &'b Provider,
+        flags: package::Flags,
+    ) -> impl Stream<Item = Package> + 'b {
+        self.query_allowed(move |plugin| plugin.query_provider(provider, flags))
+    }
+
     /// Return a sorted stream of [`Package`] by name
     pub fn by_name<'a: 'b, 'b>(
         &'a self,
@@ -74,11 +139,43 @@ impl Registry {
         self.query(move |plugin| plugin.query_name(package_name, flags))
     }
 
+    /// Return a sorted stream of [`Package`] by name which
+    /// can be selected according to the [`pin::Rules`]
+    pub fn allowed_by_name<'a: 'b, 'b>(
+        &'a self,
+        package_name: &'b package::Name,
+        flags: package::Flags,
+    ) -> impl Stream<Item = Package> + 'b {
+        self.query_allowed(move |plugin| plugin.query_name(package_name, flags))
+    }
+
+    /// Returns true if the package with `id` can be selected
+    /// from any plugin according to the [`pin::Rules`]
+    pub async fn is_allowed_id(&self, id: &package::Id) -> bool {
+        self.query_allowed(move |plugin| plugin.package(id))
+            .boxed()
+            .next()
+            .await
+            .is_some()
+    }
+
     /// Return a sorted stream of [`Package`] by id
     pub fn by_id<'a: 'b, 'b>(&'a self, id: &'b package::Id) -> impl Stream<Item = Package> + 'b {
         self.query(move |plugin| plugin.package(id))
     }
 
+    /// Return a sorted stream of [`Package`] matching the search `term`
+    ///
+    /// The name, summary, description & providers of each package
+    /// are matched case insensitively
+    pub fn search<'a: 'b, 'b>(
+        &'a self,
+        term: &'b str,
+        flags: package::Flags,
+    ) -> impl Stream<Item = Package> + 'b {
+        self.query(move |plugin| plugin.search(term, flags))
+    }
+
     /// Return a sorted stream of [`Package`] matching the given [`Flags`]
     ///
     /// [`Flags`]: package::Flags
@@ -96,6 +193,42 @@ impl Registry {
         self.list(flags | package::Flags::AVAILABLE)
     }
 
+    /// Return a [`Job`] to fetch the package from the highest priority
+    /// plugin able to provide it
+    ///
+    /// [`Job`]: job::Job
+    pub async fn fetch_item(&self, id: &package::Id) -> Option<job::Job> {
+        for plugin in self
+            .plugins
+            .iter()
+            .sorted_by(|a, b| a.priority().cmp(&b.priority()).reverse())
+        {
+            if let Some(job) = plugin.fetch_item(id).await {
+                return Some(job);
+            }
+        }
+
+        None
+    }
+
+    /// Return a [`Job`] to fetch the full package, never a delta, from
+    /// the highest priority plugin able to provide it
+    ///
+    /// [`Job`]: job::Job
+    pub async fn fetch_package(&self, id: &package::Id) -> Option<job::Job> {
+        for plugin in self
+            .plugins
+            .iter()
+            .sorted_by(|a, b| a.priority().cmp(&b.priority()).reverse())
+        {
+            if let Some(job) = plugin.fetch_package(id).await {
+                return Some(job);
+            }
+        }
+
+        None
+    }
+
     /// Return a new transaction for this registry
     pub fn transaction(&self) -> Result<Transaction<'_>, transaction::Error> {
         transaction::new(self)
@@ -135,9 +268,11 @@ mod test {
                 licenses: Default::default(),
                 dependencies: Default::default(),
                 providers: Default::default(),
+                conflicts: Default::default(),
                 uri: Default::default(),
                 hash: Default::default(),
                 download_size: Default::default(),
+                installed_size: Default::default(),
             },
             flags: package::Flags::NONE,
         };
@@ -189,9 +324,11 @@ mod test {
                 licenses: Default::default(),
                 dependencies: Default::default(),
                 providers: Default::default(),
+                conflicts: Default::default(),
                 uri: Default::default(),
                 hash: Default::default(),
                 download_size: Default::default(),
+                installed_size: Default::default(),
             },
             flags,
         };
@@ -242,4 +379,112 @@ mod test {
         assert!(matches(installed_source, &["d"]));
         assert!(matches(available_source, &["e"]));
     }
+
+    #[tokio::test]
+    async fn test_conflicts() {
+        let mut registry = Registry::default();
+
+        let provider = |name: &str| Provider::from_name(name).unwrap();
+        let package = |id: &str, conflicts: &[&str]| Package {
+            id: package::Id::from(id.to_string()),
+            meta: package::Meta {
+                name: package::Name::from(id.to_string()),
+                version_identifier: Default::default(),
+                source_release: Default::default(),
+                build_release: Default::default(),
+                architecture: Default::default(),
+                summary: Default::default(),
+                description: Default::default(),
+                source_id: Default::default(),
+                homepage: Default::default(),
+                licenses: Default::default(),
+                dependencies: Default::default(),
+                providers: [provider(id)].into_iter().collect(),
+                conflicts: conflicts.iter().map(|name| provider(name)).collect(),
+                uri: Default::default(),
+                hash: Default::default(),
+                download_size: Default::default(),
+                installed_size: Default::default(),
+            },
+            flags: package::Flags::AVAILABLE,
+        };
+
+        registry.add_plugin(Plugin::Test(plugin::Test::new(
+            1,
+            vec![package("a", &[]), package("b", &["a"]), package("c", &[])],
+        )));
+
+        let id = |id: &str| package::Id::from(id.to_string());
+
+        let mut tx = registry.transaction().unwrap();
+        tx.add(vec![id("a"), id("c")]).await.unwrap();
+
+        // Declared by the incoming side
+        let conflicts = tx.conflicts_with(&[id("b")]).await.unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].package.id, id("b"));
+        assert_eq!(conflicts[0].conflicting.id, id("a"));
+
+        let result = tx.add(vec![id("b")]).await;
+        assert!(matches!(result, Err(transaction::Error::Conflicts(c)) if c.len() == 1));
+
+        // The failed add leaves the transaction as it was
+        let mut ids = tx.finalize().cloned().collect::<Vec<_>>();
+        ids.sort_by_key(|id| String::from(id.clone()));
+        assert_eq!(ids, [id("a"), id("c")]);
+    }
+
+    #[tokio::test]
+    async fn test_orphans() {
+        let mut registry = Registry::default();
+
+        let provider = |name: &str| Provider::from_name(name).unwrap();
+        let package = |id: &str, dependencies: &[&str]| Package {
+            id: package::Id::from(id.to_string()),
+            meta: package::Meta {
+                name: package::Name::from(id.to_string()),
+                version_identifier: Default::default(),
+                source_release: Default::default(),
+                build_release: Default::default(),
+                architecture: Default::default(),
+                summary: Default::default(),
+                description: Default::default(),
+                source_id: Default::default(),
+                homepage: Default::default(),
+                licenses: Default::default(),
+                dependencies: dependencies
+                    .iter()
+                    .map(|name| format!("name({name})").parse().unwrap())
+                    .collect(),
+                providers: [provider(id)].into_iter().collect(),
+                conflicts: Default::default(),
+                uri: Default::default(),
+                hash: Default::default(),
+                download_size: Default::default(),
+                installed_size: Default::default(),
+            },
+            flags: package::Flags::INSTALLED,
+        };
+
+        registry.add_plugin(Plugin::Test(plugin::Test::new(
+            1,
+            vec![
+                package("a", &["b"]),
+                package("b", &[]),
+                package("c", &["d"]),
+                package("d", &[]),
+            ],
+        )));
+
+        let id = |id: &str| package::Id::from(id.to_string());
+
+        let mut tx = registry
+            .transaction_with_installed(vec![id("a"), id("b"), id("c"), id("d")])
+            .await
+            .unwrap();
+        tx.remove_orphans(&[id("a")]);
+
+        let finalized = tx.finalize().cloned().collect::<HashSet<_>>();
+        assert_eq!(finalized, [id("a"), id("b")].into_iter().collect());
+    }
 }
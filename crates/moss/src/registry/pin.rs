@@ -0,0 +1,166 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Rules holding packages back or pinning them to a
+//! repository or version
+
+use std::collections::BTreeMap;
+
+use config::Config;
+use serde::{Deserialize, Serialize};
+
+use crate::{package, repository, Package};
+
+/// Pin & hold rules, by package name
+///
+/// Loaded from the `pin` config domain, i.e. `/etc/moss/pin.d/*.yaml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Rules(BTreeMap<package::Name, Rule>);
+
+impl Rules {
+    pub fn with(items: impl IntoIterator<Item = (package::Name, Rule)>) -> Self {
+        Self(items.into_iter().collect())
+    }
+
+    pub fn get(&self, name: &package::Name) -> Option<&Rule> {
+        self.0.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&package::Name, &Rule)> {
+        self.0.iter()
+    }
+}
+
+impl Config for Rules {
+    fn domain() -> String {
+        "pin".into()
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self(self.0.into_iter().chain(other.0).collect())
+    }
+}
+
+/// Constraints on which candidates of a package can be selected
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    /// Keep the installed version, never replacing it
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub hold: bool,
+    /// Only select candidates from this repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<repository::Id>,
+    /// Only select candidates of this version, i.e. `1.2.3`,
+    /// `1.2.3-4` (source release) or `1.2.3-4-1` (build release)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl Rule {
+    /// Returns true if this rule doesn't constrain anything
+    pub fn is_empty(&self) -> bool {
+        !self.hold && self.repository.is_none() && self.version.is_none()
+    }
+
+    /// Returns true if `candidate`, provided by `repository` (if any),
+    /// can be selected while `installed` is the installed package of the same name
+    pub fn allows(
+        &self,
+        candidate: &Package,
+        repository: Option<&repository::Id>,
+        installed: Option<&Package>,
+    ) -> bool {
+        if let Some(installed) = installed {
+            // Held packages can't be replaced
+            if self.hold {
+                return installed.id == candidate.id;
+            }
+            // Keeping what's installed never breaks a pin, but a repository
+            // must satisfy the pin to provide it
+            if installed.id == candidate.id && repository.is_none() {
+                return true;
+            }
+        }
+
+        if let Some(pinned) = &self.repository {
+            if repository != Some(pinned) {
+                return false;
+            }
+        }
+
+        if let Some(version) = &self.version {
+            let meta = &candidate.meta;
+            let source = format!("{}-{}", meta.version_identifier, meta.source_release);
+            let build = format!("{source}-{}", meta.build_release);
+
+            if ![&meta.version_identifier, &source, &build].contains(&version) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn package(id: &str, version: &str, source_release: u64) -> Package {
+        Package {
+            id: package::Id::from(id.to_string()),
+            meta: package::Meta {
+                name: package::Name::from("gcc".to_string()),
+                version_identifier: version.to_string(),
+                source_release,
+                build_release: 1,
+                architecture: Default::default(),
+                summary: Default::default(),
+                description: Default::default(),
+                source_id: Default::default(),
+                homepage: Default::default(),
+                licenses: Default::default(),
+                dependencies: Default::default(),
+                providers: Default::default(),
+                conflicts: Default::default(),
+                uri: Default::default(),
+                hash: Default::default(),
+                download_size: Default::default(),
+                installed_size: Default::default(),
+            },
+            flags: package::Flags::AVAILABLE,
+        }
+    }
+
+    #[test]
+    fn allows() {
+        let installed = package("a", "13.2.0", 5);
+        let newer = package("b", "14.1.0", 6);
+        let volatile = repository::Id::new("volatile".to_string());
+        let unstable = repository::Id::new("unstable".to_string());
+
+        let hold = Rule {
+            hold: true,
+            ..Default::default()
+        };
+        assert!(hold.allows(&installed, None, Some(&installed)));
+        assert!(!hold.allows(&newer, Some(&volatile), Some(&installed)));
+        assert!(hold.allows(&newer, Some(&volatile), None));
+
+        let pin = Rule {
+            repository: Some(volatile.clone()),
+            version: Some("14.1.0-6".to_string()),
+            ..Default::default()
+        };
+        assert!(pin.allows(&installed, None, Some(&installed)));
+        assert!(!pin.allows(&installed, Some(&unstable), Some(&installed)));
+        assert!(pin.allows(&newer, Some(&volatile), Some(&installed)));
+        assert!(!pin.allows(&newer, Some(&unstable), Some(&installed)));
+        assert!(!pin.allows(&package("c", "14.1.0", 7), Some(&volatile), None));
+    }
+}
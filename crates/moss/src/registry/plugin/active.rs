@@ -89,6 +89,12 @@ impl Active {
             .await
     }
 
+    /// Query packages matching the search `term`
+    pub async fn search(&self, term: &str, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Search(term.to_string())))
+            .await
+    }
+
     pub fn priority(&self) -> u64 {
         u64::MAX
     }
@@ -2,14 +2,22 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
 
 use crate::package::{self, meta, Meta, MissingMetaFieldError, Package};
-use crate::registry::job::Job;
+use crate::registry::job::{self, Job};
 use crate::{stone, Provider};
 use ::stone::read::PayloadKind;
 use futures::StreamExt;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
+use tokio::task;
+use url::Url;
 
 // TODO:
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
@@ -20,8 +28,11 @@ pub struct Cobble {
 
 impl Cobble {
     /// Add a package to the cobble set
+    ///
+    /// The package is identified by its hash, the same as
+    /// packages from a repository index
     pub async fn add_package(&mut self, path: impl Into<PathBuf>) -> Result<meta::Id, Error> {
-        let path = path.into();
+        let path = path.into().canonicalize()?;
         let (_, payloads) = stone::stream_payloads(&path).await?;
 
         // Grab the metapayload
@@ -38,9 +49,20 @@ impl Cobble {
             .await
             .ok_or(Error::MissingMetaPayload)?;
 
-        // Whack it into the cobbler
-        let meta = Meta::from_stone_payload(&metadata.body)?;
-        let id = meta.id();
+        let (hash, size) = hash_file(&path).await?;
+
+        // Whack it into the cobbler, fetchable from its local path
+        let meta = Meta {
+            uri: Some(
+                Url::from_file_path(&path)
+                    .map_err(|_| Error::InvalidPath(path.clone()))?
+                    .to_string(),
+            ),
+            hash: Some(hash.clone()),
+            download_size: Some(size),
+            ..Meta::from_stone_payload(&metadata.body)?
+        };
+        let id = meta::Id::from(package::Id::from(hash));
         let ret = id.clone();
 
         self.packages.insert(id, State { path, meta });
@@ -80,15 +102,41 @@ impl Cobble {
         self.query(flags, |meta| meta.name == *package_name)
     }
 
+    pub fn search(&self, term: &str, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, |meta| meta.matches(term))
+    }
+
     pub fn priority(&self) -> u64 {
         u64::MAX
     }
 
-    pub fn fetch_item(&self, id: &package::Id) -> Job {
-        todo!()
+    pub fn fetch_item(&self, id: &package::Id) -> Option<Job> {
+        let state = self.packages.get(&meta::Id::from(id.clone()))?;
+
+        Some(Job {
+            domain: job::Domain::Package(id.clone()),
+            origin: job::Origin::LocalFile(state.path.clone()),
+            mirrors: vec![],
+            check: state.meta.hash.clone().map(job::CheckType::Sha256),
+            size: state.meta.download_size.unwrap_or_default(),
+        })
     }
 }
 
+/// Returns the sha256 & size of the file at `path`
+async fn hash_file(path: &Path) -> Result<(String, u64), Error> {
+    let path = path.to_owned();
+
+    task::spawn_blocking(move || {
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut File::open(path)?, &mut hasher)?;
+
+        Ok((hex::encode(hasher.finalize()), size))
+    })
+    .await
+    .expect("join handle")
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct State {
     path: PathBuf,
@@ -111,6 +159,12 @@ pub enum Error {
     #[error("Missing metadata payload")]
     MissingMetaPayload,
 
+    #[error("Invalid package path {0:?}")]
+    InvalidPath(PathBuf),
+
+    #[error("read")]
+    Read(#[from] io::Error),
+
     #[error("io")]
     Io(#[from] stone::read::Error),
 
@@ -22,7 +22,7 @@ pub use self::test::Test;
 use super::job::Job;
 
 mod active;
-mod cobble;
+pub mod cobble;
 mod repository;
 
 /// A [`Registry`] plugin that enables querying [`Package`] information.
@@ -96,6 +96,18 @@ impl Plugin {
         })
     }
 
+    /// Returns a list of packages matching the search `term` and `flags`
+    pub async fn search(&self, term: &str, flags: package::Flags) -> package::Sorted<Vec<Package>> {
+        package::Sorted::new(match self {
+            Plugin::Active(plugin) => plugin.search(term, flags).await,
+            Plugin::Cobble(plugin) => plugin.search(term, flags),
+            Plugin::Repository(plugin) => plugin.search(term, flags).await,
+
+            #[cfg(test)]
+            Plugin::Test(plugin) => plugin.search(term, flags),
+        })
+    }
+
     /// Plugin priority
     ///
     /// Higher priority = better chance of selection
@@ -110,18 +122,36 @@ impl Plugin {
         }
     }
 
+    /// Returns the id of the repository this plugin provides packages from
+    pub fn repository(&self) -> Option<&crate::repository::Id> {
+        match self {
+            Plugin::Repository(plugin) => Some(plugin.id()),
+            _ => None,
+        }
+    }
+
     /// Request that the item is fetched from its location into a storage
-    /// medium.
-    pub fn fetch_item(&self, id: &package::Id) -> Job {
+    /// medium. Returns `None` if the plugin can't fetch the `package`.
+    pub async fn fetch_item(&self, id: &package::Id) -> Option<Job> {
         match self {
-            Plugin::Active(_) => panic!("Active plugin queried for fetch"),
+            // Installed packages are already local
+            Plugin::Active(_) => None,
             Plugin::Cobble(plugin) => plugin.fetch_item(id),
-            Plugin::Repository(plugin) => plugin.fetch_item(id),
+            Plugin::Repository(plugin) => plugin.fetch_item(id).await,
 
             #[cfg(test)]
             Plugin::Test(plugin) => plugin.fetch_item(id),
         }
     }
+
+    /// Like [`Plugin::fetch_item`], but the job always fetches
+    /// the full package rather than a delta
+    pub async fn fetch_package(&self, id: &package::Id) -> Option<Job> {
+        match self {
+            Plugin::Repository(plugin) => plugin.fetch_package(id).await,
+            _ => self.fetch_item(id).await,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -173,15 +203,24 @@ pub mod test {
                 .collect()
         }
 
-        pub fn fetch_item(&self, id: &package::Id) -> Job {
-            Job {
+        pub fn search(&self, term: &str, flags: package::Flags) -> Vec<Package> {
+            self.packages
+                .iter()
+                .filter(|p| p.meta.matches(term) && p.flags.contains(flags))
+                .cloned()
+                .collect()
+        }
+
+        pub fn fetch_item(&self, id: &package::Id) -> Option<Job> {
+            Some(Job {
                 domain: crate::registry::job::Domain::Package(id.clone()),
                 origin: crate::registry::job::Origin::LocalFile(PathBuf::from(
                     "test/bash-completion-2.11-1-1-x86_64.stone",
                 )),
+                mirrors: vec![],
                 check: None,
                 size: 168864,
-            }
+            })
         }
     }
 }
@@ -3,22 +3,41 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use log::warn;
+use stone::payload::layout;
+use url::Url;
 
 use crate::{
+    client::cache,
     db,
     package::{self, Package},
-    registry::job::Job,
-    repository, Provider,
+    registry::job::{self, Job},
+    repository, Installation, Provider,
 };
 
 #[derive(Debug)]
 pub struct Repository {
     active: repository::Active,
+    installation: Installation,
+    layout_db: db::layout::Database,
 }
 
 impl Repository {
-    pub fn new(active: repository::Active) -> Self {
-        Self { active }
+    /// Return a new Repository plugin, using the layouts of packages
+    /// already unpacked to the `installation` to decide when deltas can be used
+    pub fn new(
+        active: repository::Active,
+        installation: Installation,
+        layout_db: db::layout::Database,
+    ) -> Self {
+        Self {
+            active,
+            installation,
+            layout_db,
+        }
+    }
+
+    pub fn id(&self) -> &repository::Id {
+        &self.active.id
     }
 
     pub fn priority(&self) -> u64 {
@@ -93,8 +112,79 @@ impl Repository {
             .await
     }
 
-    pub fn fetch_item(&self, id: &package::Id) -> Job {
-        todo!()
+    /// Query packages matching the search `term`
+    pub async fn search(&self, term: &str, flags: package::Flags) -> Vec<Package> {
+        self.query(flags, Some(db::meta::Filter::Search(term.to_string())))
+            .await
+    }
+
+    /// Return a [`Job`] to fetch the package, preferring the smallest delta
+    /// which applies to a package whose assets are all cached
+    pub async fn fetch_item(&self, id: &package::Id) -> Option<Job> {
+        let mut deltas = match self.active.db.deltas(id).await {
+            Ok(deltas) => deltas,
+            Err(error) => {
+                warn!("failed to query repository deltas: {error}");
+                vec![]
+            }
+        };
+        deltas.sort_by_key(|delta| delta.download_size);
+
+        for delta in deltas {
+            let Ok(url) = self.active.repository.uri.join(&delta.uri) else {
+                continue;
+            };
+
+            if self.has_assets(&delta.from).await {
+                return Some(Job {
+                    domain: job::Domain::Package(id.clone()),
+                    mirrors: self.active.repository.mirrored(&url),
+                    origin: job::Origin::RemoteFile(url),
+                    check: Some(job::CheckType::Sha256(delta.hash)),
+                    size: delta.download_size,
+                });
+            }
+        }
+
+        self.fetch_package(id).await
+    }
+
+    /// Return a [`Job`] to fetch the full package, never a delta
+    pub async fn fetch_package(&self, id: &package::Id) -> Option<Job> {
+        let package = self.package(id).await?;
+
+        let url = package.meta.uri?.parse::<Url>().ok()?;
+
+        Some(Job {
+            domain: job::Domain::Package(id.clone()),
+            mirrors: self.active.repository.mirrored(&url),
+            origin: job::Origin::RemoteFile(url),
+            check: package.meta.hash.map(job::CheckType::Sha256),
+            size: package.meta.download_size.unwrap_or_default(),
+        })
+    }
+
+    /// Returns true if every asset of a previously unpacked package is cached
+    async fn has_assets(&self, id: &package::Id) -> bool {
+        let layouts = match self.layout_db.query(id).await {
+            Ok(layouts) => layouts,
+            Err(error) => {
+                warn!("failed to query package layout: {error}");
+                return false;
+            }
+        };
+
+        // Never unpacked here
+        if layouts.is_empty() {
+            return false;
+        }
+
+        let digests = layouts.iter().filter_map(|layout| match &layout.entry {
+            layout::Entry::Regular(digest, _) => Some(*digest),
+            _ => None,
+        });
+
+        cache::assets_exist(digests, &self.installation).await
     }
 }
 
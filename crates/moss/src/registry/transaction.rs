@@ -2,12 +2,15 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
+use std::fmt;
+
 use dag::Dag;
 use futures::{StreamExt, TryFutureExt};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{package, Provider, Registry};
+use crate::{package, Package, Provider, Registry};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Id(u64);
@@ -28,6 +31,27 @@ enum Lookup {
     Global,
 }
 
+/// A package declaring a conflict with a provider of another package
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    /// The package declaring the conflict
+    pub package: Package,
+    /// The package which provides the conflict
+    pub conflicting: Package,
+    /// The conflicting provider
+    pub provider: Provider,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} conflicts with {} ({})",
+            self.package.meta.name, self.conflicting.meta.name, self.provider
+        )
+    }
+}
+
 /// A Transaction is used to modify one system state to another
 #[derive(Clone, Debug)]
 pub struct Transaction<'a> {
@@ -64,7 +88,19 @@ pub(super) async fn new_with_installed(
 
 impl<'a> Transaction<'a> {
     /// Add a package to this transaction
+    ///
+    /// Returns an error if any incoming package is disallowed by the [`pin::Rules`]
+    ///
+    /// [`pin::Rules`]: super::pin::Rules
     pub async fn add(&mut self, incoming: Vec<package::Id>) -> Result<(), Error> {
+        for id in &incoming {
+            if let Some(package) = self.registry.by_id(id).boxed().next().await {
+                if !self.registry.is_allowed_id(id).await {
+                    return Err(Error::Pinned(package.meta.name));
+                }
+            }
+        }
+
         self.update(incoming, Lookup::Global).await
     }
 
@@ -83,13 +119,55 @@ impl<'a> Transaction<'a> {
         Ok(())
     }
 
+    /// Remove all packages which are no longer reachable from the
+    /// `explicit` packages, i.e. orphaned transitive dependencies
+    pub fn remove_orphans(&mut self, explicit: &[package::Id]) {
+        let reachable = self.packages.subgraph(explicit);
+
+        let orphans = self
+            .packages
+            .iter_nodes()
+            .filter(|package| !reachable.node_exists(package))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for package in orphans {
+            self.packages.remove_node(&package);
+        }
+    }
+
+    /// Return all conflicts between the packages of this transaction and
+    /// `packages`, as declared by either side
+    pub async fn conflicts_with(&self, packages: &[package::Id]) -> Result<Vec<Conflict>, Error> {
+        let ours = self.packages(self.packages.iter_nodes()).await?;
+        let theirs = self
+            .packages(packages.iter().filter(|id| !self.packages.node_exists(id)))
+            .await?;
+
+        Ok(clashes(&ours, &theirs)
+            .chain(clashes(&theirs, &ours))
+            .collect())
+    }
+
     /// Return the package IDs in the fully baked configuration
     pub fn finalize(&self) -> impl Iterator<Item = &package::Id> + '_ {
         self.packages.topo()
     }
 
-    /// Update internal package graph with all incoming packages & their deps
+    /// Update internal package graph with all incoming packages & their deps,
+    /// leaving it untouched if any can't be added
     async fn update(&mut self, incoming: Vec<package::Id>, lookup: Lookup) -> Result<(), Error> {
+        let previous = self.packages.clone();
+
+        let result = self.extend(incoming, lookup).await;
+        if result.is_err() {
+            self.packages = previous;
+        }
+
+        result
+    }
+
+    async fn extend(&mut self, incoming: Vec<package::Id>, lookup: Lookup) -> Result<(), Error> {
         let mut items = incoming;
 
         loop {
@@ -137,15 +215,47 @@ impl<'a> Transaction<'a> {
             items = next;
         }
 
+        // Installed packages are already settled, only newly
+        // added packages can introduce a conflict
+        if let Lookup::Global = lookup {
+            let packages = self.packages(self.packages.iter_nodes()).await?;
+            let conflicts = clashes(&packages, &packages).collect::<Vec<_>>();
+
+            if !conflicts.is_empty() {
+                return Err(Error::Conflicts(conflicts));
+            }
+        }
+
         Ok(())
     }
 
+    /// Look up the [`Package`] for each id
+    async fn packages(
+        &self,
+        ids: impl IntoIterator<Item = &package::Id>,
+    ) -> Result<Vec<Package>, Error> {
+        let mut packages = vec![];
+
+        for id in ids {
+            let package = self
+                .registry
+                .by_id(id)
+                .boxed()
+                .next()
+                .await
+                .ok_or(Error::NoCandidate(id.clone().into()))?;
+            packages.push(package);
+        }
+
+        Ok(packages)
+    }
+
     /// Attempt to resolve the filterered provider
     async fn resolve_provider(&self, filter: ProviderFilter) -> Result<package::Id, Error> {
         match filter {
             ProviderFilter::All(provider) => self
                 .registry
-                .by_provider(&provider, package::Flags::AVAILABLE)
+                .allowed_by_provider(&provider, package::Flags::AVAILABLE)
                 .boxed()
                 .next()
                 .await
@@ -195,11 +305,42 @@ impl<'a> Transaction<'a> {
     }
 }
 
+/// Every conflict declared by a package in `packages` against
+/// a provider of a package in `others`
+fn clashes<'a>(
+    packages: &'a [Package],
+    others: &'a [Package],
+) -> impl Iterator<Item = Conflict> + 'a {
+    packages.iter().flat_map(move |package| {
+        others
+            .iter()
+            .filter(move |other| other.id != package.id)
+            .flat_map(move |other| {
+                package
+                    .meta
+                    .conflicts
+                    .iter()
+                    .filter(|conflict| other.meta.providers.contains(conflict))
+                    .map(move |conflict| Conflict {
+                        package: package.clone(),
+                        conflicting: other.clone(),
+                        provider: conflict.clone(),
+                    })
+            })
+    })
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("No such name: {0}")]
     NoCandidate(String),
 
+    #[error("{0} is held or pinned to another version")]
+    Pinned(package::Name),
+
+    #[error("conflicting packages: {}", .0.iter().join(", "))]
+    Conflicts(Vec<Conflict>),
+
     #[error("Not yet implemented")]
     NotImplemented,
 
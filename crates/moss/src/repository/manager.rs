@@ -2,19 +2,21 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 use futures::{future, stream, StreamExt, TryStreamExt};
 use thiserror::Error;
 use tokio::{fs, io};
+use tui::Stylize;
+use url::Url;
 use xxhash_rust::xxh3::xxh3_64;
 
 use crate::db::meta;
 use crate::{environment, stone};
 use crate::{package, Installation};
 
-use crate::repository::{self, Repository};
+use crate::repository::{self, trust, Repository};
 
 enum Source {
     System(config::Manager),
@@ -38,6 +40,7 @@ pub struct Manager {
     source: Source,
     installation: Installation,
     repositories: HashMap<repository::Id, repository::Active>,
+    trust: trust::Store,
 }
 
 impl Manager {
@@ -81,6 +84,18 @@ impl Manager {
             Source::Explicit { repos, .. } => repos.clone(),
         };
 
+        // Explicit sources aren't configured on the installation, i.e. a build
+        // root, so verify them against the keys trusted by the host instead
+        let trust = match &source {
+            Source::System(config) => config.try_load::<trust::Store>().await?,
+            Source::Explicit { .. } => {
+                config::Manager::system("/", environment::NAME)
+                    .try_load::<trust::Store>()
+                    .await?
+            }
+        }
+        .unwrap_or_default();
+
         // Open all repo meta dbs and collect into hash map
         let repositories =
             future::try_join_all(configs.into_iter().map(|(id, repository)| async {
@@ -96,6 +111,7 @@ impl Manager {
             source,
             installation,
             repositories,
+            trust,
         })
     }
 
@@ -118,6 +134,16 @@ impl Manager {
             config.save(&id, &map).await.map_err(Error::SaveConfig)?;
         }
 
+        self.use_repository(id, repository).await
+    }
+
+    /// Use a [`Repository`] without saving its configuration, so
+    /// it's forgotten once the manager is reloaded
+    pub async fn use_repository(
+        &mut self,
+        id: repository::Id,
+        repository: Repository,
+    ) -> Result<(), Error> {
         let db = open_meta_db(self.source.identifier(), &repository, &self.installation).await?;
 
         self.repositories
@@ -130,11 +156,14 @@ impl Manager {
     /// file and updating it's associated meta database
     pub async fn refresh_all(&mut self) -> Result<(), Error> {
         // Fetch index file + add to meta_db
-        future::try_join_all(
-            self.repositories.iter().map(|(id, state)| {
-                refresh_index(self.source.identifier(), state, &self.installation)
-            }),
-        )
+        future::try_join_all(self.repositories.iter().map(|(id, state)| {
+            refresh_index(
+                self.source.identifier(),
+                state,
+                &self.installation,
+                &self.trust,
+            )
+        }))
         .await?;
 
         Ok(())
@@ -143,7 +172,13 @@ impl Manager {
     /// Refresh a [`Repository`] by Id
     pub async fn refresh(&mut self, id: &repository::Id) -> Result<(), Error> {
         if let Some(repo) = self.repositories.get(id) {
-            refresh_index(self.source.identifier(), repo, &self.installation).await
+            refresh_index(
+                self.source.identifier(),
+                repo,
+                &self.installation,
+                &self.trust,
+            )
+            .await
         } else {
             Err(Error::UnknownRepo(id.clone()))
         }
@@ -167,16 +202,23 @@ impl Manager {
                 !index_file.exists()
             })
             .map(|(id, state)| async {
-                println!("Initializing repo {}...", *id);
+                // Keep stdout clean for machine readable output
+                eprintln!("Initializing repo {}...", *id);
 
-                refresh_index(self.source.identifier(), state, &self.installation).await
+                refresh_index(
+                    self.source.identifier(),
+                    state,
+                    &self.installation,
+                    &self.trust,
+                )
+                .await
             })
             .buffer_unordered(environment::MAX_NETWORK_CONCURRENCY)
             .try_collect::<Vec<_>>()
             .await?;
 
         if !initialized.is_empty() {
-            println!();
+            eprintln!();
         }
 
         Ok(())
@@ -255,12 +297,13 @@ async fn open_meta_db(
 }
 
 /// Fetches a stone index file from the repository URL,
-/// saves it to the repo installation path, then
-/// loads it's metadata into the meta db
+/// verifies it against the `trust` store, saves it to the
+/// repo installation path, then loads it's metadata into the meta db
 async fn refresh_index(
     identifier: &str,
     state: &repository::Active,
     installation: &Installation,
+    trust: &trust::Store,
 ) -> Result<(), Error> {
     let out_dir = cache_dir(identifier, &state.repository, installation);
 
@@ -270,8 +313,19 @@ async fn refresh_index(
 
     let out_path = out_dir.join("stone.index");
 
-    // Fetch index & write to `out_path`
-    repository::fetch_index(state.repository.uri.clone(), &out_path).await?;
+    let fetched_path = out_dir.join("stone.index.new");
+
+    // Fetch index & write to `fetched_path`
+    let url = repository::fetch_index(&state.repository, &fetched_path).await?;
+
+    // Only replace the existing index once the new one is trusted
+    if let Err(error) = verify_index(state, &url, &fetched_path, trust).await {
+        let _ = fs::remove_file(&fetched_path).await;
+        return Err(error);
+    }
+    fs::rename(&fetched_path, &out_path)
+        .await
+        .map_err(Error::WriteIndex)?;
 
     // Wipe db since we're refreshing from a new index file
     state.db.wipe().await?;
@@ -280,35 +334,31 @@ async fn refresh_index(
     let (_, payloads) = stone::stream_payloads(&out_path).await?;
 
     // Update each payload into the meta db
-    payloads
+    let ids = payloads
         .map_err(Error::ReadStone)
         // Batch up to `DB_BATCH_SIZE` payloads
         .chunks(environment::DB_BATCH_SIZE)
         // Transpose error for early bail
         .map(|results| results.into_iter().collect::<Result<Vec<_>, _>>())
-        .try_for_each(|payloads| async {
-            // Construct Meta for each payload
-            let packages = payloads
-                .into_iter()
-                .filter_map(|payload| {
-                    if let stone::read::PayloadKind::Meta(meta) = payload {
-                        Some(meta)
-                    } else {
-                        None
-                    }
-                })
-                .map(|payload| {
-                    let meta = package::Meta::from_stone_payload(&payload.body)?;
-
-                    // Create id from hash of meta
-                    let hash = meta.hash.clone().ok_or(Error::MissingMetaField(
-                        stone::payload::meta::Tag::PackageHash,
-                    ))?;
-                    let id = package::Id::from(hash);
-
-                    Ok((id, meta))
-                })
-                .collect::<Result<Vec<_>, Error>>()?;
+        .try_fold(HashSet::new(), |mut ids, payloads| async move {
+            let mut packages = vec![];
+
+            for payload in payloads {
+                let stone::read::PayloadKind::Meta(meta) = payload else {
+                    continue;
+                };
+
+                let meta = package::Meta::from_stone_payload(&meta.body)?;
+
+                // Create id from hash of meta
+                let hash = meta.hash.clone().ok_or(Error::MissingMetaField(
+                    stone::payload::meta::Tag::PackageHash,
+                ))?;
+                let id = package::Id::from(hash);
+
+                ids.insert(id.clone());
+                packages.push((id, meta));
+            }
 
             // Batch add to db
             //
@@ -316,10 +366,95 @@ async fn refresh_index(
             // package has 13 binds x 1k batch size = 17k. This leaves us
             // overhead to add more binds in the future, otherwise we can
             // lower the `DB_BATCH_SIZE`.
-            state.db.batch_add(packages).await.map_err(Error::Database)
+            state
+                .db
+                .batch_add(packages)
+                .await
+                .map_err(Error::Database)?;
+
+            Ok(ids)
         })
         .await?;
 
+    refresh_deltas(state, &url, &out_dir, trust, &ids).await
+}
+
+/// Fetches the deltas published alongside the index fetched from `url`, verifies
+/// them against the `trust` store and loads those producing one of `ids` into the meta db
+async fn refresh_deltas(
+    state: &repository::Active,
+    url: &Url,
+    out_dir: &Path,
+    trust: &trust::Store,
+    ids: &HashSet<package::Id>,
+) -> Result<(), Error> {
+    let out_path = out_dir.join("stone.deltas");
+
+    let Some((url, bytes)) = repository::fetch_deltas(url).await? else {
+        let _ = fs::remove_file(&out_path).await;
+        return Ok(());
+    };
+
+    fs::write(&out_path, &bytes)
+        .await
+        .map_err(Error::WriteIndex)?;
+
+    // Unverified indexes were already warned about
+    if !trust.is_empty() {
+        verify_index(state, &url, &out_path, trust).await?;
+    }
+
+    let (_, payloads) = stone::stream_payloads(&out_path).await?;
+
+    let mut deltas = vec![];
+    for payload in payloads.try_collect::<Vec<_>>().await? {
+        let stone::read::PayloadKind::Meta(meta) = payload else {
+            continue;
+        };
+
+        // Skip deltas for packages missing from the index
+        if let Some(delta) = package::Delta::from_stone_payload(&meta.body)? {
+            if ids.contains(&delta.to) {
+                deltas.push(delta);
+            }
+        }
+    }
+
+    for chunk in deltas.chunks(environment::DB_BATCH_SIZE) {
+        state.db.batch_add_deltas(chunk.to_vec()).await?;
+    }
+
+    Ok(())
+}
+
+/// Verifies the index at `path`, fetched from `url`, was signed by a key in the `trust` store
+///
+/// Unsigned indexes are accepted, with a warning, when no keys are trusted
+async fn verify_index(
+    state: &repository::Active,
+    url: &Url,
+    path: &Path,
+    trust: &trust::Store,
+) -> Result<(), Error> {
+    if trust.is_empty() {
+        eprintln!(
+            "{} no trusted keys, index of repo {} accepted without verification",
+            "Warning".yellow(),
+            state.id
+        );
+        return Ok(());
+    }
+
+    let signature = repository::fetch_signature(url)
+        .await?
+        .ok_or_else(|| Error::Unsigned(state.id.clone()))?;
+
+    let index = fs::read(path).await.map_err(Error::WriteIndex)?;
+
+    trust
+        .verify(&index, &signature)
+        .map_err(|error| Error::Signature(state.id.clone(), error))?;
+
     Ok(())
 }
 
@@ -335,12 +470,20 @@ pub enum Error {
     RemoveDir(#[source] io::Error),
     #[error("fetch index file")]
     FetchIndex(#[from] repository::FetchError),
+    #[error("write index file")]
+    WriteIndex(#[source] io::Error),
+    #[error("index of repo {0} is not signed")]
+    Unsigned(repository::Id),
+    #[error("index of repo {0}")]
+    Signature(repository::Id, #[source] trust::Error),
     #[error("read index file")]
     ReadStone(#[from] stone::read::Error),
     #[error("meta db")]
     Database(#[from] meta::Error),
     #[error("save config")]
     SaveConfig(#[source] config::SaveError),
+    #[error("load config")]
+    LoadConfig(#[from] config::LoadError),
     #[error("unknown repo")]
     UnknownRepo(repository::Id),
 }
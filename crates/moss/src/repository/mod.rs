@@ -2,16 +2,13 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{collections::HashMap, fmt, path::Path};
+use std::{collections::HashMap, fmt, iter, path::Path};
 
 use config::Config;
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::{
-    fs::File,
-    io::{self, AsyncWriteExt},
-};
+use tokio::{fs, io};
 use url::Url;
 
 use crate::{db::meta, request};
@@ -19,6 +16,7 @@ use crate::{db::meta, request};
 pub use self::manager::Manager;
 
 pub mod manager;
+pub mod trust;
 
 /// A unique [`Repository`] identifier
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -55,11 +53,38 @@ impl From<String> for Id {
 }
 
 /// Repository configuration data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Repository {
     pub description: String,
     pub uri: Url,
     pub priority: Priority,
+    /// Base URIs of mirrors hosting the same files as the
+    /// directory of `uri`, tried in order when it fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<Url>,
+}
+
+impl Repository {
+    /// Returns the locations of `url`, a file of this repository, on each mirror
+    pub fn mirrored(&self, url: &Url) -> Vec<Url> {
+        let Ok(base) = self.uri.join("./") else {
+            return vec![];
+        };
+        let Some(relative) = url.as_str().strip_prefix(base.as_str()) else {
+            return vec![];
+        };
+
+        self.mirrors
+            .iter()
+            .filter_map(|mirror| {
+                let mut mirror = mirror.clone();
+                if !mirror.path().ends_with('/') {
+                    mirror.set_path(&format!("{}/", mirror.path()));
+                }
+                mirror.join(relative).ok()
+            })
+            .collect()
+    }
 }
 
 /// An active repository that has been
@@ -125,6 +150,10 @@ impl Map {
     pub fn iter(&self) -> impl Iterator<Item = (&Id, &Repository)> {
         self.0.iter()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 impl IntoIterator for Map {
@@ -146,18 +175,69 @@ impl Config for Map {
     }
 }
 
-async fn fetch_index(url: Url, out_path: impl AsRef<Path>) -> Result<(), FetchError> {
-    let mut stream = request::get(url).await?;
+/// Fetch the index of `repository` from its uri or any mirror, returning the url it was fetched from
+async fn fetch_index(
+    repository: &Repository,
+    out_path: impl AsRef<Path>,
+) -> Result<Url, FetchError> {
+    let out_path = out_path.as_ref();
+
+    // Indexes change between refreshes, so never resume a previous fetch
+    if fs::try_exists(out_path).await? {
+        fs::remove_file(out_path).await?;
+    }
+
+    let urls = iter::once(repository.uri.clone()).chain(repository.mirrored(&repository.uri));
+
+    Ok(request::download(urls, out_path, |_| {}).await?)
+}
 
-    let mut out = File::create(out_path).await?;
+/// Fetch the detached signature of the file at `url`, if one exists
+async fn fetch_signature(url: &Url) -> Result<Option<String>, FetchError> {
+    let mut url = url.clone();
+    url.set_path(&format!("{}.sig", url.path()));
+
+    Ok(fetch_optional(url)
+        .await?
+        .map(|signature| String::from_utf8_lossy(&signature).into_owned()))
+}
+
+/// Fetch the deltas published alongside the index fetched from `url`, if any,
+/// returning the url they were fetched from
+///
+/// Deltas are kept out of the index itself so clients unaware of them can still read it
+async fn fetch_deltas(url: &Url) -> Result<Option<(Url, Vec<u8>)>, FetchError> {
+    let Ok(url) = url.join("stone.deltas") else {
+        return Ok(None);
+    };
+
+    Ok(fetch_optional(url.clone())
+        .await?
+        .map(|deltas| (url, deltas)))
+}
+
+/// Fetch the file at `url`, returning `None` if it doesn't exist
+async fn fetch_optional(url: Url) -> Result<Option<Vec<u8>>, FetchError> {
+    let mut stream = match request::get(url).await {
+        Ok(stream) => stream,
+        Err(request::Error::Fetch(error))
+            if error.status() == Some(reqwest::StatusCode::NOT_FOUND) =>
+        {
+            return Ok(None)
+        }
+        Err(request::Error::Read(error)) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(None)
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut bytes = vec![];
 
     while let Some(chunk) = stream.next().await {
-        out.write_all(&chunk?).await?;
+        bytes.extend_from_slice(&chunk?);
     }
 
-    out.flush().await?;
-
-    Ok(())
+    Ok(Some(bytes))
 }
 
 #[derive(Debug, Error)]
@@ -167,3 +247,46 @@ pub enum FetchError {
     #[error("io")]
     Io(#[from] io::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mirrored() {
+        let repository = Repository {
+            description: String::default(),
+            uri: "https://cdn.example.com/x86_64/stone.index"
+                .parse()
+                .unwrap(),
+            priority: Priority::new(0),
+            mirrors: vec![
+                "https://mirror.example.com/serpent/x86_64".parse().unwrap(),
+                "https://other.example.com/".parse().unwrap(),
+            ],
+        };
+
+        let url = "https://cdn.example.com/x86_64/pool/b/bash-1.stone"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            repository
+                .mirrored(&url)
+                .iter()
+                .map(Url::as_str)
+                .collect::<Vec<_>>(),
+            vec![
+                "https://mirror.example.com/serpent/x86_64/pool/b/bash-1.stone",
+                "https://other.example.com/pool/b/bash-1.stone",
+            ]
+        );
+        assert!(repository
+            .mirrored(
+                &"https://elsewhere.example.com/bash-1.stone"
+                    .parse()
+                    .unwrap()
+            )
+            .is_empty());
+    }
+}
@@ -0,0 +1,139 @@
+// SPDX-FileCopyrightText: Copyright © 2020-2024 Serpent OS Developers
+//
+// SPDX-License-Identifier: MPL-2.0
+
+//! Signing of repository indexes & verification against
+//! a store of trusted public keys
+
+use std::{collections::BTreeMap, fmt};
+
+use config::Config;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Trusted public keys, by name
+///
+/// Loaded from the `trust` config domain, i.e. `/etc/moss/trust.d/*.yaml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Store(BTreeMap<String, String>);
+
+impl Store {
+    /// Returns true if no keys are trusted
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Verify `signature` of `data`, returning the name of
+    /// the trusted key it was signed with
+    ///
+    /// Keys are decoded here rather than when loading the store so
+    /// a malformed key can't silently disable verification
+    pub fn verify(&self, data: &[u8], signature: &str) -> Result<&str, Error> {
+        let signature = decode_signature(signature)?;
+
+        for (name, key) in &self.0 {
+            let key =
+                PublicKey::try_from(key.clone()).map_err(|_| Error::InvalidKey(name.clone()))?;
+
+            if key.0.verify(data, &signature).is_ok() {
+                return Ok(name);
+            }
+        }
+
+        Err(Error::Untrusted)
+    }
+}
+
+impl Config for Store {
+    fn domain() -> String {
+        "trust".into()
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self(self.0.into_iter().chain(other.0).collect())
+    }
+}
+
+/// A hex encoded ed25519 public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey(VerifyingKey);
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        hex::encode(self.0.as_bytes()).fmt(f)
+    }
+}
+
+impl TryFrom<String> for PublicKey {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let bytes = decode_array(&value)?;
+        Ok(Self(
+            VerifyingKey::from_bytes(&bytes).map_err(|_| Error::InvalidEncoding)?,
+        ))
+    }
+}
+
+/// A hex encoded ed25519 secret key used to sign indexes
+pub struct SecretKey(SigningKey);
+
+impl SecretKey {
+    pub fn from_hex(value: &str) -> Result<Self, Error> {
+        Ok(Self(SigningKey::from_bytes(&decode_array(value)?)))
+    }
+
+    /// The public key to be trusted by clients
+    pub fn public(&self) -> PublicKey {
+        PublicKey(self.0.verifying_key())
+    }
+
+    /// Sign `data`, returning the hex encoded signature
+    pub fn sign(&self, data: &[u8]) -> String {
+        hex::encode(self.0.sign(data).to_bytes())
+    }
+}
+
+fn decode_signature(value: &str) -> Result<Signature, Error> {
+    Ok(Signature::from_bytes(&decode_array(value)?))
+}
+
+fn decode_array<const N: usize>(value: &str) -> Result<[u8; N], Error> {
+    hex::decode(value.trim())
+        .map_err(|_| Error::InvalidEncoding)?
+        .try_into()
+        .map_err(|_| Error::InvalidEncoding)
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid hex encoding")]
+    InvalidEncoding,
+    #[error("invalid trusted key {0}")]
+    InvalidKey(String),
+    #[error("not signed by a trusted key")]
+    Untrusted,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sign_verify() {
+        let secret = SecretKey::from_hex(&"42".repeat(32)).unwrap();
+        let other = SecretKey::from_hex(&"24".repeat(32)).unwrap();
+
+        let store = Store(BTreeMap::from([(
+            "serpent".to_string(),
+            secret.public().to_string(),
+        )]));
+
+        let signature = secret.sign(b"index");
+
+        assert_eq!(store.verify(b"index", &signature).unwrap(), "serpent");
+        assert!(store.verify(b"tampered", &signature).is_err());
+        assert!(store.verify(b"index", &other.sign(b"index")).is_err());
+    }
+}
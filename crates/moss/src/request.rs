@@ -2,16 +2,25 @@
 //
 // SPDX-License-Identifier: MPL-2.0
 
-use std::{io, path::PathBuf};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 
 use bytes::Bytes;
 use futures::{
     stream::{self, BoxStream},
     Stream, StreamExt,
 };
+use log::warn;
 use once_cell::sync::Lazy;
+use reqwest::{header, StatusCode};
 use thiserror::Error;
-use tokio::{fs::File, io::AsyncReadExt};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncWriteExt},
+    time,
+};
 use tokio_util::io::ReaderStream;
 use url::Url;
 
@@ -37,6 +46,113 @@ pub async fn get(url: Url) -> Result<BoxStream<'static, Result<Bytes, Error>>, E
     }
 }
 
+/// Download from the first of `urls` able to serve it to `path`, reporting
+/// the number of bytes written so far to `on_progress`
+///
+/// Failed downloads are retried with backoff before failing over to the next url.
+/// Content already written to `path` is resumed with a range request when
+/// the server supports it
+///
+/// Returns the url the download completed from
+pub async fn download(
+    urls: impl IntoIterator<Item = Url>,
+    path: &Path,
+    on_progress: impl Fn(u64),
+) -> Result<Url, Error> {
+    let mut last_error = None;
+
+    for url in urls {
+        for attempt in 0..=environment::DOWNLOAD_RETRIES {
+            if attempt > 0 {
+                let delay = environment::DOWNLOAD_RETRY_DELAY * 2_u32.pow(attempt - 1);
+                time::sleep(delay).await;
+            }
+
+            match download_once(&url, path, &on_progress).await {
+                Ok(()) => return Ok(url),
+                // Nothing another source can fix
+                Err(error @ Error::Write(_)) => return Err(error),
+                Err(error) => {
+                    warn!("download of {url} failed: {error}");
+
+                    let is_transient = error.is_transient();
+                    last_error = Some(error);
+
+                    if !is_transient {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or(Error::NoSource))
+}
+
+async fn download_once(url: &Url, path: &Path, on_progress: &impl Fn(u64)) -> Result<(), Error> {
+    if let Some(source) = url_file(url) {
+        let mut stream = read(source).await?;
+        let mut out = File::create(path).await.map_err(Error::Write)?;
+        let mut written = 0;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            out.write_all(&chunk).await.map_err(Error::Write)?;
+            written += chunk.len() as u64;
+            (on_progress)(written);
+        }
+
+        return out.flush().await.map_err(Error::Write);
+    }
+
+    let mut offset = match fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => 0,
+        Err(error) => return Err(Error::Write(error)),
+    };
+
+    let response = loop {
+        let mut request = CLIENT.get(url.clone());
+        if offset > 0 {
+            request = request.header(header::RANGE, format!("bytes={offset}-"));
+        }
+
+        let response = request.send().await?;
+
+        // Existing content doesn't belong to this resource, start over
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE && offset > 0 {
+            offset = 0;
+            continue;
+        }
+
+        break response.error_for_status()?;
+    };
+
+    // Servers are free to ignore the range and send everything
+    let (mut out, mut written) = if response.status() == StatusCode::PARTIAL_CONTENT {
+        let out = fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .await
+            .map_err(Error::Write)?;
+        (out, offset)
+    } else {
+        (File::create(path).await.map_err(Error::Write)?, 0)
+    };
+    (on_progress)(written);
+
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        out.write_all(&chunk).await.map_err(Error::Write)?;
+        written += chunk.len() as u64;
+        (on_progress)(written);
+    }
+
+    out.flush().await.map_err(Error::Write)
+}
+
 async fn fetch(url: Url) -> Result<impl Stream<Item = Result<Bytes, Error>>, Error> {
     let response = CLIENT.get(url).send().await?;
 
@@ -73,8 +189,30 @@ fn url_file(url: &Url) -> Option<PathBuf> {
 
 #[derive(Debug, Error)]
 pub enum Error {
+    #[error("No source to download from")]
+    NoSource,
     #[error("fetch")]
     Fetch(#[from] reqwest::Error),
     #[error("io")]
     Read(#[from] io::Error),
+    #[error("write")]
+    Write(#[source] io::Error),
+}
+
+impl Error {
+    /// Returns true if retrying the request may succeed
+    fn is_transient(&self) -> bool {
+        match self {
+            Error::Fetch(error) => match error.status() {
+                Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+                None => {
+                    error.is_timeout()
+                        || error.is_connect()
+                        || error.is_request()
+                        || error.is_body()
+                }
+            },
+            Error::NoSource | Error::Read(_) | Error::Write(_) => false,
+        }
+    }
 }
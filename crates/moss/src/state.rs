@@ -5,12 +5,13 @@
 use std::{fmt, io::Write};
 
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use tui::{pretty, Stylize};
 
 use crate::package;
 
 /// Unique identifier for [`State`]
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Id(i64);
 
 impl Id {
@@ -38,14 +39,15 @@ impl fmt::Display for Id {
 }
 
 /// State types
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum Kind {
     /// Automatically constructed state
     Transaction,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct State {
     /// Unique identifier for this state
     pub id: Id,
@@ -61,7 +63,7 @@ pub struct State {
     pub kind: Kind,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Selection {
     pub package: package::Id,
     /// Marks whether the package was explicitly installed
@@ -82,7 +84,7 @@ impl Selection {
     pub fn transitive(package: package::Id) -> Self {
         Self {
             package,
-            explicit: true,
+            explicit: false,
             reason: None,
         }
     }
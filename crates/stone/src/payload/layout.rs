@@ -39,10 +39,10 @@ pub enum Entry {
     Regular(u128, String),
     Symlink(String, String),
     Directory(String),
-
-    // not properly supported
-    CharacterDevice(String),
-    BlockDevice(String),
+    /// Device number (as `makedev`) + target
+    CharacterDevice(u64, String),
+    /// Device number (as `makedev`) + target
+    BlockDevice(u64, String),
     Fifo(String),
     Socket(String),
 }
@@ -53,8 +53,8 @@ impl Entry {
             Entry::Regular(hash, _) => hash.to_be_bytes().to_vec(),
             Entry::Symlink(source, _) => source.as_bytes().to_vec(),
             Entry::Directory(_) => vec![],
-            Entry::CharacterDevice(_) => vec![],
-            Entry::BlockDevice(_) => vec![],
+            Entry::CharacterDevice(device, _) => device.to_be_bytes().to_vec(),
+            Entry::BlockDevice(device, _) => device.to_be_bytes().to_vec(),
             Entry::Fifo(_) => vec![],
             Entry::Socket(_) => vec![],
         }
@@ -65,8 +65,8 @@ impl Entry {
             Entry::Regular(_, target) => target.as_bytes().to_vec(),
             Entry::Symlink(_, target) => target.as_bytes().to_vec(),
             Entry::Directory(target) => target.as_bytes().to_vec(),
-            Entry::CharacterDevice(target) => target.as_bytes().to_vec(),
-            Entry::BlockDevice(target) => target.as_bytes().to_vec(),
+            Entry::CharacterDevice(_, target) => target.as_bytes().to_vec(),
+            Entry::BlockDevice(_, target) => target.as_bytes().to_vec(),
             Entry::Fifo(target) => target.as_bytes().to_vec(),
             Entry::Socket(target) => target.as_bytes().to_vec(),
         }
@@ -77,8 +77,8 @@ impl Entry {
             Entry::Regular(..) => 1,
             Entry::Symlink(..) => 2,
             Entry::Directory(_) => 3,
-            Entry::CharacterDevice(_) => 4,
-            Entry::BlockDevice(_) => 5,
+            Entry::CharacterDevice(..) => 4,
+            Entry::BlockDevice(..) => 5,
             Entry::Fifo(_) => 6,
             Entry::Socket(_) => 7,
         }
@@ -134,11 +134,30 @@ impl Record for Layout {
             FileType::Directory => {
                 Entry::Directory(sanitize(reader.read_string(target_length as u64)?))
             }
-            _ => {
-                if source_length > 0 {
-                    let _ = reader.read_vec(source_length as usize);
+            FileType::CharacterDevice | FileType::BlockDevice => {
+                let source = reader.read_vec(source_length as usize)?;
+                // Older writers didn't record the device number
+                let device = source
+                    .try_into()
+                    .map(u64::from_be_bytes)
+                    .unwrap_or_default();
+                let target = sanitize(reader.read_string(target_length as u64)?);
+
+                if file_type == FileType::CharacterDevice {
+                    Entry::CharacterDevice(device, target)
+                } else {
+                    Entry::BlockDevice(device, target)
+                }
+            }
+            FileType::Fifo | FileType::Socket => {
+                let _ = reader.read_vec(source_length as usize)?;
+                let target = sanitize(reader.read_string(target_length as u64)?);
+
+                if file_type == FileType::Fifo {
+                    Entry::Fifo(target)
+                } else {
+                    Entry::Socket(target)
                 }
-                unreachable!()
             }
         };
 
@@ -174,3 +193,33 @@ impl Record for Layout {
         4 + 4 + 4 + 4 + 2 + 2 + 1 + 11 + self.entry.source().len() + self.entry.target().len()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn special_roundtrip() {
+        for entry in [
+            Entry::CharacterDevice(0x0501, "lib/udev/devices/console".into()),
+            Entry::BlockDevice(0x0700, "lib/udev/devices/loop0".into()),
+            Entry::Fifo("lib/initramfs/fifo".into()),
+            Entry::Socket("lib/initramfs/socket".into()),
+        ] {
+            let layout = Layout {
+                uid: 0,
+                gid: 0,
+                mode: 0o600,
+                tag: 0,
+                entry,
+            };
+
+            let mut bytes = vec![];
+            layout.encode(&mut bytes).unwrap();
+            assert_eq!(bytes.len(), layout.size());
+
+            let decoded = Layout::decode(bytes.as_slice()).unwrap();
+            assert_eq!(decoded, layout);
+        }
+    }
+}
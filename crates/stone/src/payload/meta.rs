@@ -4,7 +4,7 @@
 
 use std::{
     fmt::Display,
-    io::{Read, Write},
+    io::{self, Read, Write},
 };
 
 use super::{DecodeError, EncodeError, Record};
@@ -147,6 +147,31 @@ pub enum Tag {
     SourcePath = 19,
     // Ref/commit of the upstream source
     SourceRef = 20,
+    // Repository index specific (Package a delta applies to)
+    DeltaFrom = 21,
+    // Repository index specific (Package a delta produces)
+    DeltaTo = 22,
+    // Size of the package contents once installed
+    InstalledSize = 23,
+}
+
+/// Decode `num_records` meta records, skipping any with a tag unknown
+/// to this version so newer writers can add tags without breaking us
+pub fn decode_records<R: Read>(
+    mut reader: R,
+    num_records: usize,
+) -> Result<Vec<Meta>, DecodeError> {
+    let mut records = Vec::with_capacity(num_records);
+
+    for _ in 0..num_records {
+        match Meta::decode(&mut reader) {
+            Ok(record) => records.push(record),
+            Err(DecodeError::UnknownMetaTag(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(records)
 }
 
 /// Helper to decode a dependency's encoded kind
@@ -169,8 +194,11 @@ fn decode_dependency(i: u8) -> Result<Dependency, DecodeError> {
 impl Record for Meta {
     fn decode<R: Read>(mut reader: R) -> Result<Self, DecodeError> {
         let length = reader.read_u32()?;
+        let tag = reader.read_u16()?;
+        let kind = reader.read_u8()?;
+        let _padding = reader.read_array::<1>()?;
 
-        let tag = match reader.read_u16()? {
+        let tag = match tag {
             1 => Tag::Name,
             2 => Tag::Architecture,
             3 => Tag::Version,
@@ -191,12 +219,16 @@ impl Record for Meta {
             18 => Tag::SourceURI,
             19 => Tag::SourcePath,
             20 => Tag::SourceRef,
-            t => return Err(DecodeError::UnknownMetaTag(t)),
+            21 => Tag::DeltaFrom,
+            22 => Tag::DeltaTo,
+            23 => Tag::InstalledSize,
+            t => {
+                // Consume the value so the following records can still be read
+                io::copy(&mut (&mut reader).take(length as u64), &mut io::sink())?;
+                return Err(DecodeError::UnknownMetaTag(t));
+            }
         };
 
-        let kind = reader.read_u8()?;
-        let _padding = reader.read_array::<1>()?;
-
         // Remove null terminated byte from string
         let sanitize = |s: String| s.trim_end_matches('\0').to_string();
 
@@ -276,3 +308,38 @@ impl Record for Meta {
         4 + 2 + 1 + 1 + self.kind.size()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_skip_unknown_tag() {
+        let records = [
+            Meta {
+                tag: Tag::Name,
+                kind: Kind::String("nano".into()),
+            },
+            Meta {
+                tag: Tag::Summary,
+                kind: Kind::String("Text editor".into()),
+            },
+            Meta {
+                tag: Tag::Release,
+                kind: Kind::Uint64(1),
+            },
+        ];
+
+        let mut bytes = vec![];
+        for record in &records {
+            record.encode(&mut bytes).unwrap();
+        }
+
+        // Retag the summary as something from a newer writer
+        let offset = records[0].size() + 4;
+        bytes[offset..offset + 2].copy_from_slice(&999u16.to_be_bytes());
+
+        let decoded = decode_records(bytes.as_slice(), records.len()).unwrap();
+        assert_eq!(decoded, [records[0].clone(), records[2].clone()]);
+    }
+}
@@ -115,6 +115,8 @@ impl PayloadKind {
     ) -> Result<Option<Self>, Error> {
         match payload::Header::decode(&mut reader) {
             Ok(header) => {
+                let offset = reader.stream_position()?;
+
                 hasher.reset();
                 let mut hashed = digest::Reader::new(&mut reader, hasher);
                 let mut framed = (&mut hashed).take(header.stored_size);
@@ -122,7 +124,7 @@ impl PayloadKind {
                 let payload = match header.kind {
                     payload::Kind::Meta => PayloadKind::Meta(Payload {
                         header,
-                        body: payload::decode_records(
+                        body: payload::meta::decode_records(
                             PayloadReader::new(&mut framed, header.compression)?,
                             header.num_records,
                         )?,
@@ -148,22 +150,21 @@ impl PayloadKind {
                             header.num_records,
                         )?,
                     }),
-                    payload::Kind::Content => {
-                        let offset = reader.stream_position()?;
-
-                        // Skip past, these are read by user later
-                        reader.seek(SeekFrom::Current(header.stored_size as i64))?;
-
-                        PayloadKind::Content(Payload {
-                            header,
-                            body: Content { offset },
-                        })
-                    }
+                    payload::Kind::Content => PayloadKind::Content(Payload {
+                        header,
+                        body: Content { offset },
+                    }),
                     payload::Kind::Dumb => unimplemented!("??"),
                 };
 
-                // Validate hash for non-content payloads
-                if !matches!(header.kind, payload::Kind::Content) {
+                if matches!(header.kind, payload::Kind::Content) {
+                    // Skip past, these are read by user later
+                    reader.seek(SeekFrom::Current(header.stored_size as i64))?;
+                } else {
+                    // Validate hash for non-content payloads, including any
+                    // bytes not consumed by decoding, i.e. the frame of a
+                    // payload without records
+                    io::copy(&mut framed, &mut io::sink())?;
                     validate_checksum(hasher, &header)?;
                 }
 
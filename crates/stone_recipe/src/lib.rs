@@ -106,6 +106,8 @@ pub struct Package {
     #[serde(default, rename = "rundeps")]
     pub run_deps: Vec<String>,
     #[serde(default)]
+    pub conflicts: Vec<String>,
+    #[serde(default)]
     pub paths: Vec<Path>,
 }
 
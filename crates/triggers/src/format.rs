@@ -8,7 +8,7 @@ use fnmatch::Pattern;
 use serde::Deserialize;
 
 /// Filter matched paths to a specific kind
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum PathKind {
     Directory,
@@ -26,7 +26,11 @@ pub enum Handler {
 /// Inhibitors prevent handlers from running based on some constraints
 #[derive(Debug, Deserialize)]
 pub struct Inhibitors {
+    /// Inhibit if any of these paths exist
+    #[serde(default)]
     pub paths: Vec<String>,
+    /// Inhibit when running in any of these environments
+    #[serde(default)]
     pub environment: Vec<String>,
 }
 
@@ -34,8 +38,9 @@ pub struct Inhibitors {
 #[derive(Debug, Deserialize)]
 pub struct PathDefinition {
     pub handlers: Vec<String>,
-    #[serde(rename = "type")]
-    pub kind: PathKind,
+    /// Only match paths of this kind, otherwise match any path
+    #[serde(rename = "type", default)]
+    pub kind: Option<PathKind>,
 }
 
 /// Serialiazation format of triggers
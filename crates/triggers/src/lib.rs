@@ -4,13 +4,23 @@
 
 //! System trigger management facilities
 
-use format::Trigger;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::Path,
+    process,
+};
+
+use dag::Dag;
+use format::{PathKind, Trigger};
 use thiserror::Error;
 
 pub mod format;
 
 pub struct Manager {
+    triggers: Vec<Trigger>,
     handlers: Vec<ExtractedHandler>,
+    matches: BTreeSet<CompiledHandler>,
 }
 
 #[derive(Debug)]
@@ -18,12 +28,31 @@ struct ExtractedHandler {
     trigger: String,
     handler: format::Handler,
     pattern: fnmatch::Pattern,
+    kind: Option<PathKind>,
+}
+
+/// A handler with all match variables substituted, ready to run
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CompiledHandler {
+    Run {
+        trigger: String,
+        run: String,
+        args: Vec<String>,
+    },
+    Delete {
+        trigger: String,
+        delete: Vec<String>,
+    },
 }
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("missing handler reference in {0}: {1}")]
     MissingHandler(String, String),
+    #[error("{0} failed with status {1}")]
+    RunFailed(String, process::ExitStatus),
+    #[error("io")]
+    Io(#[from] io::Error),
 }
 
 impl Manager {
@@ -44,22 +73,174 @@ impl Manager {
                         trigger: trigger.name.clone(),
                         handler: found.clone(),
                         pattern: p.clone(),
+                        kind: def.kind,
                     });
                 }
             }
         }
 
-        Ok(Self { handlers })
+        Ok(Self {
+            triggers,
+            handlers,
+            matches: BTreeSet::new(),
+        })
     }
 
     /// Push a path, building up our matches
-    pub fn push_path(&mut self, path: &str) {
+    ///
+    /// `kind` is the type of the path, or `None` if it's
+    /// neither a directory or symlink
+    pub fn push_path(&mut self, path: &str, kind: Option<PathKind>) {
         for (h, m) in self
             .handlers
             .iter()
+            .filter(|h| h.kind.is_none() || h.kind == kind)
             .filter_map(|h| h.pattern.match_path(path).map(|m| (h, m)))
         {
-            eprintln!("Matching [{}]: {:?} : {:?}", h.trigger, m, h.handler);
+            self.matches.insert(compile(h, &m.variables));
         }
     }
+
+    /// Returns all matched handlers in execution order
+    ///
+    /// Triggers are ordered by their `before` & `after` constraints, and
+    /// any trigger inhibited by a path existing in `root` or by one of the
+    /// active `environment` names is omitted.
+    pub fn handlers(&self, root: &Path, environment: &[&str]) -> Vec<CompiledHandler> {
+        let mut graph = Dag::<&str>::new();
+
+        for trigger in &self.triggers {
+            let node = graph.add_node_or_get_index(trigger.name.as_str());
+
+            if let Some(before) = &trigger.before {
+                let before = graph.add_node_or_get_index(before.as_str());
+                graph.add_edge(node, before);
+            }
+            if let Some(after) = &trigger.after {
+                let after = graph.add_node_or_get_index(after.as_str());
+                graph.add_edge(after, node);
+            }
+        }
+
+        graph
+            .topo()
+            .filter_map(|name| self.triggers.iter().find(|t| t.name == *name))
+            .filter(|trigger| !is_inhibited(trigger, root, environment))
+            .flat_map(|trigger| {
+                self.matches
+                    .iter()
+                    .filter(|handler| handler.trigger() == trigger.name)
+                    .cloned()
+            })
+            .collect()
+    }
+}
+
+impl CompiledHandler {
+    /// Name of the trigger which owns this handler
+    pub fn trigger(&self) -> &str {
+        match self {
+            CompiledHandler::Run { trigger, .. } => trigger,
+            CompiledHandler::Delete { trigger, .. } => trigger,
+        }
+    }
+
+    /// Execute the handler, with `root` as the filesystem root
+    /// to resolve [`format::Handler::Delete`] paths against
+    pub fn run(&self, root: &Path) -> Result<(), Error> {
+        match self {
+            CompiledHandler::Run { run, args, .. } => {
+                let status = process::Command::new(run).args(args).status()?;
+
+                if !status.success() {
+                    return Err(Error::RunFailed(run.clone(), status));
+                }
+            }
+            CompiledHandler::Delete { delete, .. } => {
+                for path in delete {
+                    let path = root.join(path.trim_start_matches('/'));
+
+                    match fs::symlink_metadata(&path) {
+                        Ok(meta) if meta.is_dir() => fs::remove_dir_all(&path)?,
+                        Ok(_) => fs::remove_file(&path)?,
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Substitute match variables, i.e. `$(version)`, into the handler
+fn compile(handler: &ExtractedHandler, variables: &HashMap<String, String>) -> CompiledHandler {
+    let substitute = |input: &String| {
+        variables.iter().fold(input.clone(), |acc, (key, value)| {
+            acc.replace(&format!("$({key})"), value)
+        })
+    };
+
+    match &handler.handler {
+        format::Handler::Run { run, args } => CompiledHandler::Run {
+            trigger: handler.trigger.clone(),
+            run: substitute(run),
+            args: args.iter().map(substitute).collect(),
+        },
+        format::Handler::Delete { delete } => CompiledHandler::Delete {
+            trigger: handler.trigger.clone(),
+            delete: delete.iter().map(substitute).collect(),
+        },
+    }
+}
+
+fn is_inhibited(trigger: &Trigger, root: &Path, environment: &[&str]) -> bool {
+    let Some(inhibitors) = &trigger.inhibitors else {
+        return false;
+    };
+
+    inhibitors
+        .paths
+        .iter()
+        .any(|path| root.join(path.trim_start_matches('/')).exists())
+        || inhibitors
+            .environment
+            .iter()
+            .any(|env| environment.contains(&env.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{format::PathKind, CompiledHandler, Manager};
+
+    #[test]
+    fn test_manager_handlers() {
+        let trigger = serde_yaml::from_str(include_str!("../../../test/trigger.yml")).unwrap();
+        let mut manager = Manager::new(vec![trigger]).unwrap();
+        // Root where no inhibitor paths can exist
+        let root = Path::new("/dev/null");
+
+        // Wrong kind
+        manager.push_path("/usr/lib/modules/6.6.7-267.current/kernel", None);
+        assert!(manager.handlers(root, &[]).is_empty());
+
+        manager.push_path(
+            "/usr/lib/modules/6.6.7-267.current/kernel",
+            Some(PathKind::Directory),
+        );
+        assert_eq!(
+            manager.handlers(root, &[]),
+            vec![CompiledHandler::Run {
+                trigger: "depmod".into(),
+                run: "/sbin/depmod".into(),
+                args: vec!["-a".into(), "6.6.7-267.current".into()],
+            }]
+        );
+
+        // Inhibited
+        assert!(manager.handlers(root, &["chroot"]).is_empty());
+    }
 }